@@ -1,7 +1,21 @@
-use megasim_lib::compiler::Op;
-use std::{collections::HashMap, env, fs::File, io::Read};
+use megasim_lib::{
+    compiler::{Op, cseg_to_ihex, disassemble_cseg, disassemble_flash, eseg_to_ihex},
+    elf::function_labels,
+    sim::naive::{
+        chip::{Chip, FirmwareFormat, State},
+        peripherals::usart::SerialFrame,
+    },
+};
+use std::{
+    collections::HashMap,
+    env, fs,
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+    thread,
+};
 
-fn stringify_program(cseg: &HashMap<u64, Op>, dseg: &HashMap<u64, u64>) -> Result<String, ()> {
+fn stringify_program(cseg: &HashMap<u64, Op>, dseg: &HashMap<u64, u64>) -> Result<String, String> {
     let mut output = String::new();
 
     output.push_str("--- DSEG ---\n");
@@ -12,50 +26,174 @@ fn stringify_program(cseg: &HashMap<u64, Op>, dseg: &HashMap<u64, u64>) -> Resul
     }
 
     output.push_str("\n--- CSEG ---\n");
-    let mut cseg_addrs: Vec<_> = cseg.keys().collect();
-    cseg_addrs.sort();
-    for addr in cseg_addrs {
-        let op = &cseg[addr];
-        let formatted_instr = match op {
-            Op::Nullary(m) => m.to_uppercase(),
-            Op::Unary(m, a1) => format!("{} {}", m.to_uppercase(), a1),
-            Op::Binary(m, a1, a2) => format!("{} {} {}", m.to_uppercase(), a1, a2),
-            Op::Ternary(m, a1, a2, a3) => format!("{} {} {} {}", m.to_uppercase(), a1, a2, a3),
-        };
-        output.push_str(&format!("{}: {}\n", addr, formatted_instr));
+    for (addr, text) in disassemble_cseg(cseg, &HashMap::new())? {
+        output.push_str(&format!("{}: {}\n", addr, text));
     }
 
     Ok(output)
 }
 
+fn stringify_flash(chip: &Chip) -> String {
+    let mut output = String::new();
+    let labels = function_labels(&chip.symbols);
+
+    output.push_str("--- FLASH ---\n");
+    for (addr, text) in disassemble_flash(&chip.flash, &labels) {
+        if let Some(label) = labels.get(&addr) {
+            output.push_str(&format!("{}:\n", label));
+        }
+        output.push_str(&format!("{}: {}\n", addr, text));
+    }
+
+    output
+}
+
+fn write_ihex(path: &str, cseg: &HashMap<u64, Op>, eseg: &HashMap<u64, u64>) {
+    let hex = cseg_to_ihex(cseg).unwrap_or_else(|e| panic!("Failed to encode program: {}", e));
+    fs::write(path, hex).expect("Failed to write HEX file");
+    println!("Wrote {}", path);
+
+    if !eseg.is_empty() {
+        let eep_path = Path::new(path).with_extension("eep");
+        fs::write(&eep_path, eseg_to_ihex(eseg)).expect("Failed to write EEP file");
+        println!("Wrote {}", eep_path.display());
+    }
+}
+
+// Runs the chip with the USART bridged to stdin/stdout, until it halts.
+fn run_serial(chip: &mut Chip) {
+    let port = chip.serial_port();
+
+    let tx = port.tx;
+    thread::spawn(move || {
+        for byte in io::stdin().lock().bytes() {
+            let Ok(byte) = byte else { break };
+            if tx.send(SerialFrame::new(byte as u16)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut stdout = io::stdout();
+    while chip.step(None).is_some() {
+        let bytes: Vec<u8> = port.rx.try_iter().map(|data| data as u8).collect();
+        if !bytes.is_empty() {
+            stdout.write_all(&bytes).expect("Failed to write to stdout");
+            stdout.flush().expect("Failed to write to stdout");
+        }
+    }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        eprintln!("Usage: megasim <input_asm_path>");
-        std::process::exit(1);
+    let mut hex_path = None;
+    let mut serial = false;
+    let mut input_path = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--hex" if i + 1 < args.len() => {
+                hex_path = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "--serial" => serial = true,
+            arg => input_path = Some(arg.to_string()),
+        }
+        i += 1;
     }
 
-    let input_path = &args[1];
+    let Some(input_path) = input_path else {
+        eprintln!(
+            "Usage: megasim [--hex <output.hex>] [--serial] <input_asm_path | firmware.hex | firmware.bin | firmware.elf>"
+        );
+        eprintln!("  --hex     assemble <input_asm_path> to Intel HEX and exit");
+        eprintln!("  --serial  bridge the USART to stdin/stdout");
+        std::process::exit(1);
+    };
+
+    let mut chip = Chip::new();
+
+    if FirmwareFormat::from_path(Path::new(&input_path)).is_some() {
+        // Firmware is already an image, there is nothing to assemble
+        if hex_path.is_some() {
+            eprintln!("--hex only applies to assembly input");
+            std::process::exit(1);
+        }
+
+        chip.load_firmware(Path::new(&input_path))
+            .unwrap_or_else(|e| panic!("Failed to load firmware: {}", e));
+        if !serial {
+            println!("Loaded!");
+            println!("{}", stringify_flash(&chip));
+        }
+    } else {
+        let mut source = String::new();
+        let mut file = File::open(&input_path).expect("Failed to open input file");
+        file.read_to_string(&mut source)
+            .expect("Failed to read input file");
+
+        let (cseg, dseg, eseg) = megasim_lib::compiler::compile(&source).unwrap_or_else(|e| {
+            eprintln!("Failed to compile: {}", e);
+            std::process::exit(1);
+        });
+
+        // Only export the image, e.g. for flashing with avrdude
+        if let Some(hex_path) = hex_path {
+            println!("Compiled!");
+            write_ihex(&hex_path, &cseg, &eseg);
+            return;
+        }
 
-    let mut source = String::new();
-    let mut file = File::open(input_path).expect("Failed to open input file");
-    file.read_to_string(&mut source)
-        .expect("Failed to read input file");
+        if !serial {
+            println!("Compiled!");
+            let program = stringify_program(&cseg, &dseg).unwrap_or_else(|e| {
+                eprintln!("Failed to disassemble: {}", e);
+                std::process::exit(1);
+            });
+            println!("{}", program);
+        }
 
-    let (cseg, dseg) = megasim_lib::compiler::compile(&source);
-    println!("Compiled!");
-    println!("{}", stringify_program(&cseg, &dseg).unwrap());
+        if let Err(e) = chip.apply_cseg(&cseg) {
+            eprintln!("Failed to load program: {}", e);
+            std::process::exit(1);
+        }
+        if let Err(e) = chip.apply_dseg(&dseg) {
+            eprintln!("Failed to load program: {}", e);
+            std::process::exit(1);
+        }
+    }
 
-    let mut chip = megasim_lib::sim::naive::chip::Chip::new();
-    chip.apply_cseg(&cseg).unwrap();
-    chip.apply_dseg(&dseg).unwrap();
+    // Serial output goes to stdout, so there is no trace
+    if serial {
+        run_serial(&mut chip);
+        return;
+    }
 
     for _ in 0..10_000 {
-        println!("PC={} | PORTA={:?}", chip.pc, chip.ram[59]);
+        let symbol = chip.symbolize_pc(chip.pc).map(|s| format!(" <{}>", s));
+        println!(
+            "PC={}{} | PORTA={:?}",
+            chip.pc,
+            symbol.unwrap_or_default(),
+            chip.ram[59]
+        );
         // println!("{:?}", chip);
-        if !chip.step(None) {
+        if chip.step(None).is_none() {
             break;
         }
     }
+
+    println!(
+        "Ran {} cycles ({:.3} ms at {} Hz)",
+        chip.cycles,
+        chip.elapsed_ns() as f64 / 1e6,
+        chip.clock_freq
+    );
+
+    if chip.state == State::Break {
+        let pc = chip.pc.wrapping_sub(1);
+        let symbol = chip.symbolize_pc(pc).map(|s| format!(" <{}>", s));
+        println!("Breakpoint at PC={}{}", pc, symbol.unwrap_or_default());
+    }
 }
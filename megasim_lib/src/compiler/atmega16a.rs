@@ -1,5 +1,79 @@
 use std::collections::HashMap;
 
+// Last flash word address (Page 16, 8K words)
+pub const FLASHEND: i64 = 0x1FFF;
+
+// I/O registers by I/O address (add 0x20 for the data address).
+// Data from Register Summary (Page 319)
+pub const IO_REGISTERS: [(&str, i64); 66] = [
+    ("twbr", 0x00),
+    ("twsr", 0x01),
+    ("twar", 0x02),
+    ("twdr", 0x03),
+    ("adcl", 0x04),
+    ("adch", 0x05),
+    ("adcsra", 0x06),
+    ("admux", 0x07),
+    ("acsr", 0x08),
+    ("ubrrl", 0x09),
+    ("ucsrb", 0x0A),
+    ("ucsra", 0x0B),
+    ("udr", 0x0C),
+    ("spcr", 0x0D),
+    ("spsr", 0x0E),
+    ("spdr", 0x0F),
+    ("pind", 0x10),
+    ("ddrd", 0x11),
+    ("portd", 0x12),
+    ("pinc", 0x13),
+    ("ddrc", 0x14),
+    ("portc", 0x15),
+    ("pinb", 0x16),
+    ("ddrb", 0x17),
+    ("portb", 0x18),
+    ("pina", 0x19),
+    ("ddra", 0x1A),
+    ("porta", 0x1B),
+    ("eecr", 0x1C),
+    ("eedr", 0x1D),
+    ("eearl", 0x1E),
+    ("eearh", 0x1F),
+    ("ubrrh", 0x20),
+    ("ucsrc", 0x20),
+    ("wdtcr", 0x21),
+    ("assr", 0x22),
+    ("ocr2", 0x23),
+    ("tcnt2", 0x24),
+    ("tccr2", 0x25),
+    ("icr1l", 0x26),
+    ("icr1h", 0x27),
+    ("ocr1bl", 0x28),
+    ("ocr1bh", 0x29),
+    ("ocr1al", 0x2A),
+    ("ocr1ah", 0x2B),
+    ("tcnt1l", 0x2C),
+    ("tcnt1h", 0x2D),
+    ("tccr1b", 0x2E),
+    ("tccr1a", 0x2F),
+    ("sfior", 0x30),
+    ("osccal", 0x31),
+    ("ocdr", 0x31),
+    ("tcnt0", 0x32),
+    ("tccr0", 0x33),
+    ("mcucsr", 0x34),
+    ("mcucr", 0x35),
+    ("twcr", 0x36),
+    ("spmcr", 0x37),
+    ("tifr", 0x38),
+    ("timsk", 0x39),
+    ("gifr", 0x3A),
+    ("gicr", 0x3B),
+    ("ocr0", 0x3C),
+    ("spl", 0x3D),
+    ("sph", 0x3E),
+    ("sreg", 0x3F),
+];
+
 pub fn gen_symbols() -> HashMap<String, i64> {
     let mut symbols: HashMap<String, i64> = HashMap::new();
 
@@ -8,83 +82,27 @@ pub fn gen_symbols() -> HashMap<String, i64> {
         symbols.insert(format!("r{}", i), i);
     }
 
+    // X, Y and Z pointer halves (Page 12)
+    let pointers = [
+        ("xl", 26),
+        ("xh", 27),
+        ("yl", 28),
+        ("yh", 29),
+        ("zl", 30),
+        ("zh", 31),
+    ];
+    for (name, reg) in pointers {
+        symbols.insert(name.into(), reg);
+    }
+
     // 2. Memory Constants
     symbols.insert("ramend".into(), 0x045F); // Page 17
-    symbols.insert("flashend".into(), 0x1FFF); // Page 16 (8K words)
+    symbols.insert("flashend".into(), FLASHEND); // Page 16 (8K words)
     symbols.insert("eend".into(), 0x01FF); // Page 18 (512 bytes)
     symbols.insert("pagesize".into(), 64); // Page 254 (words)
 
     // 3. I/O Registers
-    // Data from Register Summary (Page 319)
-    let io = [
-        ("twbr", 0x00),
-        ("twsr", 0x01),
-        ("twar", 0x02),
-        ("twdr", 0x03),
-        ("adcl", 0x04),
-        ("adch", 0x05),
-        ("adcsra", 0x06),
-        ("admux", 0x07),
-        ("acsr", 0x08),
-        ("ubrrl", 0x09),
-        ("ucsrb", 0x0A),
-        ("ucsra", 0x0B),
-        ("udr", 0x0C),
-        ("spcr", 0x0D),
-        ("spsr", 0x0E),
-        ("spdr", 0x0F),
-        ("pind", 0x10),
-        ("ddrd", 0x11),
-        ("portd", 0x12),
-        ("pinc", 0x13),
-        ("ddrc", 0x14),
-        ("portc", 0x15),
-        ("pinb", 0x16),
-        ("ddrb", 0x17),
-        ("portb", 0x18),
-        ("pina", 0x19),
-        ("ddra", 0x1A),
-        ("porta", 0x1B),
-        ("eecr", 0x1C),
-        ("eedr", 0x1D),
-        ("eearl", 0x1E),
-        ("eearh", 0x1F),
-        ("ucsrc", 0x20),
-        ("ubrrh", 0x20),
-        ("wdtcr", 0x21),
-        ("assr", 0x22),
-        ("ocr2", 0x23),
-        ("tcnt2", 0x24),
-        ("tccr2", 0x25),
-        ("icr1l", 0x26),
-        ("icr1h", 0x27),
-        ("ocr1bl", 0x28),
-        ("ocr1bh", 0x29),
-        ("ocr1al", 0x2A),
-        ("ocr1ah", 0x2B),
-        ("tcnt1l", 0x2C),
-        ("tcnt1h", 0x2D),
-        ("tccr1b", 0x2E),
-        ("tccr1a", 0x2F),
-        ("sfior", 0x30),
-        ("osccal", 0x31),
-        ("ocdr", 0x31),
-        ("tcnt0", 0x32),
-        ("tccr0", 0x33),
-        ("mcucsr", 0x34),
-        ("mcucr", 0x35),
-        ("twcr", 0x36),
-        ("spmcr", 0x37),
-        ("tifr", 0x38),
-        ("timsk", 0x39),
-        ("gifr", 0x3A),
-        ("gicr", 0x3B),
-        ("ocr0", 0x3C),
-        ("spl", 0x3D),
-        ("sph", 0x3E),
-        ("sreg", 0x3F),
-    ];
-    for (name, addr) in io {
+    for (name, addr) in IO_REGISTERS {
         symbols.insert(name.into(), addr);
     }
 
@@ -136,6 +154,15 @@ pub fn gen_symbols() -> HashMap<String, i64> {
     symbols.insert("intf0".into(), 6);
     symbols.insert("intf1".into(), 7);
 
+    // SPMCR (Page 250)
+    symbols.insert("spmen".into(), 0);
+    symbols.insert("pgers".into(), 1);
+    symbols.insert("pgwrt".into(), 2);
+    symbols.insert("blbset".into(), 3);
+    symbols.insert("rwwsre".into(), 4);
+    symbols.insert("rwwsb".into(), 6);
+    symbols.insert("spmie".into(), 7);
+
     // TIMSK (Page 82, 109, 128)
     symbols.insert("toie0".into(), 0);
     symbols.insert("ocie0".into(), 1);
@@ -244,4 +271,4 @@ pub fn gen_symbols() -> HashMap<String, i64> {
     }
 
     symbols
-}
\ No newline at end of file
+}
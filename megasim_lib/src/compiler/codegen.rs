@@ -1,4 +1,8 @@
-use crate::compiler::parser::{Directive, Expression, Function, Operator, Statement};
+use crate::compiler::atmega16a::FLASHEND;
+use crate::compiler::encoder::encode;
+use crate::compiler::parser::{
+    Directive, Expression, Function, Operator, Pointer, PointerMode, Statement,
+};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
@@ -9,6 +13,14 @@ pub enum Op {
     Ternary(String, i64, i64, i64),
 }
 
+// Pointer operands are lowered to the low register of the X/Y/Z pair, with
+// the addressing mode stored in the bits above it.
+pub const PTR_POST_INC: i64 = 1 << 8;
+pub const PTR_PRE_DEC: i64 = 2 << 8;
+
+// Code, data and EEPROM segments of an assembled program
+pub type Program = (HashMap<u64, Op>, HashMap<u64, u64>, HashMap<u64, u64>);
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum Segment {
     Cseg,
@@ -16,22 +28,52 @@ enum Segment {
     Eseg,
 }
 
+impl Op {
+    pub fn mnemonic(&self) -> &str {
+        match self {
+            Op::Nullary(m) | Op::Unary(m, _) | Op::Binary(m, _, _) | Op::Ternary(m, _, _, _) => m,
+        }
+    }
+
+    // Size of the instruction in flash words.
+    pub fn width(&self) -> u64 {
+        get_instruction_width(self.mnemonic())
+    }
+}
+
 fn get_instruction_width(mnemonic: &str) -> u64 {
     match mnemonic {
-        "jmp" | "call" | "lds" | "sts" => todo!("not implemented"),
+        "jmp" | "call" | "lds" | "sts" => 2,
         _ => 1,
     }
 }
 
-pub fn codegen(ast: &[Statement]) -> (HashMap<u64, Op>, HashMap<u64, u64>) {
+// Relative branches, as (index of the target operand, reach in words).
+fn relative_target(mnemonic: &str) -> Option<(usize, i64)> {
+    match mnemonic {
+        "rjmp" | "rcall" => Some((0, 2048)),
+        "brbs" | "brbc" => Some((1, 64)),
+        "brcs" | "brlo" | "breq" | "brmi" | "brvs" | "brlt" | "brhs" | "brts" | "brie" | "brcc"
+        | "brsh" | "brne" | "brpl" | "brvc" | "brge" | "brhc" | "brtc" | "brid" => Some((0, 64)),
+        _ => None,
+    }
+}
+
+// Lays out the program. Fails on directives and instructions placed in a
+// segment that cannot hold them, on code placed past the end of flash, and
+// on instructions that don't encode, like branch targets out of reach.
+pub fn codegen(ast: &[(usize, Statement)]) -> Result<Program, String> {
     let mut cseg: HashMap<u64, Op> = HashMap::new();
-    let mut dseg: HashMap<u64, u64> = HashMap::new();
+    let dseg: HashMap<u64, u64> = HashMap::new();
+    let mut eseg: HashMap<u64, u64> = HashMap::new();
     let mut symbols: HashMap<String, i64> = crate::compiler::atmega16a::gen_symbols();
 
     fn eval(expr: &Expression, syms: &HashMap<String, i64>) -> i64 {
         match expr {
             Expression::Integer(n) => *n,
-            Expression::Identifier(s) => *syms.get(s).expect(&format!("Undefined symbol: {}", s)),
+            Expression::Identifier(s) => *syms
+                .get(s)
+                .unwrap_or_else(|| panic!("Undefined symbol: {}", s)),
             Expression::FunctionCall(f, arg) => {
                 let v = eval(arg, syms);
                 match f {
@@ -40,6 +82,28 @@ pub fn codegen(ast: &[Statement]) -> (HashMap<u64, Op>, HashMap<u64, u64>) {
                 }
             }
             Expression::BinaryOp(Operator::ShiftLeft, l, r) => eval(l, syms) << eval(r, syms),
+            Expression::Pointer(p, mode) => {
+                let base = match p {
+                    Pointer::X => 26,
+                    Pointer::Y => 28,
+                    Pointer::Z => 30,
+                };
+                match mode {
+                    PointerMode::PostIncrement => base | PTR_POST_INC,
+                    PointerMode::PreDecrement => base | PTR_PRE_DEC,
+                    PointerMode::Plain | PointerMode::Displacement(_) => base,
+                }
+            }
+        }
+    }
+
+    // Displacement operands (`Y+q`) take up two values: the pointer and q.
+    fn lower(expr: &Expression, syms: &HashMap<String, i64>) -> Vec<i64> {
+        match expr {
+            Expression::Pointer(_, PointerMode::Displacement(q)) => {
+                vec![eval(expr, syms), eval(q, syms)]
+            }
+            _ => vec![eval(expr, syms)],
         }
     }
 
@@ -48,7 +112,7 @@ pub fn codegen(ast: &[Statement]) -> (HashMap<u64, Op>, HashMap<u64, u64>) {
     let mut eseg_pc: u64 = 0;
     let mut current_seg = Segment::Cseg;
 
-    for s in ast {
+    for (line, s) in ast {
         match s {
             Statement::Directive(Directive::Cseg) => current_seg = Segment::Cseg,
             Statement::Directive(Directive::Dseg) => current_seg = Segment::Dseg,
@@ -56,6 +120,12 @@ pub fn codegen(ast: &[Statement]) -> (HashMap<u64, Op>, HashMap<u64, u64>) {
             Statement::Directive(Directive::Org(expr)) => {
                 let val = eval(expr, &symbols) as u64;
                 match current_seg {
+                    Segment::Cseg if val > FLASHEND as u64 => {
+                        return Err(format!(
+                            ".org {:#x} past the end of flash ({:#x}) on line {}",
+                            val, FLASHEND, line
+                        ));
+                    }
                     Segment::Cseg => cseg_pc = val,
                     Segment::Dseg => dseg_pc = val,
                     Segment::Eseg => eseg_pc = val,
@@ -77,12 +147,22 @@ pub fn codegen(ast: &[Statement]) -> (HashMap<u64, Op>, HashMap<u64, u64>) {
                 };
                 symbols.insert(name.clone(), addr as i64);
             }
+            Statement::Directive(Directive::Db(values)) => match current_seg {
+                Segment::Cseg => cseg_pc += (values.len() as u64).div_ceil(2),
+                Segment::Dseg => dseg_pc += values.len() as u64,
+                Segment::Eseg => eseg_pc += values.len() as u64,
+            },
+            Statement::Directive(Directive::Dw(values)) => match current_seg {
+                Segment::Cseg => cseg_pc += values.len() as u64,
+                Segment::Dseg => dseg_pc += 2 * values.len() as u64,
+                Segment::Eseg => eseg_pc += 2 * values.len() as u64,
+            },
             Statement::Instruction(mnemonic, _) => {
                 if current_seg != Segment::Cseg {
-                    panic!(
-                        "Cannot place instruction {} in data/eeprom segment",
-                        mnemonic
-                    );
+                    return Err(format!(
+                        "Cannot place instruction {} in data/eeprom segment on line {}",
+                        mnemonic, line
+                    ));
                 }
                 cseg_pc += get_instruction_width(mnemonic);
             }
@@ -90,34 +170,100 @@ pub fn codegen(ast: &[Statement]) -> (HashMap<u64, Op>, HashMap<u64, u64>) {
     }
 
     let mut cseg_pc: u64 = 0;
+    let mut eseg_pc: u64 = 0;
     let mut current_seg = Segment::Cseg;
 
-    for s in ast {
+    for (line, s) in ast {
         match s {
             Statement::Directive(Directive::Cseg) => current_seg = Segment::Cseg,
             Statement::Directive(Directive::Dseg) => current_seg = Segment::Dseg,
             Statement::Directive(Directive::Eseg) => current_seg = Segment::Eseg,
             Statement::Directive(Directive::Org(expr)) => {
-                let val = eval(&expr, &symbols) as u64;
+                let val = eval(expr, &symbols) as u64;
                 match current_seg {
                     Segment::Cseg => cseg_pc = val,
-                    _ => {}
+                    Segment::Eseg => eseg_pc = val,
+                    Segment::Dseg => {}
+                }
+            }
+            Statement::Directive(Directive::Db(values)) => {
+                let mut bytes: Vec<u8> = vec![];
+                for v in values {
+                    let val = eval(v, &symbols);
+                    if !(-128..=255).contains(&val) {
+                        return Err(format!(
+                            ".db: constant {} out of range -128-255 on line {}",
+                            val, line
+                        ));
+                    }
+                    bytes.push(val as u8);
+                }
+
+                match current_seg {
+                    // Bytes are packed two to a word, low byte first.
+                    Segment::Cseg => {
+                        for pair in bytes.chunks(2) {
+                            let word = pair[0] as i64 | (*pair.get(1).unwrap_or(&0) as i64) << 8;
+                            cseg.insert(cseg_pc, Op::Unary(".dw".into(), word));
+                            cseg_pc += 1;
+                        }
+                    }
+                    Segment::Eseg => {
+                        for b in bytes {
+                            eseg.insert(eseg_pc, b as u64);
+                            eseg_pc += 1;
+                        }
+                    }
+                    Segment::Dseg => {
+                        return Err(format!(
+                            ".db is not supported in the data segment on line {}",
+                            line
+                        ));
+                    }
+                }
+            }
+            Statement::Directive(Directive::Dw(values)) => {
+                for v in values {
+                    let word = eval(v, &symbols) & 0xFFFF;
+
+                    match current_seg {
+                        Segment::Cseg => {
+                            cseg.insert(cseg_pc, Op::Unary(".dw".into(), word));
+                            cseg_pc += 1;
+                        }
+                        // Words are stored low byte first.
+                        Segment::Eseg => {
+                            eseg.insert(eseg_pc, (word & 0xFF) as u64);
+                            eseg.insert(eseg_pc + 1, (word >> 8) as u64);
+                            eseg_pc += 2;
+                        }
+                        Segment::Dseg => {
+                            return Err(format!(
+                                ".dw is not supported in the data segment on line {}",
+                                line
+                            ));
+                        }
+                    }
                 }
             }
             Statement::Instruction(mnemonic, operands) => {
                 assert!(current_seg == Segment::Cseg);
 
-                let vals: Vec<i64> = match mnemonic.as_str() {
-                    "rjmp" | "rcall" | "brcc" | "breq" | "brne" | "brtc" | "brts" => {
-                        // println!(
-                        //     "{:?} {:?}",
-                        //     cseg_pc,
-                        //     eval(operands.get(0).unwrap(), &symbols)
-                        // );
-                        vec![eval(operands.get(0).unwrap(), &symbols) - (cseg_pc as i64) - 1]
+                let mut vals: Vec<i64> = operands.iter().flat_map(|o| lower(o, &symbols)).collect();
+
+                if let Some((idx, reach)) = relative_target(mnemonic) {
+                    let target = vals.get_mut(idx).ok_or_else(|| {
+                        format!("{} expects a branch target on line {}", mnemonic, line)
+                    })?;
+                    let offset = *target - (cseg_pc as i64) - 1;
+                    if !(-reach..reach).contains(&offset) {
+                        return Err(format!(
+                            "Branch target of {} at {} out of range ({} words, max ±{}) on line {}",
+                            mnemonic, cseg_pc, offset, reach, line
+                        ));
                     }
-                    _ => operands.iter().map(|o| eval(o, &symbols)).collect(),
-                };
+                    *target = offset;
+                }
 
                 let op = match vals.len() {
                     0 => Op::Nullary(mnemonic.clone()),
@@ -126,13 +272,152 @@ pub fn codegen(ast: &[Statement]) -> (HashMap<u64, Op>, HashMap<u64, u64>) {
                     3 => Op::Ternary(mnemonic.clone(), vals[0], vals[1], vals[2]),
                     _ => panic!("unknown arity"),
                 };
+                encode(&op).map_err(|e| format!("{} on line {}", e, line))?;
 
                 cseg.insert(cseg_pc, op);
-                cseg_pc += get_instruction_width(&mnemonic);
+                cseg_pc += get_instruction_width(mnemonic);
             }
             _ => {}
         }
     }
 
-    (cseg, dseg)
+    Ok((cseg, dseg, eseg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+
+    // Code segment ops in address order, as `{:?}` for comparison
+    fn cseg(src: &str) -> Vec<String> {
+        let (cseg, _, _) = compile(src).unwrap();
+        let mut ops: Vec<_> = cseg.into_iter().collect();
+        ops.sort_by_key(|(addr, _)| *addr);
+        ops.iter().map(|(_, op)| format!("{:?}", op)).collect()
+    }
+
+    #[test]
+    fn pointer_operands() {
+        let ops = cseg("st X+, r16\nld r17, -y\nldd r18, Z+5\nstd y + 3, r18\nld r20, x\n");
+        assert_eq!(
+            ops,
+            [
+                format!(r#"Binary("st", {}, 16)"#, 26 | PTR_POST_INC),
+                format!(r#"Binary("ld", 17, {})"#, 28 | PTR_PRE_DEC),
+                r#"Ternary("ldd", 18, 30, 5)"#.to_string(),
+                r#"Ternary("std", 28, 3, 18)"#.to_string(),
+                r#"Binary("ld", 20, 26)"#.to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn two_word_instructions_take_two_addresses() {
+        let (cseg, _, _) = compile("jmp end\nlds r16, 0x100\nend:\nsts 0x45F, r16\n").unwrap();
+        let mut addrs: Vec<_> = cseg.keys().copied().collect();
+        addrs.sort();
+        assert_eq!(addrs, [0, 2, 4]);
+        assert_eq!(format!("{:?}", cseg[&0]), r#"Unary("jmp", 4)"#);
+    }
+
+    #[test]
+    fn db_packs_bytes_low_first() {
+        let ops = cseg(".db 1, 2, 3\n.dw 0xBEEF\n");
+        assert_eq!(
+            ops,
+            [
+                r#"Unary(".dw", 513)"#,
+                r#"Unary(".dw", 3)"#,
+                r#"Unary(".dw", 48879)"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn db_string_literals() {
+        let ops = cseg(".db \"Hi\", 0\n.db \"a\\\"b\\n\"\n");
+        let word =
+            |low: u8, high: u8| format!(r#"Unary(".dw", {})"#, u16::from_le_bytes([low, high]));
+        assert_eq!(
+            ops,
+            [
+                word(b'H', b'i'),
+                word(0, 0),
+                word(b'a', b'"'),
+                word(b'b', b'\n')
+            ]
+        );
+    }
+
+    #[test]
+    fn db_in_eeprom() {
+        let (_, _, eseg) = compile(".eseg\n.db \"AB\"\n.dw 0x1234\n").unwrap();
+        let mut bytes: Vec<_> = eseg.into_iter().collect();
+        bytes.sort();
+        assert_eq!(bytes, [(0, 0x41), (1, 0x42), (2, 0x34), (3, 0x12)]);
+    }
+
+    #[test]
+    fn db_in_dseg_is_an_error() {
+        assert!(compile(".dseg\n.db 1\n").is_err());
+        assert!(compile(".dseg\n.dw 1\n").is_err());
+        assert!(compile(".dseg\nbuf:\n.cseg\nnop\n").is_ok());
+    }
+
+    #[test]
+    fn branch_aliases_take_relative_targets() {
+        let ops = cseg("start:\nnop\nbreq start\nbrbc 0, end\nbrlo start\nend:\n");
+        assert_eq!(
+            ops,
+            [
+                r#"Nullary("nop")"#,
+                r#"Unary("breq", -2)"#,
+                r#"Binary("brbc", 0, 1)"#,
+                r#"Unary("brlo", -4)"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn branch_out_of_range() {
+        let src = format!("start:\n{}breq start\n", "nop\n".repeat(64));
+        let err = compile(&src).unwrap_err();
+        assert!(err.contains("out of range"), "{}", err);
+
+        let src = format!("start:\n{}breq start\n", "nop\n".repeat(63));
+        assert!(compile(&src).is_ok());
+    }
+
+    #[test]
+    fn encoder_errors_name_the_line() {
+        let err = compile("nop\nldi r5, 1\n").unwrap_err();
+        assert_eq!(err, "LDI: invalid register R5, must be R16-R31 on line 2");
+
+        let err = compile("; comment\n\nfoo r1\n").unwrap_err();
+        assert_eq!(err, "Unknown instruction: foo on line 3");
+
+        let err = compile("ldd r16, x+5\n").unwrap_err();
+        assert!(err.ends_with(" on line 1"), "{}", err);
+    }
+
+    #[test]
+    fn code_past_the_end_of_flash() {
+        let err = compile("jmp 0x3FFFFF\n").unwrap_err();
+        assert!(err.contains("past the end of flash"), "{}", err);
+
+        let err = compile("nop\n.org 0x3000\nnop\n").unwrap_err();
+        assert_eq!(err, ".org 0x3000 past the end of flash (0x1fff) on line 2");
+
+        assert!(compile(".org flashend\nnop\n").is_ok());
+        assert!(compile(".eseg\n.org 0x3000\n").is_ok());
+    }
+
+    #[test]
+    fn db_values_must_fit_a_byte() {
+        let err = compile(".db 1, 300\n").unwrap_err();
+        assert_eq!(err, ".db: constant 300 out of range -128-255 on line 1");
+
+        assert_eq!(cseg(".db -1, 255\n"), [r#"Unary(".dw", 65535)"#]);
+    }
 }
@@ -1,9 +1,8 @@
-use std::collections::HashMap;
-use crate::compiler::codegen::Op;
+use crate::compiler::codegen::Program;
 use crate::compiler::lexer::tokenize;
 use crate::compiler::parser::parse;
 use crate::compiler::codegen::codegen;
 
-pub fn compile(text: &str) -> (HashMap<u64, Op>, HashMap<u64, u64>) {
+pub fn compile(text: &str) -> Result<Program, String> {
     codegen(&parse(&tokenize(text)))
 }
\ No newline at end of file
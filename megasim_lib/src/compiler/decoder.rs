@@ -0,0 +1,181 @@
+use crate::compiler::codegen::Op;
+use crate::compiler::opcodes::{Arg, Opcode, opcodes};
+use std::sync::OnceLock;
+
+// Maps the bits stored in an operand field back to the operand value.
+fn operand_value(arg: &Arg, field: u32, width: u32) -> i64 {
+    let field = field as i64;
+
+    match *arg {
+        Arg::Reg(_) | Arg::Imm(_) | Arg::Byte(_) => field,
+        Arg::RegHigh(_) | Arg::RegMul(_) => field + 16,
+        Arg::RegWord(_) => 24 + field * 2,
+        Arg::RegPair(_) => field * 2,
+        Arg::Rel(_) if field >= 1 << (width - 1) => field - (1 << width),
+        Arg::Rel(_) => field,
+        Arg::Ptr(ptr) => ptr,
+    }
+}
+
+// Operand values, in the order of the table entry's args. Unused entries are
+// 0.
+fn operands(opcode: &Opcode, word: u32) -> [i64; 3] {
+    let mut vals = [0; 3];
+    for (val, arg) in vals.iter_mut().zip(opcode.args) {
+        let positions = arg.letter().map_or(&[][..], |l| opcode.positions(l));
+        let field = positions
+            .iter()
+            .fold(0, |acc, pos| (acc << 1) | ((word >> pos) & 1));
+        *val = operand_value(arg, field, positions.len() as u32);
+    }
+    vals
+}
+
+// Table entry for every possible first instruction word. The second word of
+// two-word instructions only holds operand bits, so the first word decides.
+fn lookup() -> &'static [Option<&'static Opcode>] {
+    static LOOKUP: OnceLock<Vec<Option<&'static Opcode>>> = OnceLock::new();
+
+    LOOKUP.get_or_init(|| {
+        (0..=u16::MAX)
+            .map(|word| {
+                // Some encodings overlap (LD Rd, Y is LDD Rd, Y+0), the
+                // pattern with the most fixed bits is the more specific one.
+                opcodes()
+                    .iter()
+                    .filter(|o| {
+                        let shift = (o.width() as u32 - 1) * 16;
+                        (word as u32) & (o.mask >> shift) == o.value >> shift
+                    })
+                    .max_by_key(|o| o.mask.count_ones())
+            })
+            .collect()
+    })
+}
+
+// Finds the table entry for the instruction starting at `word` and extracts
+// its operands. `next` is the following flash word, only used by two-word
+// instructions.
+pub(crate) fn decode_opcode(word: u16, next: u16) -> Option<(&'static Opcode, [i64; 3])> {
+    let opcode = lookup()[word as usize]?;
+    let bits = if opcode.width() == 2 {
+        ((word as u32) << 16) | next as u32
+    } else {
+        word as u32
+    };
+
+    Some((opcode, operands(opcode, bits)))
+}
+
+// Decodes the instruction starting at `word`. Aliases are never produced,
+// e.g. CLR decodes as EOR and BREQ as BRBS.
+pub fn decode(word: u16, next: u16) -> Option<Op> {
+    let (opcode, vals) = decode_opcode(word, next)?;
+
+    let mnemonic = opcode.mnemonic.to_string();
+    Some(match opcode.args.len() {
+        0 => Op::Nullary(mnemonic),
+        1 => Op::Unary(mnemonic, vals[0]),
+        2 => Op::Binary(mnemonic, vals[0], vals[1]),
+        3 => Op::Ternary(mnemonic, vals[0], vals[1], vals[2]),
+        _ => panic!("unknown arity"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::atmega16a::FLASHEND;
+    use crate::compiler::encode;
+
+    // Operand values for every argument of `opcode`, at the low or high end
+    // of their range.
+    fn operand_values(opcode: &Opcode, high: bool) -> Vec<i64> {
+        opcode
+            .args
+            .iter()
+            .map(|arg| {
+                let width = arg.letter().map_or(0, |l| opcode.positions(l).len() as u32);
+                match (*arg, high) {
+                    (Arg::Reg(_), false) => 0,
+                    (Arg::Reg(_), true) => 31,
+                    (Arg::RegHigh(_), false) => 16,
+                    (Arg::RegHigh(_), true) => 31,
+                    (Arg::RegMul(_), false) => 16,
+                    (Arg::RegMul(_), true) => 23,
+                    (Arg::RegWord(_), false) => 24,
+                    (Arg::RegWord(_), true) => 30,
+                    (Arg::RegPair(_), false) => 0,
+                    (Arg::RegPair(_), true) => 30,
+                    (Arg::Imm(_), false) => 0,
+                    // JMP and CALL can't reach past the end of flash
+                    (Arg::Imm(_), true) if matches!(opcode.mnemonic, "jmp" | "call") => FLASHEND,
+                    (Arg::Imm(_), true) => (1 << width) - 1,
+                    (Arg::Byte(_), false) => 0,
+                    (Arg::Byte(_), true) => 0xFF,
+                    (Arg::Rel(_), false) => -(1 << (width - 1)),
+                    (Arg::Rel(_), true) => (1 << (width - 1)) - 1,
+                    (Arg::Ptr(ptr), _) => ptr,
+                }
+            })
+            .collect()
+    }
+
+    fn op(mnemonic: &str, vals: &[i64]) -> Op {
+        let mnemonic = mnemonic.to_string();
+        match *vals {
+            [] => Op::Nullary(mnemonic),
+            [a] => Op::Unary(mnemonic, a),
+            [a, b] => Op::Binary(mnemonic, a, b),
+            [a, b, c] => Op::Ternary(mnemonic, a, b, c),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn round_trips_every_opcode() {
+        for opcode in opcodes() {
+            for high in [false, true] {
+                let op = op(opcode.mnemonic, &operand_values(opcode, high));
+                let words = encode(&op).unwrap();
+                assert_eq!(words.len(), opcode.width(), "{:?}", op);
+
+                let decoded = decode(words[0], *words.get(1).unwrap_or(&0)).unwrap();
+                // At the low end some patterns collide with a more specific
+                // one (LDD Rd, Y+0 is LD Rd, Y), which must encode the same
+                if high {
+                    assert_eq!(format!("{:?}", decoded), format!("{:?}", op));
+                }
+                assert_eq!(encode(&decoded).unwrap(), words, "{:?}", op);
+            }
+        }
+    }
+
+    #[test]
+    fn second_word_is_all_operand_bits() {
+        for opcode in opcodes().iter().filter(|o| o.width() == 2) {
+            assert_eq!(opcode.mask & 0xFFFF, 0, "{}", opcode.mnemonic);
+        }
+    }
+
+    #[test]
+    fn most_specific_pattern_wins() {
+        // LDD Rd, Y+0 is LD Rd, Y
+        assert_eq!(
+            format!("{:?}", decode(0x8008, 0)),
+            r#"Some(Binary("ld", 0, 28))"#
+        );
+        // CLR R1 is EOR R1, R1
+        assert_eq!(
+            format!("{:?}", decode(0x2411, 0)),
+            r#"Some(Binary("eor", 1, 1))"#
+        );
+    }
+
+    #[test]
+    fn invalid_opcodes() {
+        for word in [0x0001, 0x9409 | 1 << 4, 0xFFFF] {
+            assert!(decode(word, 0).is_none(), "0x{:04X}", word);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use crate::compiler::atmega16a::IO_REGISTERS;
+use crate::compiler::codegen::{Op, PTR_POST_INC, PTR_PRE_DEC};
+use crate::compiler::decoder::decode_opcode;
+use crate::compiler::encoder::encode;
+use crate::compiler::opcodes::{Arg, BRANCH_CLEAR, BRANCH_SET, FLAG_CLEAR, FLAG_SET};
+use std::collections::HashMap;
+
+//
+// Disassembler
+//
+// Output is megasim assembly: lowercase mnemonics, branch targets as absolute
+// word addresses (or labels), so a listing assembles back to the same words.
+//
+
+fn register(r: i64) -> String {
+    format!("r{}", r)
+}
+
+fn pointer(ptr: i64) -> String {
+    let name = match ptr & 0xFF {
+        26 => "X",
+        28 => "Y",
+        _ => "Z",
+    };
+
+    if ptr & PTR_POST_INC != 0 {
+        format!("{}+", name)
+    } else if ptr & PTR_PRE_DEC != 0 {
+        format!("-{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+// Shared addresses are named after the first register listed, e.g. UBRRH
+// for UBRRH/UCSRC.
+fn io_register(a: i64) -> String {
+    IO_REGISTERS
+        .iter()
+        .find(|(_, addr)| *addr == a)
+        .map_or_else(|| format!("0x{:02X}", a), |(name, _)| name.to_string())
+}
+
+fn code_address(addr: i64, labels: &HashMap<u64, String>) -> String {
+    labels
+        .get(&(addr as u64))
+        .cloned()
+        .unwrap_or_else(|| format!("0x{:X}", addr))
+}
+
+// Renders the instruction starting at `word`, located at word address `addr`.
+// `labels` names code addresses (word addresses) used as jump targets.
+pub fn disassemble(
+    word: u16,
+    next: u16,
+    addr: u64,
+    labels: &HashMap<u64, String>,
+) -> Option<String> {
+    let (opcode, vals) = decode_opcode(word, next)?;
+    let mut mnemonic = opcode.mnemonic;
+    let mut args: Vec<(&Arg, i64)> = opcode.args.iter().zip(vals).collect();
+
+    // Read SREG bit operations the way they are usually written.
+    match mnemonic {
+        "brbs" | "brbc" => {
+            let table = if mnemonic == "brbs" {
+                BRANCH_SET
+            } else {
+                BRANCH_CLEAR
+            };
+            mnemonic = table[args[0].1 as usize];
+            args.remove(0);
+        }
+        "bset" | "bclr" => {
+            let table = if mnemonic == "bset" {
+                FLAG_SET
+            } else {
+                FLAG_CLEAR
+            };
+            mnemonic = table[args[0].1 as usize];
+            args.clear();
+        }
+        _ => {}
+    }
+
+    let mut operands: Vec<String> = vec![];
+    for (arg, val) in args {
+        let text = match *arg {
+            Arg::Reg(_) | Arg::RegHigh(_) | Arg::RegMul(_) | Arg::RegWord(_) | Arg::RegPair(_) => {
+                register(val)
+            }
+            Arg::Byte(_) => format!("0x{:02X}", val),
+            Arg::Rel(_) => code_address(addr as i64 + 1 + val, labels),
+            Arg::Ptr(ptr) => pointer(ptr),
+            Arg::Imm('A') => io_register(val),
+            Arg::Imm('k') if matches!(mnemonic, "lds" | "sts") => format!("0x{:04X}", val),
+            Arg::Imm('k') => code_address(val, labels),
+            // Displacement, joined with the preceding Y/Z operand
+            Arg::Imm('q') => {
+                let ptr = operands.pop().unwrap_or_default();
+                format!("{}+{}", ptr, val)
+            }
+            Arg::Imm(_) => val.to_string(),
+        };
+        operands.push(text);
+    }
+
+    if operands.is_empty() {
+        Some(mnemonic.to_string())
+    } else {
+        Some(format!("{} {}", mnemonic, operands.join(", ")))
+    }
+}
+
+// Disassembles a flash image, skipping erased (0xFFFF) words. Words that are
+// not valid opcodes are listed as `.dw`.
+pub fn disassemble_flash(flash: &[u16], labels: &HashMap<u64, String>) -> Vec<(u64, String)> {
+    let mut lines = vec![];
+    let mut addr = 0;
+
+    while addr < flash.len() {
+        let word = flash[addr];
+        if word == 0xFFFF {
+            addr += 1;
+            continue;
+        }
+
+        let next = *flash.get(addr + 1).unwrap_or(&0xFFFF);
+        let width = decode_opcode(word, next).map_or(1, |(o, _)| o.width());
+        let text = disassemble(word, next, addr as u64, labels)
+            .unwrap_or_else(|| format!(".dw 0x{:04X}", word));
+
+        lines.push((addr as u64, text));
+        addr += width;
+    }
+
+    lines
+}
+
+// Disassembles an assembled code segment. Data words stay `.dw`.
+pub fn disassemble_cseg(
+    cseg: &HashMap<u64, Op>,
+    labels: &HashMap<u64, String>,
+) -> Result<Vec<(u64, String)>, String> {
+    let mut addrs: Vec<_> = cseg.keys().copied().collect();
+    addrs.sort();
+
+    let mut lines = vec![];
+    for addr in addrs {
+        let words = encode(&cseg[&addr]).map_err(|e| format!("{} at {}", e, addr))?;
+        let next = *words.get(1).unwrap_or(&0xFFFF);
+
+        let text = match &cseg[&addr] {
+            Op::Unary(m, word) if m == ".dw" => format!(".dw 0x{:04X}", word),
+            _ => disassemble(words[0], next, addr, labels).unwrap_or_default(),
+        };
+        lines.push((addr, text));
+    }
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+
+    const SRC: &str = "
+    start:
+        ldi r16, 0x3F
+        out ddrb, r16
+        in r17, 0x20
+        ld r0, x+
+        st -y, r1
+        ldd r2, z+5
+        lds r3, 0x0060
+        movw r24, r30
+        breq start
+        brbc 0, end
+        sei
+        clt
+        call end
+    end:
+        rjmp start
+    ";
+
+    fn listing(src: &str, labels: &HashMap<u64, String>) -> Vec<String> {
+        let (cseg, _, _) = compile(src).unwrap();
+        disassemble_cseg(&cseg, labels)
+            .unwrap()
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect()
+    }
+
+    #[test]
+    fn canonical_assembly() {
+        assert_eq!(
+            listing(SRC, &HashMap::new()),
+            [
+                "ldi r16, 0x3F",
+                "out ddrb, r16",
+                "in r17, ubrrh",
+                "ld r0, X+",
+                "st -Y, r1",
+                "ldd r2, Z+5",
+                "lds r3, 0x0060",
+                "movw r24, r30",
+                "breq 0x0",
+                "brcc 0xF",
+                "sei",
+                "clt",
+                "call 0xF",
+                "rjmp 0x0",
+            ]
+        );
+    }
+
+    #[test]
+    fn branch_targets_use_labels() {
+        let labels = HashMap::from([(0, "start".to_string()), (15, "end".to_string())]);
+        let lines = listing(SRC, &labels);
+        assert_eq!(lines[8..10], ["breq start", "brcc end"]);
+        assert_eq!(lines[12..], ["call end", "rjmp start"]);
+    }
+
+    #[test]
+    fn listing_assembles_to_the_same_words() {
+        let (cseg, _, _) = compile(SRC).unwrap();
+        let lines = disassemble_cseg(&cseg, &HashMap::new()).unwrap();
+        let relisted: String = lines.iter().map(|(_, l)| format!("{}\n", l)).collect();
+        let (again, _, _) = compile(&relisted).unwrap();
+
+        for (addr, op) in &cseg {
+            assert_eq!(encode(op), encode(&again[addr]), "at {}", addr);
+        }
+    }
+
+    #[test]
+    fn flash_skips_erased_words_and_keeps_data() {
+        let flash = [0x0000, 0xFFFF, 0x940E, 0x0000, 0x0001];
+        assert_eq!(
+            disassemble_flash(&flash, &HashMap::new()),
+            [
+                (0, "nop".to_string()),
+                (2, "call 0x0".to_string()),
+                (4, ".dw 0x0001".to_string()),
+            ]
+        );
+    }
+}
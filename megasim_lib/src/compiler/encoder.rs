@@ -0,0 +1,272 @@
+use crate::compiler::atmega16a::FLASHEND;
+use crate::compiler::codegen::Op;
+use crate::compiler::opcodes::{
+    Arg, BRANCH_CLEAR, BRANCH_SET, FLAG_CLEAR, FLAG_SET, Opcode, opcodes,
+};
+use std::collections::HashMap;
+
+// Rewrites aliases (CLR, LSL, BREQ, SEC, ...) into the instruction they
+// assemble to.
+fn canonical(op: &Op) -> Op {
+    match op {
+        Op::Nullary(m) => {
+            if let Some(s) = FLAG_SET.iter().position(|f| f == m) {
+                Op::Unary("bset".into(), s as i64)
+            } else if let Some(s) = FLAG_CLEAR.iter().position(|f| f == m) {
+                Op::Unary("bclr".into(), s as i64)
+            } else {
+                op.clone()
+            }
+        }
+        Op::Unary(m, a) => match m.as_str() {
+            "clr" => Op::Binary("eor".into(), *a, *a),
+            "lsl" => Op::Binary("add".into(), *a, *a),
+            "rol" => Op::Binary("adc".into(), *a, *a),
+            "tst" => Op::Binary("and".into(), *a, *a),
+            "ser" => Op::Binary("ldi".into(), *a, 0xFF),
+            "brlo" => Op::Binary("brbs".into(), 0, *a),
+            "brsh" => Op::Binary("brbc".into(), 0, *a),
+            _ => {
+                if let Some(s) = BRANCH_SET.iter().position(|b| b == m) {
+                    Op::Binary("brbs".into(), s as i64, *a)
+                } else if let Some(s) = BRANCH_CLEAR.iter().position(|b| b == m) {
+                    Op::Binary("brbc".into(), s as i64, *a)
+                } else {
+                    op.clone()
+                }
+            }
+        },
+        Op::Binary(m, a, b) => match m.as_str() {
+            "cbr" => Op::Binary("andi".into(), *a, !*b & 0xFF),
+            "sbr" => Op::Binary("ori".into(), *a, *b),
+            _ => op.clone(),
+        },
+        Op::Ternary(_, _, _, _) => op.clone(),
+    }
+}
+
+fn operands(op: &Op) -> Vec<i64> {
+    match op {
+        Op::Nullary(_) => vec![],
+        Op::Unary(_, a) => vec![*a],
+        Op::Binary(_, a, b) => vec![*a, *b],
+        Op::Ternary(_, a, b, c) => vec![*a, *b, *c],
+    }
+}
+
+// Maps an operand value to the bits stored in its field.
+fn field_value(arg: &Arg, val: i64, width: u32) -> Result<u32, String> {
+    let max = 1i64 << width;
+
+    let field = match *arg {
+        Arg::Reg(_) if (0..=31).contains(&val) => val,
+        Arg::Reg(_) => return Err(format!("invalid register R{}, must be R0-R31", val)),
+        Arg::RegHigh(_) if (16..=31).contains(&val) => val - 16,
+        Arg::RegHigh(_) => return Err(format!("invalid register R{}, must be R16-R31", val)),
+        Arg::RegMul(_) if (16..=23).contains(&val) => val - 16,
+        Arg::RegMul(_) => return Err(format!("invalid register R{}, must be R16-R23", val)),
+        Arg::RegWord(_) if matches!(val, 24 | 26 | 28 | 30) => (val - 24) / 2,
+        Arg::RegWord(_) => {
+            return Err(format!(
+                "invalid register R{}, must be R24, R26, R28 or R30",
+                val
+            ));
+        }
+        Arg::RegPair(_) if (0..=30).contains(&val) && val & 1 == 0 => val / 2,
+        Arg::RegPair(_) => return Err(format!("invalid register R{}, must be even", val)),
+        Arg::Imm(_) if (0..max).contains(&val) => val,
+        Arg::Imm(_) => return Err(format!("constant {} out of range 0-{}", val, max - 1)),
+        Arg::Byte(_) if (-128..=255).contains(&val) => val & 0xFF,
+        Arg::Byte(_) => return Err(format!("constant {} out of range -128-255", val)),
+        Arg::Rel(_) if (-max / 2..max / 2).contains(&val) => val & (max - 1),
+        Arg::Rel(_) => {
+            return Err(format!(
+                "offset {} out of range {}-{}",
+                val,
+                -max / 2,
+                max / 2 - 1
+            ));
+        }
+        Arg::Ptr(ptr) if ptr == val => 0,
+        Arg::Ptr(_) => return Err("invalid pointer operand".into()),
+    };
+
+    Ok(field as u32)
+}
+
+fn encode_with(opcode: &Opcode, vals: &[i64]) -> Result<Vec<u16>, String> {
+    if opcode.args.len() != vals.len() {
+        return Err(format!(
+            "expected {} operands, found {}",
+            opcode.args.len(),
+            vals.len()
+        ));
+    }
+
+    let mut word = opcode.value;
+
+    for (arg, val) in opcode.args.iter().zip(vals) {
+        // The JMP and CALL field reaches 4M words, past the end of flash
+        if matches!(opcode.mnemonic, "jmp" | "call") && *val > FLASHEND {
+            return Err(format!(
+                "target {:#x} past the end of flash ({:#x})",
+                val, FLASHEND
+            ));
+        }
+
+        let positions = arg.letter().map_or(&[][..], |l| opcode.positions(l));
+        let field = field_value(arg, *val, positions.len() as u32)?;
+
+        for (i, pos) in positions.iter().enumerate() {
+            let bit = (field >> (positions.len() - 1 - i)) & 1;
+            word |= bit << pos;
+        }
+    }
+
+    Ok(match opcode.width() {
+        2 => vec![(word >> 16) as u16, word as u16],
+        _ => vec![word as u16],
+    })
+}
+
+// Encodes a single instruction into its opcode word(s).
+pub fn encode(op: &Op) -> Result<Vec<u16>, String> {
+    if let Op::Unary(m, word) = op
+        && m == ".dw"
+    {
+        return Ok(vec![*word as u16]);
+    }
+
+    let op = canonical(op);
+    let vals = operands(&op);
+
+    let mut error = None;
+    for opcode in opcodes().iter().filter(|o| o.mnemonic == op.mnemonic()) {
+        match encode_with(opcode, &vals) {
+            Ok(words) => return Ok(words),
+            Err(e) => error = error.or(Some(e)),
+        }
+    }
+
+    Err(match error {
+        Some(e) => format!("{}: {}", op.mnemonic().to_uppercase(), e),
+        None => format!("Unknown instruction: {}", op.mnemonic()),
+    })
+}
+
+// Encodes a code segment into flash words, keyed by word address.
+pub fn encode_cseg(cseg: &HashMap<u64, Op>) -> Result<HashMap<u64, u16>, String> {
+    let mut words = HashMap::new();
+
+    for (addr, op) in cseg {
+        let encoded = encode(op).map_err(|e| format!("{} at {}", e, addr))?;
+        for (i, word) in encoded.into_iter().enumerate() {
+            words.insert(addr + i as u64, word);
+        }
+    }
+
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+
+    // Flash words of an assembled program, in address order
+    fn words(src: &str) -> Vec<u16> {
+        let (cseg, _, _) = compile(src).unwrap();
+        let words = encode_cseg(&cseg).unwrap();
+        let mut addrs: Vec<_> = words.keys().copied().collect();
+        addrs.sort();
+        addrs.iter().map(|addr| words[addr]).collect()
+    }
+
+    #[test]
+    fn instructions() {
+        let src = "
+            ldi r16, 0xFF
+            out 0x3f, r0
+            in r24, 0x3f
+            ret
+            ld r16, x+
+            ld r0, y
+            ld r1, -z
+            st z, r2
+            std y+1, r24
+            ldd r24, z+5
+            adiw r24, 1
+            lpm
+            lpm r16, z+
+            mulsu r16, r23
+            ldi r16, -1
+            ";
+        assert_eq!(
+            words(src),
+            [
+                0xEF0F, 0xBE0F, 0xB78F, 0x9508, 0x910D, 0x8008, 0x9012, 0x8220, 0x8389, 0x8185,
+                0x9601, 0x95C8, 0x9105, 0x0307, 0xEF0F,
+            ]
+        );
+    }
+
+    #[test]
+    fn two_word_instructions() {
+        assert_eq!(
+            words("jmp 0\ncall 0x100\nlds r24, 0x60\nsts 0x60, r24\n"),
+            [
+                0x940C, 0x0000, 0x940E, 0x0100, 0x9180, 0x0060, 0x9380, 0x0060
+            ]
+        );
+        assert_eq!(words("jmp flashend\n"), [0x940C, 0x1FFF]);
+    }
+
+    #[test]
+    fn relative_offsets() {
+        assert_eq!(
+            words("back:\nrjmp back\nbreq fwd\nfwd:\nbrne fwd\n"),
+            [0xCFFF, 0xF001, 0xF7F9]
+        );
+    }
+
+    #[test]
+    fn aliases() {
+        assert_eq!(
+            words("clr r1\nser r17\ncbr r16, 0x0f\nsbr r16, 3\nlsl r2\nrol r3\ntst r4\n"),
+            [0x2411, 0xEF1F, 0x7F00, 0x6003, 0x0C22, 0x1C33, 0x2044]
+        );
+        assert_eq!(
+            words("sei\ncli\nsec\nclt\nbrlo 0\nbrsh 0\n"),
+            [0x9478, 0x94F8, 0x9408, 0x94E8, 0xF3D8, 0xF7D0]
+        );
+    }
+
+    #[test]
+    fn operand_errors() {
+        let err = encode(&Op::Binary("ldi".into(), 5, 1)).unwrap_err();
+        assert_eq!(err, "LDI: invalid register R5, must be R16-R31");
+
+        let err = encode(&Op::Binary("adiw".into(), 24, 64)).unwrap_err();
+        assert_eq!(err, "ADIW: constant 64 out of range 0-63");
+
+        let err = encode(&Op::Binary("movw".into(), 1, 2)).unwrap_err();
+        assert!(err.contains("must be even"), "{}", err);
+
+        let err = encode(&Op::Unary("jmp".into(), 0x3FFFFF)).unwrap_err();
+        assert_eq!(err, "JMP: target 0x3fffff past the end of flash (0x1fff)");
+
+        let err = encode(&Op::Unary("call".into(), 0x2000)).unwrap_err();
+        assert!(err.contains("past the end of flash"), "{}", err);
+
+        let err = encode(&Op::Nullary("frob".into())).unwrap_err();
+        assert_eq!(err, "Unknown instruction: frob");
+    }
+
+    #[test]
+    fn cseg_errors_name_the_address() {
+        let mut cseg = HashMap::new();
+        cseg.insert(7, Op::Binary("ldi".into(), 0, 0));
+        let err = encode_cseg(&cseg).unwrap_err();
+        assert!(err.ends_with(" at 7"), "{}", err);
+    }
+}
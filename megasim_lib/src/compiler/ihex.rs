@@ -0,0 +1,228 @@
+use crate::compiler::codegen::Op;
+use crate::compiler::encoder::encode_cseg;
+use std::collections::{BTreeMap, HashMap};
+
+//
+// Intel HEX output
+//
+// Data records hold at most 16 bytes and never cross a gap or a 64 KB
+// boundary. Addresses above 64 KB are reached with extended linear address
+// records.
+//
+
+const RECORD_DATA: u8 = 0x00;
+const RECORD_EOF: u8 = 0x01;
+const RECORD_EXTENDED_SEGMENT: u8 = 0x02;
+const RECORD_START_SEGMENT: u8 = 0x03;
+const RECORD_EXTENDED_LINEAR: u8 = 0x04;
+const RECORD_START_LINEAR: u8 = 0x05;
+
+const RECORD_LEN: usize = 16;
+
+fn record(kind: u8, addr: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+    bytes.extend_from_slice(data);
+
+    let sum = bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    bytes.push(sum.wrapping_neg());
+
+    let mut line = String::from(":");
+    for b in bytes {
+        line.push_str(&format!("{:02X}", b));
+    }
+    line.push('\n');
+    line
+}
+
+// Writes a byte image, keyed by byte address, as Intel HEX.
+pub fn to_ihex(image: &BTreeMap<u64, u8>) -> String {
+    let mut out = String::new();
+    let mut upper = 0;
+    let mut run: Vec<u8> = vec![];
+    let mut run_addr = 0;
+
+    for (addr, byte) in image {
+        let contiguous = run_addr + run.len() as u64 == *addr && addr >> 16 == run_addr >> 16;
+        if !run.is_empty() && (!contiguous || run.len() == RECORD_LEN) {
+            out.push_str(&record(RECORD_DATA, run_addr as u16, &run));
+            run.clear();
+        }
+
+        if run.is_empty() {
+            run_addr = *addr;
+            if addr >> 16 != upper {
+                upper = addr >> 16;
+                out.push_str(&record(
+                    RECORD_EXTENDED_LINEAR,
+                    0,
+                    &(upper as u16).to_be_bytes(),
+                ));
+            }
+        }
+        run.push(*byte);
+    }
+
+    if !run.is_empty() {
+        out.push_str(&record(RECORD_DATA, run_addr as u16, &run));
+    }
+    out.push_str(&record(RECORD_EOF, 0, &[]));
+
+    out
+}
+
+// Encodes the code segment and writes it as Intel HEX. Flash words are
+// stored little-endian at byte address 2 * word address.
+pub fn cseg_to_ihex(cseg: &HashMap<u64, Op>) -> Result<String, String> {
+    let mut image = BTreeMap::new();
+
+    for (addr, word) in encode_cseg(cseg)? {
+        image.insert(addr * 2, word as u8);
+        image.insert(addr * 2 + 1, (word >> 8) as u8);
+    }
+
+    Ok(to_ihex(&image))
+}
+
+// Writes the EEPROM segment as Intel HEX, the contents of an `.eep` file.
+pub fn eseg_to_ihex(eseg: &HashMap<u64, u64>) -> String {
+    let image: BTreeMap<u64, u8> = eseg.iter().map(|(a, b)| (*a, *b as u8)).collect();
+    to_ihex(&image)
+}
+
+//
+// Intel HEX input
+//
+
+fn parse_record(line: &str) -> Result<(u8, u16, Vec<u8>), String> {
+    let digits = line
+        .strip_prefix(':')
+        .ok_or_else(|| "record must start with ':'".to_string())?;
+    if digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("invalid hex digits".into());
+    }
+
+    let bytes: Vec<u8> = (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+        .collect();
+    if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+        return Err("invalid record length".into());
+    }
+    if bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) != 0 {
+        return Err("checksum mismatch".into());
+    }
+
+    let addr = u16::from_be_bytes([bytes[1], bytes[2]]);
+    Ok((bytes[3], addr, bytes[4..bytes.len() - 1].to_vec()))
+}
+
+// Reads an Intel HEX file into a byte image, keyed by byte address.
+pub fn from_ihex(text: &str) -> Result<BTreeMap<u64, u8>, String> {
+    let mut image = BTreeMap::new();
+    let mut base: u64 = 0;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (kind, addr, data) =
+            parse_record(line).map_err(|e| format!("Line {}: {}", n + 1, e))?;
+        match kind {
+            RECORD_DATA => {
+                for (i, byte) in data.iter().enumerate() {
+                    image.insert(base + addr as u64 + i as u64, *byte);
+                }
+            }
+            RECORD_EOF => return Ok(image),
+            RECORD_EXTENDED_SEGMENT | RECORD_EXTENDED_LINEAR if data.len() == 2 => {
+                let upper = u16::from_be_bytes([data[0], data[1]]) as u64;
+                base = if kind == RECORD_EXTENDED_SEGMENT {
+                    upper << 4
+                } else {
+                    upper << 16
+                };
+            }
+            RECORD_START_SEGMENT | RECORD_START_LINEAR => {}
+            _ => return Err(format!("Line {}: invalid record type {:02X}", n + 1, kind)),
+        }
+    }
+
+    Err("Missing end-of-file record".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+
+    #[test]
+    fn cseg_words_are_little_endian() {
+        let (cseg, _, _) = compile("ldi r16, 1\nrjmp 0\n").unwrap();
+        assert_eq!(
+            cseg_to_ihex(&cseg).unwrap(),
+            ":0400000001E0FECF4E\n:00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn eseg_bytes() {
+        let (_, _, eseg) = compile(".eseg\n.org 0x10\n.db 0xAA, 0x55\n").unwrap();
+        assert_eq!(eseg_to_ihex(&eseg), ":02001000AA55EF\n:00000001FF\n");
+    }
+
+    #[test]
+    fn records_split_at_16_bytes_and_gaps() {
+        let mut image: BTreeMap<u64, u8> = (0..20).map(|a| (a, a as u8)).collect();
+        image.insert(0x40, 0xFF);
+        let lines: Vec<_> = to_ihex(&image).lines().map(String::from).collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with(":10000000"));
+        assert!(lines[1].starts_with(":04001000"));
+        assert!(lines[2].starts_with(":01004000FF"));
+        assert_eq!(lines[3], ":00000001FF");
+    }
+
+    #[test]
+    fn extended_linear_address_above_64k() {
+        let image = BTreeMap::from([(0xFFFF, 0x11), (0x10000, 0x22)]);
+        assert_eq!(
+            to_ihex(&image),
+            ":01FFFF0011F0\n:020000040001F9\n:0100000022DD\n:00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let image: BTreeMap<u64, u8> = (0x1FFF0..0x20010).map(|a| (a, a as u8)).collect();
+        assert_eq!(from_ihex(&to_ihex(&image)).unwrap(), image);
+    }
+
+    #[test]
+    fn extended_segment_address() {
+        let image = from_ihex(":020000021000EC\n:0100000022DD\n:00000001FF\n").unwrap();
+        assert_eq!(image, BTreeMap::from([(0x10000, 0x22)]));
+    }
+
+    #[test]
+    fn input_errors() {
+        let cases = [
+            (
+                "0100000022DD\n:00000001FF\n",
+                "Line 1: record must start with ':'",
+            ),
+            (":0100000022DE\n:00000001FF\n", "Line 1: checksum mismatch"),
+            (
+                ":0200000022DD\n:00000001FF\n",
+                "Line 1: invalid record length",
+            ),
+            (":01000000G2DD\n:00000001FF\n", "Line 1: invalid hex digits"),
+            ("\n:00000006FA\n", "Line 2: invalid record type 06"),
+            (":0100000022DD\n", "Missing end-of-file record"),
+        ];
+        for (text, err) in cases {
+            assert_eq!(from_ihex(text).unwrap_err(), err);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::vec;
+use std::{iter::Peekable, str::Chars, vec};
 
 //
 // Tokenizer
@@ -22,6 +22,8 @@ pub enum Token {
     EndOfLine,
 
     String(String),
+    // String literal, with escapes resolved and case kept
+    Quoted(String),
 
     None,
 }
@@ -45,11 +47,12 @@ impl Token {
             Token::EndOfLine => matches!(other, Token::EndOfLine),
 
             Token::String(_) => matches!(other, Token::String(_)),
+            Token::Quoted(_) => matches!(other, Token::Quoted(_)),
             Token::None => false,
         }
     }
 
-    pub fn from_string(&self) -> String {
+    pub fn get_string(&self) -> String {
         match self {
             Token::String(x) => x.to_string(),
             _ => panic!("Current token is not a string!"),
@@ -57,14 +60,43 @@ impl Token {
     }
 }
 
+// Reads a string literal up to the closing quote. An unterminated literal
+// ends at the end of the line.
+fn read_quoted(chars: &mut Peekable<Chars>) -> String {
+    let mut out = String::new();
+
+    while let Some(&c) = chars.peek() {
+        if c == '\n' {
+            break;
+        }
+        chars.next();
+
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some('t') => out.push('\t'),
+                Some('0') => out.push('\0'),
+                Some(c) => out.push(c),
+                None => {}
+            },
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
 pub fn tokenize(text: &str) -> Vec<Token> {
     let mut tokens = vec![];
-    let text = text.to_lowercase();
     let text = text.replace("\r", "");
+    let mut chars = text.chars().peekable();
     let mut current_string: Vec<char> = vec![];
 
-    for c in text.chars() {
+    while let Some(c) = chars.next() {
         let tok = match c {
+            '"' => Token::Quoted(read_quoted(&mut chars)),
             '.' => Token::Dot,
             ',' => Token::Comma,
             ':' => Token::Colon,
@@ -83,8 +115,9 @@ pub fn tokenize(text: &str) -> Vec<Token> {
             ' ' => Token::Space,
             '\t' => Token::Tab,
             '\n' => Token::EndOfLine,
+            // Everything but string literals is case insensitive
             _ => {
-                current_string.push(c);
+                current_string.extend(c.to_lowercase());
                 Token::None
             }
         };
@@ -107,12 +140,14 @@ pub fn tokenize(text: &str) -> Vec<Token> {
     tokens
 }
 
+#[allow(dead_code)]
 pub fn detokenize(tokens: &[Token]) -> String {
     let mut out = String::new();
 
     for t in tokens {
         match t {
             Token::String(s) => out.push_str(s),
+            Token::Quoted(s) => out.push_str(&format!("{:?}", s)),
             Token::Dot => out.push('.'),
             Token::Comma => out.push(','),
             Token::Colon => out.push(':'),
@@ -171,3 +206,40 @@ impl<T> Stream<T> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Token {
+        Token::String(s.to_string())
+    }
+
+    #[test]
+    fn quoted_strings_keep_case() {
+        let tokens = tokenize("LDI R16, 1 ; \"Hi, There\"");
+        assert_eq!(tokens[0], string("ldi"));
+        assert_eq!(tokens[2], string("r16"));
+        assert_eq!(tokens[9], Token::Quoted("Hi, There".to_string()));
+    }
+
+    #[test]
+    fn quoted_string_escapes() {
+        let tokens = tokenize(r#""a\"b\\c\n\0""#);
+        assert_eq!(tokens[0], Token::Quoted("a\"b\\c\n\0".to_string()));
+    }
+
+    #[test]
+    fn unterminated_string_ends_at_line_end() {
+        let tokens = tokenize("\"abc\nnop");
+        assert_eq!(
+            tokens,
+            [
+                Token::Quoted("abc".to_string()),
+                Token::EndOfLine,
+                string("nop"),
+                Token::EndOfLine,
+            ]
+        );
+    }
+}
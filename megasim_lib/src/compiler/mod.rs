@@ -1,8 +1,21 @@
 mod lexer;
 mod parser;
 mod codegen;
+mod decoder;
+mod disassembler;
+mod encoder;
+mod ihex;
+mod opcodes;
 
+#[allow(clippy::module_inception)]
 mod compiler;
 mod atmega16a;
 
-pub use {codegen::Op, compiler::compile};
\ No newline at end of file
+pub use {
+    codegen::{Op, PTR_POST_INC, PTR_PRE_DEC, Program},
+    compiler::compile,
+    decoder::decode,
+    disassembler::{disassemble, disassemble_cseg, disassemble_flash},
+    encoder::{encode, encode_cseg},
+    ihex::{cseg_to_ihex, eseg_to_ihex, from_ihex, to_ihex},
+};
\ No newline at end of file
@@ -0,0 +1,233 @@
+use crate::compiler::codegen::{PTR_POST_INC, PTR_PRE_DEC};
+use std::sync::OnceLock;
+
+//
+// Opcode table
+//
+// Patterns are written as in the instruction set manual, MSB first. Letters
+// mark operand bits; an operand's bits are listed MSB first wherever its
+// letter appears.
+//
+
+#[derive(Debug, Clone, Copy)]
+pub enum Arg {
+    // R0-R31
+    Reg(char),
+    // R16-R31
+    RegHigh(char),
+    // R16-R23
+    RegMul(char),
+    // R24, R26, R28, R30
+    RegWord(char),
+    // Even registers R0-R30
+    RegPair(char),
+    // Unsigned constant
+    Imm(char),
+    // 8-bit constant, also accepts negative values
+    Byte(char),
+    // Signed word offset
+    Rel(char),
+    // Fixed pointer operand, has no bits in the opcode
+    Ptr(i64),
+}
+
+impl Arg {
+    pub fn letter(&self) -> Option<char> {
+        match *self {
+            Arg::Reg(l)
+            | Arg::RegHigh(l)
+            | Arg::RegMul(l)
+            | Arg::RegWord(l)
+            | Arg::RegPair(l)
+            | Arg::Imm(l)
+            | Arg::Byte(l)
+            | Arg::Rel(l) => Some(l),
+            Arg::Ptr(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub args: &'static [Arg],
+    pub bits: u32,
+    pub mask: u32,
+    pub value: u32,
+    fields: Vec<(char, Vec<u32>)>,
+}
+
+impl Opcode {
+    // Size of the instruction in flash words.
+    pub fn width(&self) -> usize {
+        (self.bits / 16) as usize
+    }
+
+    // Bit positions of an operand, MSB first.
+    pub fn positions(&self, letter: char) -> &[u32] {
+        self.fields
+            .iter()
+            .find(|(l, _)| *l == letter)
+            .map_or(&[], |(_, positions)| positions.as_slice())
+    }
+}
+
+const X: i64 = 26;
+const Y: i64 = 28;
+const Z: i64 = 30;
+
+use Arg::*;
+
+#[rustfmt::skip]
+const TABLE: &[(&str, &str, &[Arg])] = &[
+    // Arithmetic and Logic
+    ("adc", "0001 11rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("add", "0000 11rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("adiw", "1001 0110 KKdd KKKK", &[RegWord('d'), Imm('K')]),
+    ("and", "0010 00rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("andi", "0111 KKKK dddd KKKK", &[RegHigh('d'), Byte('K')]),
+    ("com", "1001 010d dddd 0000", &[Reg('d')]),
+    ("cp", "0001 01rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("cpc", "0000 01rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("cpi", "0011 KKKK dddd KKKK", &[RegHigh('d'), Byte('K')]),
+    ("dec", "1001 010d dddd 1010", &[Reg('d')]),
+    ("eor", "0010 01rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("fmul", "0000 0011 0ddd 1rrr", &[RegMul('d'), RegMul('r')]),
+    ("fmuls", "0000 0011 1ddd 0rrr", &[RegMul('d'), RegMul('r')]),
+    ("fmulsu", "0000 0011 1ddd 1rrr", &[RegMul('d'), RegMul('r')]),
+    ("inc", "1001 010d dddd 0011", &[Reg('d')]),
+    ("mul", "1001 11rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("muls", "0000 0010 dddd rrrr", &[RegHigh('d'), RegHigh('r')]),
+    ("mulsu", "0000 0011 0ddd 0rrr", &[RegMul('d'), RegMul('r')]),
+    ("neg", "1001 010d dddd 0001", &[Reg('d')]),
+    ("or", "0010 10rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("ori", "0110 KKKK dddd KKKK", &[RegHigh('d'), Byte('K')]),
+    ("sbc", "0000 10rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("sbci", "0100 KKKK dddd KKKK", &[RegHigh('d'), Byte('K')]),
+    ("sbiw", "1001 0111 KKdd KKKK", &[RegWord('d'), Imm('K')]),
+    ("sub", "0001 10rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("subi", "0101 KKKK dddd KKKK", &[RegHigh('d'), Byte('K')]),
+
+    // Branch
+    ("brbc", "1111 01kk kkkk ksss", &[Imm('s'), Rel('k')]),
+    ("brbs", "1111 00kk kkkk ksss", &[Imm('s'), Rel('k')]),
+    ("call", "1001 010k kkkk 111k kkkk kkkk kkkk kkkk", &[Imm('k')]),
+    ("cpse", "0001 00rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("icall", "1001 0101 0000 1001", &[]),
+    ("ijmp", "1001 0100 0000 1001", &[]),
+    ("jmp", "1001 010k kkkk 110k kkkk kkkk kkkk kkkk", &[Imm('k')]),
+    ("rcall", "1101 kkkk kkkk kkkk", &[Rel('k')]),
+    ("ret", "1001 0101 0000 1000", &[]),
+    ("reti", "1001 0101 0001 1000", &[]),
+    ("rjmp", "1100 kkkk kkkk kkkk", &[Rel('k')]),
+    ("sbic", "1001 1001 AAAA Abbb", &[Imm('A'), Imm('b')]),
+    ("sbis", "1001 1011 AAAA Abbb", &[Imm('A'), Imm('b')]),
+    ("sbrc", "1111 110r rrrr 0bbb", &[Reg('r'), Imm('b')]),
+    ("sbrs", "1111 111r rrrr 0bbb", &[Reg('r'), Imm('b')]),
+
+    // Data Transfer
+    ("in", "1011 0AAd dddd AAAA", &[Reg('d'), Imm('A')]),
+    ("ld", "1001 000d dddd 1100", &[Reg('d'), Ptr(X)]),
+    ("ld", "1001 000d dddd 1101", &[Reg('d'), Ptr(X | PTR_POST_INC)]),
+    ("ld", "1001 000d dddd 1110", &[Reg('d'), Ptr(X | PTR_PRE_DEC)]),
+    ("ld", "1000 000d dddd 1000", &[Reg('d'), Ptr(Y)]),
+    ("ld", "1001 000d dddd 1001", &[Reg('d'), Ptr(Y | PTR_POST_INC)]),
+    ("ld", "1001 000d dddd 1010", &[Reg('d'), Ptr(Y | PTR_PRE_DEC)]),
+    ("ld", "1000 000d dddd 0000", &[Reg('d'), Ptr(Z)]),
+    ("ld", "1001 000d dddd 0001", &[Reg('d'), Ptr(Z | PTR_POST_INC)]),
+    ("ld", "1001 000d dddd 0010", &[Reg('d'), Ptr(Z | PTR_PRE_DEC)]),
+    ("ldd", "10q0 qq0d dddd 1qqq", &[Reg('d'), Ptr(Y), Imm('q')]),
+    ("ldd", "10q0 qq0d dddd 0qqq", &[Reg('d'), Ptr(Z), Imm('q')]),
+    ("ldi", "1110 KKKK dddd KKKK", &[RegHigh('d'), Byte('K')]),
+    ("lds", "1001 000d dddd 0000 kkkk kkkk kkkk kkkk", &[Reg('d'), Imm('k')]),
+    ("lpm", "1001 0101 1100 1000", &[]),
+    ("lpm", "1001 000d dddd 0100", &[Reg('d'), Ptr(Z)]),
+    ("lpm", "1001 000d dddd 0101", &[Reg('d'), Ptr(Z | PTR_POST_INC)]),
+    ("mov", "0010 11rd dddd rrrr", &[Reg('d'), Reg('r')]),
+    ("movw", "0000 0001 dddd rrrr", &[RegPair('d'), RegPair('r')]),
+    ("out", "1011 1AAr rrrr AAAA", &[Imm('A'), Reg('r')]),
+    ("pop", "1001 000d dddd 1111", &[Reg('d')]),
+    ("push", "1001 001d dddd 1111", &[Reg('d')]),
+    ("spm", "1001 0101 1110 1000", &[]),
+    ("st", "1001 001r rrrr 1100", &[Ptr(X), Reg('r')]),
+    ("st", "1001 001r rrrr 1101", &[Ptr(X | PTR_POST_INC), Reg('r')]),
+    ("st", "1001 001r rrrr 1110", &[Ptr(X | PTR_PRE_DEC), Reg('r')]),
+    ("st", "1000 001r rrrr 1000", &[Ptr(Y), Reg('r')]),
+    ("st", "1001 001r rrrr 1001", &[Ptr(Y | PTR_POST_INC), Reg('r')]),
+    ("st", "1001 001r rrrr 1010", &[Ptr(Y | PTR_PRE_DEC), Reg('r')]),
+    ("st", "1000 001r rrrr 0000", &[Ptr(Z), Reg('r')]),
+    ("st", "1001 001r rrrr 0001", &[Ptr(Z | PTR_POST_INC), Reg('r')]),
+    ("st", "1001 001r rrrr 0010", &[Ptr(Z | PTR_PRE_DEC), Reg('r')]),
+    ("std", "10q0 qq1r rrrr 1qqq", &[Ptr(Y), Imm('q'), Reg('r')]),
+    ("std", "10q0 qq1r rrrr 0qqq", &[Ptr(Z), Imm('q'), Reg('r')]),
+    ("sts", "1001 001r rrrr 0000 kkkk kkkk kkkk kkkk", &[Imm('k'), Reg('r')]),
+
+    // Bit and Bittest
+    ("asr", "1001 010d dddd 0101", &[Reg('d')]),
+    ("bclr", "1001 0100 1sss 1000", &[Imm('s')]),
+    ("bld", "1111 100d dddd 0bbb", &[Reg('d'), Imm('b')]),
+    ("bset", "1001 0100 0sss 1000", &[Imm('s')]),
+    ("bst", "1111 101d dddd 0bbb", &[Reg('d'), Imm('b')]),
+    ("cbi", "1001 1000 AAAA Abbb", &[Imm('A'), Imm('b')]),
+    ("lsr", "1001 010d dddd 0110", &[Reg('d')]),
+    ("ror", "1001 010d dddd 0111", &[Reg('d')]),
+    ("sbi", "1001 1010 AAAA Abbb", &[Imm('A'), Imm('b')]),
+    ("swap", "1001 010d dddd 0010", &[Reg('d')]),
+
+    // MCU Control
+    ("break", "1001 0101 1001 1000", &[]),
+    ("nop", "0000 0000 0000 0000", &[]),
+    ("sleep", "1001 0101 1000 1000", &[]),
+    ("wdr", "1001 0101 1010 1000", &[]),
+];
+
+// Conditional branches on a set / cleared SREG bit, indexed by bit.
+pub const BRANCH_SET: [&str; 8] = [
+    "brcs", "breq", "brmi", "brvs", "brlt", "brhs", "brts", "brie",
+];
+pub const BRANCH_CLEAR: [&str; 8] = [
+    "brcc", "brne", "brpl", "brvc", "brge", "brhc", "brtc", "brid",
+];
+
+// SREG bit set / clear instructions, indexed by bit.
+pub const FLAG_SET: [&str; 8] = ["sec", "sez", "sen", "sev", "ses", "seh", "set", "sei"];
+pub const FLAG_CLEAR: [&str; 8] = ["clc", "clz", "cln", "clv", "cls", "clh", "clt", "cli"];
+
+pub fn opcodes() -> &'static [Opcode] {
+    static OPCODES: OnceLock<Vec<Opcode>> = OnceLock::new();
+
+    OPCODES.get_or_init(|| {
+        TABLE
+            .iter()
+            .map(|(mnemonic, pattern, args)| {
+                let bits: Vec<char> = pattern.chars().filter(|c| *c != ' ').collect();
+                let mut mask = 0;
+                let mut value = 0;
+                let mut fields: Vec<(char, Vec<u32>)> = vec![];
+                for (i, c) in bits.iter().enumerate() {
+                    let pos = (bits.len() - 1 - i) as u32;
+                    match c {
+                        '0' => mask |= 1 << pos,
+                        '1' => {
+                            mask |= 1 << pos;
+                            value |= 1 << pos;
+                        }
+                        _ => match fields.iter_mut().find(|(l, _)| l == c) {
+                            Some((_, positions)) => positions.push(pos),
+                            None => fields.push((*c, vec![pos])),
+                        },
+                    }
+                }
+
+                Opcode {
+                    mnemonic,
+                    args,
+                    bits: bits.len() as u32,
+                    mask,
+                    value,
+                    fields,
+                }
+            })
+            .collect()
+    })
+}
@@ -1,4 +1,4 @@
-use crate::compiler::lexer::{Token, Stream};
+use crate::compiler::lexer::{Stream, Token, tokenize};
 
 //
 // Parser types
@@ -16,6 +16,8 @@ pub enum Directive {
     Equ(String, Expression),
     Def(String, String),
     Org(Expression),
+    Db(Vec<Expression>),
+    Dw(Vec<Expression>),
     Cseg,
     Dseg,
     Eseg,
@@ -27,6 +29,22 @@ pub enum Expression {
     Identifier(String),
     BinaryOp(Operator, Box<Expression>, Box<Expression>),
     FunctionCall(Function, Box<Expression>),
+    Pointer(Pointer, PointerMode),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Pointer {
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Debug)]
+pub enum PointerMode {
+    Plain,
+    PostIncrement,
+    PreDecrement,
+    Displacement(Box<Expression>),
 }
 
 #[derive(Debug)]
@@ -103,8 +121,8 @@ fn parse_expression(tb: &mut Stream<Token>) -> Expression {
                 Expression::FunctionCall(func, Box::new(arg))
             } else if let Ok(num) = val.parse::<i64>() {
                 Expression::Integer(num)
-            } else if val.starts_with("0x") {
-                let num = i64::from_str_radix(&val[2..], 16).unwrap_or(0);
+            } else if let Some(hex) = val.strip_prefix("0x") {
+                let num = i64::from_str_radix(hex, 16).unwrap_or(0);
                 Expression::Integer(num)
             } else {
                 Expression::Identifier(val)
@@ -114,27 +132,113 @@ fn parse_expression(tb: &mut Stream<Token>) -> Expression {
     };
 
     capture_only(tb, &[Token::Space]);
-    if !tb.end() && tb.current().is(&Token::Less) {
-        if let Some(next) = tb.peek(1) {
-            if next.is(&Token::Less) {
-                tb.advance();
-                tb.advance();
-                let right = parse_expression(tb);
-                expr = Expression::BinaryOp(Operator::ShiftLeft, Box::new(expr), Box::new(right));
-            }
-        }
+    if !tb.end()
+        && tb.current().is(&Token::Less)
+        && tb.peek(1).is_some_and(|next| next.is(&Token::Less))
+    {
+        tb.advance();
+        tb.advance();
+        let right = parse_expression(tb);
+        expr = Expression::BinaryOp(Operator::ShiftLeft, Box::new(expr), Box::new(right));
     }
 
     expr
 }
 
+//
+// Pointer Parser
+//
+
+fn takes_pointer(mnemonic: &str) -> bool {
+    matches!(mnemonic, "ld" | "ldd" | "st" | "std" | "lpm")
+}
+
+// Parses `X`, `X+`, `-X` and `Y+q` operands. Leaves the stream untouched and
+// returns None if the operand is not pointer syntax.
+fn parse_pointer(tb: &mut Stream<Token>) -> Option<Expression> {
+    let start = tb.pos;
+
+    let mut text = String::new();
+    for t in capture_until(tb, &[Token::Comma, Token::Semicolon, Token::EndOfLine]) {
+        match t {
+            Token::String(s) => text.push_str(s),
+            Token::LeftParen => text.push('('),
+            Token::RightParen => text.push(')'),
+            Token::Less => text.push('<'),
+            Token::Greater => text.push('>'),
+            _ => {}
+        }
+    }
+
+    let (pre_decrement, rest) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.as_str()),
+    };
+
+    let pointer = match rest.chars().next() {
+        Some('x') => Some(Pointer::X),
+        Some('y') => Some(Pointer::Y),
+        Some('z') => Some(Pointer::Z),
+        _ => None,
+    };
+
+    let mode = match (pre_decrement, rest.get(1..)) {
+        (true, Some("")) => Some(PointerMode::PreDecrement),
+        (false, Some("")) => Some(PointerMode::Plain),
+        (false, Some("+")) => Some(PointerMode::PostIncrement),
+        (false, Some(disp)) if disp.starts_with('+') => {
+            let mut disp_tb = Stream::new(tokenize(&disp[1..]));
+            Some(PointerMode::Displacement(Box::new(parse_expression(
+                &mut disp_tb,
+            ))))
+        }
+        _ => None,
+    };
+
+    match (pointer, mode) {
+        (Some(pointer), Some(mode)) => Some(Expression::Pointer(pointer, mode)),
+        _ => {
+            tb.pos = start;
+            None
+        }
+    }
+}
+
 //
 // Directive Parser
 //
 
+fn parse_expression_list(tb: &mut Stream<Token>) -> Vec<Expression> {
+    let mut values = vec![];
+
+    loop {
+        capture_only(tb, &[Token::Space, Token::Tab]);
+        if tb.end() || tb.current().is(&Token::EndOfLine) || tb.current().is(&Token::Semicolon) {
+            break;
+        }
+
+        // A string literal stands for its bytes
+        if let Token::Quoted(text) = tb.current() {
+            values.extend(text.bytes().map(|b| Expression::Integer(b as i64)));
+            tb.advance();
+        } else {
+            values.push(parse_expression(tb));
+        }
+
+        capture_only(tb, &[Token::Space, Token::Tab]);
+        if !tb.end() && tb.current().is(&Token::Comma) {
+            tb.advance();
+        } else {
+            break;
+        }
+    }
+
+    values
+}
+
 fn parse_directive(tb: &mut Stream<Token>) -> Statement {
     tb.advance();
-    let dir_name = tb.current().from_string();
+    let dir_name = tb.current().get_string();
     tb.advance();
 
     let filler = &[Token::Space, Token::Equals];
@@ -143,7 +247,7 @@ fn parse_directive(tb: &mut Stream<Token>) -> Statement {
         "equ" => {
             // .equ NAME = VALUE
             capture_only(tb, filler);
-            let name = tb.current().from_string();
+            let name = tb.current().get_string();
             tb.advance();
 
             capture_only(tb, filler);
@@ -153,11 +257,11 @@ fn parse_directive(tb: &mut Stream<Token>) -> Statement {
         "def" => {
             // .def NAME = REGISTER
             capture_only(tb, filler);
-            let name = tb.current().from_string();
+            let name = tb.current().get_string();
             tb.advance();
 
             capture_only(tb, filler);
-            let register = tb.current().from_string();
+            let register = tb.current().get_string();
             tb.advance();
             Directive::Def(name, register)
         }
@@ -167,9 +271,11 @@ fn parse_directive(tb: &mut Stream<Token>) -> Statement {
             let value = parse_expression(tb);
             Directive::Org(value)
         }
+        "db" => Directive::Db(parse_expression_list(tb)),
+        "dw" => Directive::Dw(parse_expression_list(tb)),
         "cseg" => Directive::Cseg,
         "dseg" => Directive::Dseg,
-        "eseg" => Directive::Dseg,
+        "eseg" => Directive::Eseg,
         x => panic!("Unknown directive: {:?}", x),
     };
 
@@ -177,7 +283,7 @@ fn parse_directive(tb: &mut Stream<Token>) -> Statement {
 }
 
 fn parse_instruction(tb: &mut Stream<Token>) -> Statement {
-    let mnemonic = tb.current().from_string();
+    let mnemonic = tb.current().get_string();
     tb.advance();
 
     let mut operands = vec![];
@@ -188,7 +294,12 @@ fn parse_instruction(tb: &mut Stream<Token>) -> Statement {
             break;
         }
 
-        operands.push(parse_expression(tb));
+        let pointer = if takes_pointer(&mnemonic) {
+            parse_pointer(tb)
+        } else {
+            None
+        };
+        operands.push(pointer.unwrap_or_else(|| parse_expression(tb)));
 
         capture_only(tb, &[Token::Space]);
 
@@ -203,7 +314,7 @@ fn parse_instruction(tb: &mut Stream<Token>) -> Statement {
 }
 
 fn parse_label(tb: &mut Stream<Token>) -> Statement {
-    let name = tb.current().from_string();
+    let name = tb.current().get_string();
     tb.advance();
     tb.advance();
 
@@ -240,17 +351,23 @@ fn parse_line(tb: &mut Stream<Token>) -> Vec<Statement> {
     statements
 }
 
-pub fn parse(tokens: &[Token]) -> Vec<Statement> {
-    let mut ir: Vec<Statement> = vec![Statement::Directive(Directive::Cseg)];
+// Parses the statements of a program, each with the source line it is on.
+pub fn parse(tokens: &[Token]) -> Vec<(usize, Statement)> {
+    let mut ir: Vec<(usize, Statement)> = vec![(1, Statement::Directive(Directive::Cseg))];
+    let mut line = 1;
 
     let mut tb = Stream::new(tokens.to_vec());
 
     while !tb.end() {
+        if tb.current().is(&Token::EndOfLine) {
+            line += 1;
+        }
+
         if tb.current().is(&Token::Space) || tb.current().is(&Token::EndOfLine) || tb.current().is(&Token::Tab) {
             tb.advance();
         } else {
             for s in parse_line(&mut tb) {
-                ir.push(s);
+                ir.push((line, s));
             }
 
             if !(tb.end() || tb.current().is(&Token::EndOfLine)) {
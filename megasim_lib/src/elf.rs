@@ -0,0 +1,379 @@
+use std::collections::{BTreeMap, HashMap};
+
+//
+// ELF loader for avr-gcc output
+//
+// AVR ELF files are 32-bit little-endian. Addresses live in separate spaces:
+// flash at 0x000000, SRAM at 0x800000 and EEPROM at 0x810000. Flash and
+// EEPROM contents are taken from the program headers (load addresses),
+// .data and .bss from the section headers (SRAM addresses).
+//
+
+const EM_AVR: u16 = 83;
+
+const PT_LOAD: u32 = 1;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+const SRAM_BASE: u32 = 0x800000;
+const EEPROM_BASE: u32 = 0x810000;
+const EEPROM_END: u32 = 0x820000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    // Address is a flash byte address
+    Function,
+    // Address is a data space address
+    Object,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub addr: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct Elf {
+    // Keyed by byte address in their own address space
+    pub flash: BTreeMap<u64, u8>,
+    pub sram: BTreeMap<u64, u8>,
+    pub eeprom: BTreeMap<u64, u8>,
+    pub symbols: Vec<Symbol>,
+}
+
+struct Section {
+    name: u32,
+    kind: u32,
+    addr: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+}
+
+fn u16_at(b: &[u8], off: usize) -> Result<u16, String> {
+    b.get(off..off + 2)
+        .map(|s| u16::from_le_bytes([s[0], s[1]]))
+        .ok_or_else(|| "Truncated ELF file".to_string())
+}
+
+fn u32_at(b: &[u8], off: usize) -> Result<u32, String> {
+    b.get(off..off + 4)
+        .map(|s| u32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+        .ok_or_else(|| "Truncated ELF file".to_string())
+}
+
+fn bytes_at(b: &[u8], off: u32, len: u32) -> Result<&[u8], String> {
+    b.get(off as usize..(off as usize).saturating_add(len as usize))
+        .ok_or_else(|| "Truncated ELF file".to_string())
+}
+
+fn string_at(b: &[u8], strtab: &Section, off: u32) -> Result<String, String> {
+    let table = bytes_at(b, strtab.offset, strtab.size)?;
+    let s = table
+        .get(off as usize..)
+        .ok_or("Invalid string table offset")?;
+    let end = s.iter().position(|c| *c == 0).unwrap_or(s.len());
+    Ok(String::from_utf8_lossy(&s[..end]).into_owned())
+}
+
+pub fn parse_elf(b: &[u8]) -> Result<Elf, String> {
+    if b.get(0..4) != Some(b"\x7fELF") {
+        return Err("Not an ELF file".into());
+    }
+    if b.get(4..6) != Some(&[1, 1]) {
+        return Err("Only 32-bit little-endian ELF files are supported".into());
+    }
+    if u16_at(b, 18)? != EM_AVR {
+        return Err("Not an AVR ELF file".into());
+    }
+
+    let phoff = u32_at(b, 28)? as usize;
+    let shoff = u32_at(b, 32)? as usize;
+    let phentsize = u16_at(b, 42)? as usize;
+    let phnum = u16_at(b, 44)? as usize;
+    let shentsize = u16_at(b, 46)? as usize;
+    let shnum = u16_at(b, 48)? as usize;
+    let shstrndx = u16_at(b, 50)? as usize;
+
+    let mut elf = Elf::default();
+
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        if u32_at(b, ph)? != PT_LOAD {
+            continue;
+        }
+
+        let offset = u32_at(b, ph + 4)?;
+        let paddr = u32_at(b, ph + 12)?;
+        let filesz = u32_at(b, ph + 16)?;
+
+        let (image, base) = match paddr {
+            0..SRAM_BASE => (&mut elf.flash, 0),
+            EEPROM_BASE..EEPROM_END => (&mut elf.eeprom, EEPROM_BASE),
+            _ => continue,
+        };
+        for (j, byte) in bytes_at(b, offset, filesz)?.iter().enumerate() {
+            image.insert((paddr - base) as u64 + j as u64, *byte);
+        }
+    }
+
+    let mut sections = vec![];
+    for i in 0..shnum {
+        let sh = shoff + i * shentsize;
+        sections.push(Section {
+            name: u32_at(b, sh)?,
+            kind: u32_at(b, sh + 4)?,
+            addr: u32_at(b, sh + 12)?,
+            offset: u32_at(b, sh + 16)?,
+            size: u32_at(b, sh + 20)?,
+            link: u32_at(b, sh + 24)?,
+        });
+    }
+
+    // Initial SRAM contents, as left behind by the C runtime startup code.
+    if let Some(shstrtab) = sections.get(shstrndx) {
+        for s in &sections {
+            let name = string_at(b, shstrtab, s.name)?;
+            if !(SRAM_BASE..EEPROM_BASE).contains(&s.addr) {
+                continue;
+            }
+
+            let addr = (s.addr - SRAM_BASE) as u64;
+            match name.as_str() {
+                ".data" => {
+                    for (j, byte) in bytes_at(b, s.offset, s.size)?.iter().enumerate() {
+                        elf.sram.insert(addr + j as u64, *byte);
+                    }
+                }
+                ".bss" if s.kind == SHT_NOBITS => {
+                    for j in 0..s.size as u64 {
+                        elf.sram.insert(addr + j, 0);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for symtab in sections.iter().filter(|s| s.kind == SHT_SYMTAB) {
+        let strtab = sections
+            .get(symtab.link as usize)
+            .ok_or("Invalid symbol string table")?;
+        let entries = bytes_at(b, symtab.offset, symtab.size)?;
+
+        for sym in entries.chunks_exact(16) {
+            let value = u32_at(sym, 4)?;
+            let kind = match sym[12] & 0xF {
+                STT_FUNC => SymbolKind::Function,
+                STT_OBJECT if value >= SRAM_BASE => SymbolKind::Object,
+                _ => continue,
+            };
+
+            elf.symbols.push(Symbol {
+                name: string_at(b, strtab, u32_at(sym, 0)?)?,
+                kind,
+                addr: match kind {
+                    SymbolKind::Function => value,
+                    SymbolKind::Object => value - SRAM_BASE,
+                },
+                size: u32_at(sym, 8)?,
+            });
+        }
+    }
+
+    Ok(elf)
+}
+
+// Names an address as `symbol+0xoffset`, using the symbol containing it or
+// the closest one below it.
+pub fn symbolize(symbols: &[Symbol], kind: SymbolKind, addr: u32) -> Option<String> {
+    let candidates = symbols.iter().filter(|s| s.kind == kind && s.addr <= addr);
+
+    let sym = candidates
+        .clone()
+        .filter(|s| addr < s.addr.saturating_add(s.size))
+        .max_by_key(|s| s.addr)
+        .or_else(|| candidates.max_by_key(|s| s.addr))?;
+
+    Some(match addr - sym.addr {
+        0 => sym.name.clone(),
+        offset => format!("{}+0x{:x}", sym.name, offset),
+    })
+}
+
+// Function symbols as disassembler labels, keyed by flash word address.
+pub fn function_labels(symbols: &[Symbol]) -> HashMap<u64, String> {
+    symbols
+        .iter()
+        .filter(|s| s.kind == SymbolKind::Function)
+        .map(|s| (s.addr as u64 / 2, s.name.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHT_PROGBITS: u32 = 1;
+    const SHT_STRTAB: u32 = 3;
+
+    fn put16(b: &mut Vec<u8>, v: u16) {
+        b.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn put32(b: &mut Vec<u8>, vals: &[u32]) {
+        for v in vals {
+            b.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    // A minimal avr-gcc style ELF: `main` and `loop` in flash, two bytes of
+    // EEPROM, an initialised `counter` in .data and three bytes of .bss.
+    fn avr_elf() -> Vec<u8> {
+        let mut symtab = vec![0; 16];
+        for (name, value, size, info) in [
+            (1, 0, 4, STT_FUNC),
+            (6, 2, 2, STT_FUNC),
+            (11, 0x800060, 2, STT_OBJECT),
+            (19, 0x10, 0, 0),
+        ] {
+            put32(&mut symtab, &[name, value, size]);
+            symtab.extend_from_slice(&[info, 0, 0, 0]);
+        }
+
+        let headers = 52 + 2 * 32;
+        let mut body = vec![];
+        let mut blob = |bytes: &[u8]| {
+            body.extend_from_slice(bytes);
+            (headers + body.len() - bytes.len()) as u32
+        };
+        let flash = blob(&[0x03, 0xE0, 0xFF, 0xCF]);
+        let eeprom = blob(&[0xAB, 0xCD]);
+        let data = blob(&[0x11, 0x22]);
+        let shstrtab = blob(b"\0.shstrtab\0.data\0.bss\0.symtab\0.strtab\0");
+        let strtab = blob(b"\0main\0loop\0counter\0__vectors\0");
+        let symbols = blob(&symtab);
+        let shoff = (headers + body.len()) as u32;
+
+        let mut b = b"\x7fELF\x01\x01\x01".to_vec();
+        b.resize(16, 0);
+        put16(&mut b, 2);
+        put16(&mut b, EM_AVR);
+        put32(&mut b, &[1, 0, 52, shoff, 0]);
+        for v in [52, 32, 2, 40, 6, 1] {
+            put16(&mut b, v);
+        }
+
+        put32(&mut b, &[PT_LOAD, flash, 0, 0, 4, 4, 5, 2]);
+        put32(
+            &mut b,
+            &[PT_LOAD, eeprom, EEPROM_BASE, EEPROM_BASE, 2, 2, 6, 1],
+        );
+        b.extend_from_slice(&body);
+
+        let sections = [
+            [0, 0, 0, 0, 0, 0, 0],
+            [1, SHT_STRTAB, 0, 0, shstrtab, 39, 0],
+            [11, SHT_PROGBITS, 3, 0x800060, data, 2, 0],
+            [17, SHT_NOBITS, 3, 0x800062, data + 2, 3, 0],
+            [22, SHT_SYMTAB, 0, 0, symbols, symtab.len() as u32, 5],
+            [30, SHT_STRTAB, 0, 0, strtab, 29, 0],
+        ];
+        for s in sections {
+            put32(&mut b, &s);
+            put32(&mut b, &[0, 1, 0]);
+        }
+        b
+    }
+
+    #[test]
+    fn loads_segments_and_sections() {
+        let elf = parse_elf(&avr_elf()).unwrap();
+        assert_eq!(
+            elf.flash,
+            BTreeMap::from([(0, 0x03), (1, 0xE0), (2, 0xFF), (3, 0xCF)])
+        );
+        assert_eq!(elf.eeprom, BTreeMap::from([(0, 0xAB), (1, 0xCD)]));
+        assert_eq!(
+            elf.sram,
+            BTreeMap::from([(0x60, 0x11), (0x61, 0x22), (0x62, 0), (0x63, 0), (0x64, 0)])
+        );
+    }
+
+    #[test]
+    fn reads_function_and_object_symbols() {
+        let elf = parse_elf(&avr_elf()).unwrap();
+        let symbols: Vec<_> = elf
+            .symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.kind, s.addr, s.size))
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                ("main", SymbolKind::Function, 0, 4),
+                ("loop", SymbolKind::Function, 2, 2),
+                ("counter", SymbolKind::Object, 0x60, 2),
+            ]
+        );
+        assert_eq!(
+            function_labels(&elf.symbols),
+            HashMap::from([(0, "main".to_string()), (1, "loop".to_string())])
+        );
+    }
+
+    #[test]
+    fn symbolize_prefers_the_innermost_symbol() {
+        let symbols = parse_elf(&avr_elf()).unwrap().symbols;
+        let name = |kind, addr| symbolize(&symbols, kind, addr);
+        assert_eq!(name(SymbolKind::Function, 0).as_deref(), Some("main"));
+        assert_eq!(name(SymbolKind::Function, 2).as_deref(), Some("loop"));
+        assert_eq!(name(SymbolKind::Function, 3).as_deref(), Some("loop+0x1"));
+        assert_eq!(name(SymbolKind::Function, 8).as_deref(), Some("loop+0x6"));
+        assert_eq!(
+            name(SymbolKind::Object, 0x61).as_deref(),
+            Some("counter+0x1")
+        );
+        assert_eq!(name(SymbolKind::Object, 0x20), None);
+    }
+
+    #[test]
+    fn symbolize_at_the_top_of_the_address_space() {
+        let symbols = [Symbol {
+            name: "top".into(),
+            kind: SymbolKind::Function,
+            addr: u32::MAX - 1,
+            size: 4,
+        }];
+        assert_eq!(
+            symbolize(&symbols, SymbolKind::Function, u32::MAX).as_deref(),
+            Some("top+0x1")
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut not_avr = avr_elf();
+        not_avr[18] = 40;
+
+        let cases: [(&[u8], &str); 4] = [
+            (b"junk", "Not an ELF file"),
+            (
+                b"\x7fELF\x02\x01",
+                "Only 32-bit little-endian ELF files are supported",
+            ),
+            (b"\x7fELF\x01\x01", "Truncated ELF file"),
+            (&not_avr, "Not an AVR ELF file"),
+        ];
+        for (bytes, err) in cases {
+            assert_eq!(parse_elf(bytes).unwrap_err(), err);
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod compiler;
+pub mod elf;
 pub mod sim;
\ No newline at end of file
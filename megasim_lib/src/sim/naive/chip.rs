@@ -1,20 +1,46 @@
-use crate::compiler::Op;
+use crate::compiler::{Op, decode, encode, from_ihex};
+use crate::elf::{Symbol, SymbolKind, parse_elf, symbolize};
+use crate::sim::naive::interrupts::{self, Interrupts};
 use crate::sim::naive::ops::{
     arithmetic_and_logic::{
-        op_and, op_andi, op_clr, op_com, op_cpi, op_dec, op_eor, op_inc, op_or, op_ori,
+        op_adc, op_add, op_adiw, op_and, op_andi, op_com, op_cp, op_cpc, op_cpi, op_dec, op_eor,
+        op_fmul, op_fmuls, op_fmulsu, op_inc, op_mul, op_muls, op_mulsu, op_neg, op_or, op_ori,
+        op_sbc, op_sbci, op_sbiw, op_sub, op_subi,
     },
     bit_and_bittest::{
-        op_cbi, op_clc, op_clt, op_lsl, op_rol, op_ror, op_sbi, op_sec, op_sei, op_set,
+        op_asr, op_bclr, op_bld, op_bset, op_bst, op_cbi, op_lsr, op_ror, op_sbi, op_swap,
     },
     branch::{
-        op_brcc, op_breq, op_brne, op_brtc, op_brts, op_cpse, op_rcall, op_ret, op_reti, op_rjmp,
-        op_sbis, op_sbrc, op_sbrs,
+        op_brbc, op_brbs, op_call, op_cpse, op_icall, op_ijmp, op_jmp, op_rcall, op_ret, op_reti,
+        op_rjmp, op_sbic, op_sbis, op_sbrc, op_sbrs,
     },
-    data_transfer::{op_in, op_ldi, op_mov, op_out, op_pop, op_push},
+    data_transfer::{
+        op_in, op_ld, op_ldd, op_ldi, op_lds, op_lpm, op_mov, op_movw, op_out, op_pop, op_push,
+        op_spm, op_st, op_std, op_sts,
+    },
+    mcu_control::{op_break, op_nop, op_sleep, op_wdr},
+};
+use crate::sim::naive::peripherals::{
+    self, Pin,
+    adc::{self, Adc},
+    analog::{self, AnalogInputs, AnalogPin, AnalogSource},
+    comparator,
+    external::{self, ExternalInterrupts},
+    spi::{self, Spi, SpiDevice},
+    timer0::{self, Timer0},
+    timer1::{self, Timer1},
+    timer2::{self, Timer2},
+    twi::{self, Twi, TwiDevice, TwiHost},
+    usart::{self, SerialPort, Usart},
 };
 
-use core::time;
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    rc::Rc,
+};
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Sreg {
@@ -28,36 +54,195 @@ pub struct Sreg {
     pub i: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepMode {
+    Idle,
+    AdcNoiseReduction,
+    PowerDown,
+    PowerSave,
+    Standby,
+    ExtendedStandby,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Running,
+    Sleeping(SleepMode),
+    // Halted by BREAK, `resume` continues after it
+    Break,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FirmwareFormat {
+    Ihex,
+    Bin,
+    Elf,
+}
+
+impl FirmwareFormat {
+    // Picks the format of a firmware file by its extension, ignoring case.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension().and_then(|e| e.to_str())?;
+
+        match ext.to_lowercase().as_str() {
+            "hex" | "ihex" => Some(FirmwareFormat::Ihex),
+            "bin" => Some(FirmwareFormat::Bin),
+            "elf" => Some(FirmwareFormat::Elf),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Chip {
     pub pc: u16,
     pub ram: [u8; 1120], // 0-31: R0-R31 | 32-95: I/O Reg | 96-1119: SRAM
     pub clock_freq: u64,
+    pub state: State,
+
+    // Clock cycles since power-on
+    pub cycles: u64,
+
+    pub flash: [u16; 8192],
+    // Decoded instructions by flash word address, with the words they were
+    // decoded from
+    decoded: Vec<Option<(u32, Rc<Op>)>>,
+    pub(crate) spm_buffer: [u16; 64],
+    pub eeprom: [u8; 512],
+
+    // Imported from ELF firmware
+    pub symbols: Vec<Symbol>,
+
+    // Time since the last watchdog reset, in ns
+    pub(crate) wdt_elapsed: u64,
+
+    // Timer0/Timer1 prescaler, in clock cycles
+    pub(crate) prescaler: u64,
+    pub timer0: Timer0,
+    pub timer1: Timer1,
+    pub timer2: Timer2,
+    pub usart: Usart,
+    pub spi: Spi,
+    pub twi: Twi,
+    pub adc: Adc,
+    pub(crate) analog: AnalogInputs,
+
+    pub(crate) interrupts: Interrupts,
+    pub(crate) external: ExternalInterrupts,
+}
 
-    // hack
-    // pub flash: [u16; 8192],
-    pub program: HashMap<u16, Op>,
-
-    // also hack
-    prev_int0: bool,
-    prev_int1: bool,
-    prev_int2: bool,
+impl Default for Chip {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chip {
     pub const IO_OFFSET: u16 = 32;
+    // Last flash word address
+    pub const FLASHEND: u16 = 0x1FFF;
 
     pub fn new() -> Self {
-        Chip {
+        let mut chip = Chip {
             pc: 0,
             ram: [0; 1120],
             clock_freq: 8_000_000,
+            state: State::Running,
+
+            cycles: 0,
+
+            flash: [0xFFFF; 8192],
+            decoded: vec![None; 8192],
+            spm_buffer: [0xFFFF; 64],
+            eeprom: [0xFF; 512],
 
-            program: HashMap::new(),
+            symbols: vec![],
 
-            prev_int0: false,
-            prev_int1: false,
-            prev_int2: false,
+            wdt_elapsed: 0,
+
+            prescaler: 0,
+            timer0: Timer0::default(),
+            timer1: Timer1::default(),
+            timer2: Timer2::default(),
+            usart: Usart::default(),
+            spi: Spi::default(),
+            twi: Twi::default(),
+            adc: Adc::default(),
+            analog: AnalogInputs::default(),
+
+            interrupts: Interrupts::default(),
+            external: ExternalInterrupts::default(),
+        };
+
+        usart::reset(&mut chip);
+        twi::reset(&mut chip);
+        chip
+    }
+
+    // Resets the CPU and I/O registers. Register file, SRAM and flash keep
+    // their contents, and the reset flags in MCUCSR are preserved.
+    pub fn reset(&mut self) {
+        let mcucsr = self.ram[0x54];
+        self.ram[Self::IO_OFFSET as usize..96].fill(0);
+        self.ram[0x54] = mcucsr;
+
+        self.pc = 0;
+        self.state = State::Running;
+        self.spm_buffer = [0xFFFF; 64];
+        self.wdt_elapsed = 0;
+        self.prescaler = 0;
+        self.timer0 = Timer0::default();
+        self.timer1 = Timer1::default();
+        self.timer2 = Timer2::default();
+        usart::reset(self);
+        spi::reset(self);
+        twi::reset(self);
+        adc::reset(self);
+
+        self.interrupts = Interrupts::default();
+        self.external = ExternalInterrupts::default();
+    }
+
+    // Connects a host serial terminal to the USART, replacing any earlier
+    // connection.
+    pub fn serial_port(&mut self) -> SerialPort {
+        usart::connect(self)
+    }
+
+    // Puts a device on the SPI bus, selected by driving `select` low. Keep a
+    // clone of the Rc to inspect the device.
+    pub fn attach_spi_device(&mut self, select: Pin, device: Rc<RefCell<dyn SpiDevice>>) {
+        spi::attach(self, select, device);
+    }
+
+    // Clocks a byte in from an external SPI master while the chip is an SPI
+    // slave with SS (PB4) low. Returns the byte shifted out, or None if the
+    // chip is not a selected slave.
+    pub fn spi_slave_transfer(&mut self, mosi: u8) -> Option<u8> {
+        spi::slave_transfer(self, mosi)
+    }
+
+    // Puts a slave device on the TWI bus. Keep a clone of the Rc to inspect
+    // the device.
+    pub fn attach_twi_device(&mut self, device: Rc<RefCell<dyn TwiDevice>>) {
+        twi::attach(self, device);
+    }
+
+    // Connects a host master to the TWI bus, which addresses the chip as a
+    // slave. Replaces any earlier connection.
+    pub fn twi_host(&mut self) -> TwiHost {
+        twi::connect(self)
+    }
+
+    // Drives an analog pin from the host, for the ADC and the analog
+    // comparator.
+    pub fn set_analog_input(&mut self, pin: AnalogPin, source: AnalogSource) {
+        analog::set_source(self, pin, source);
+    }
+
+    pub fn resume(&mut self) {
+        if self.state == State::Break {
+            self.state = State::Running;
         }
     }
 
@@ -123,16 +308,95 @@ impl Chip {
         if x >= 0 {
             self.sp_set(self.sp_get().wrapping_add(x as u16));
         } else {
-            self.sp_set(self.sp_get().wrapping_sub(x.abs() as u16));
+            self.sp_set(self.sp_get().wrapping_sub(x.unsigned_abs()));
         }
     }
 
+    // Pushes a byte onto the stack. Goes through the data space, so a stack
+    // pointer left outside SRAM (like its reset value 0) can't crash the host.
+    pub fn push_byte(&mut self, val: u8) {
+        self.data_write(self.sp_get() as usize, val);
+        self.sp_add(-1);
+    }
+
+    // Pops a byte off the stack, the reverse of `push_byte`.
+    pub fn pop_byte(&mut self) -> u8 {
+        self.sp_add(1);
+        self.data_read(self.sp_get() as usize)
+    }
+
     pub fn ram_set_byte(&mut self, idx: usize, x: u8) {
         self.ram[idx] = x;
     }
 
-    pub fn get_instr_size(&self, _addr: u16) -> u16 {
-        1
+    // Decodes the instruction at a flash word address. Returns None for
+    // words that are not a valid opcode, like erased (0xFFFF) flash.
+    pub fn fetch(&self, addr: u16) -> Option<Op> {
+        let len = self.flash.len();
+        let addr = addr as usize % len;
+
+        decode(self.flash[addr], self.flash[(addr + 1) % len])
+    }
+
+    // `fetch`, reusing the instruction decoded at `addr` as long as its flash
+    // words are unchanged.
+    fn fetch_cached(&mut self, addr: u16) -> Option<Rc<Op>> {
+        let len = self.flash.len();
+        let addr = addr as usize % len;
+        let (word, next) = (self.flash[addr], self.flash[(addr + 1) % len]);
+        let words = ((word as u32) << 16) | next as u32;
+
+        if let Some((cached, op)) = &self.decoded[addr]
+            && *cached == words
+        {
+            return Some(op.clone());
+        }
+
+        let op = Rc::new(decode(word, next)?);
+        self.decoded[addr] = Some((words, op.clone()));
+        Some(op)
+    }
+
+    // Reads a data space byte on behalf of an instruction.
+    pub fn data_read(&mut self, addr: usize) -> u8 {
+        if let Some(val) = timer1::io_read(self, addr)
+            .or_else(|| usart::io_read(self, addr))
+            .or_else(|| spi::io_read(self, addr))
+            .or_else(|| adc::io_read(self, addr))
+        {
+            return val;
+        }
+
+        // Past the end of SRAM the data space is unmapped, and reads as 0
+        self.ram.get(addr).copied().unwrap_or(0)
+    }
+
+    // Writes a data space byte on behalf of an instruction, with the side
+    // effects of writing to I/O registers.
+    pub fn data_write(&mut self, addr: usize, val: u8) {
+        if peripherals::io_write(self, addr, val)
+            || timer0::io_write(self, addr, val)
+            || timer1::io_write(self, addr, val)
+            || timer2::io_write(self, addr, val)
+            || usart::io_write(self, addr, val)
+            || spi::io_write(self, addr, val)
+            || twi::io_write(self, addr, val)
+            || adc::io_write(self, addr, val)
+            || comparator::io_write(self, addr, val)
+            || external::io_write(self, addr, val)
+            || interrupts::io_write(self, addr, val)
+        {
+            return;
+        }
+
+        // Writes to unmapped data space are ignored
+        if let Some(byte) = self.ram.get_mut(addr) {
+            *byte = val;
+        }
+    }
+
+    pub fn get_instr_size(&self, addr: u16) -> u16 {
+        self.fetch(addr).map_or(1, |op| op.width() as u16)
     }
 
     pub fn apply_dseg(&mut self, dseg: &HashMap<u64, u64>) -> Result<(), &str> {
@@ -147,187 +411,533 @@ impl Chip {
         Ok(())
     }
 
-    pub fn apply_cseg(&mut self, cseg: &HashMap<u64, Op>) -> Result<(), &str> {
+    pub fn apply_cseg(&mut self, cseg: &HashMap<u64, Op>) -> Result<(), String> {
         for (addr, op) in cseg {
-            self.program.insert(*addr as u16, op.clone());
+            if *addr + op.width() > self.flash.len() as u64 {
+                return Err("CSEG overflow".into());
+            }
+
+            let words = encode(op).map_err(|e| format!("{} at {}", e, addr))?;
+            for (i, word) in words.into_iter().enumerate() {
+                self.flash[*addr as usize + i] = word;
+            }
         }
 
         Ok(())
     }
 
-    fn _tick_interupts(&mut self) {
-        // GCIR = 91
-        // GCIR @ 6 => int0
-        // GCIR @ 7 => int1
-        // GCIR @ 5 => int2
-        // ---
-        // MCUCR = 85
-        // ISC01(1), ISC00(0) | ISC10(2), ISC11(3)
-        // 0, 0 => low
-        // 0, 1 => delta
-        // 1, 0 => falling edge
-        // 1, 1 => rising edge
-        // ISC2
-        // 0 => falling edge
-        // 1 => rising edge
-        // ---
-        // int0_signal = ??? @ ???
-        // int1_signal = ??? @ ???
-        // int2_signal = ??? @ ???
-        // ---
-        // PIND = 48
-        // PINB = 54
-        if !self.sreg_get().i {
-            return;
+    // Erases the flash and programs a firmware image, keyed by byte address.
+    // Flash words are little-endian.
+    pub fn load_flash_image(&mut self, image: &BTreeMap<u64, u8>) -> Result<(), String> {
+        let flash_bytes = (Self::FLASHEND as u64 + 1) * 2;
+        if let Some((addr, _)) = image.last_key_value()
+            && *addr >= flash_bytes
+        {
+            return Err(format!(
+                "Firmware too large: byte 0x{:X} is past FLASHEND (0x{:X})",
+                addr,
+                flash_bytes - 1
+            ));
+        }
+
+        self.flash = [0xFFFF; 8192];
+        for (addr, byte) in image {
+            let word = &mut self.flash[(*addr / 2) as usize];
+            *word = match addr % 2 {
+                0 => (*word & 0xFF00) | *byte as u16,
+                _ => (*word & 0x00FF) | (*byte as u16) << 8,
+            };
         }
 
-        pub fn set_int(c: &mut Chip, new_pc: u16) {
-            c.ram[c.sp_get() as usize] = (c.pc & 0xFF) as u8;
-            c.sp_add(-1);
-            c.ram[c.sp_get() as usize] = (c.pc >> 8) as u8;
-            c.sp_add(-1);
+        Ok(())
+    }
 
-            let mut sreg = c.sreg_get();
-            sreg.i = false;
-            c.sreg_set(&sreg);
+    pub fn load_ihex(&mut self, text: &str) -> Result<(), String> {
+        self.load_flash_image(&from_ihex(text)?)
+    }
 
-            c.pc = new_pc;
+    // Raw binary images start at flash address 0.
+    pub fn load_bin(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let image = bytes
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (i as u64, *b))
+            .collect();
+        self.load_flash_image(&image)
+    }
+
+    // Loads avr-gcc ELF output: flash, EEPROM, initialised SRAM and symbols.
+    pub fn load_elf(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let elf = parse_elf(bytes)?;
+        self.load_flash_image(&elf.flash)?;
+
+        for (addr, byte) in &elf.sram {
+            *self
+                .ram
+                .get_mut(*addr as usize)
+                .ok_or("ELF .data/.bss past RAMEND")? = *byte;
         }
 
-        let pind = self.ram[48];
-        let pinb = self.ram[54];
-        let mcucr = self.ram[85];
-        let gcir = self.ram[91];
-
-        let int0_en = ((gcir >> 6) & 1) != 0;
-        let int1_en = ((gcir >> 7) & 1) != 0;
-        let int2_en = ((gcir >> 5) & 1) != 0;
-
-        let isc0 = ((mcucr >> 0) & 0b11) as u8;
-        let isc1 = ((mcucr >> 2) & 0b11) as u8;
-        let isc2 = ((mcucr >> 6) & 0b01) as u8;
-
-        let int0 = ((pind >> 2) & 1) != 0;
-        let int1 = ((pind >> 3) & 1) != 0;
-        let int2 = ((pinb >> 2) & 1) != 0;
-
-        let int0_active = int0_en
-            && match isc0 {
-                0b00 => int0 == false,
-                0b01 => int0 != self.prev_int0,
-                0b10 => self.prev_int0 && !int0,
-                0b11 => !self.prev_int0 && int0,
-                _ => false,
-            };
-        let int1_active = int1_en
-            && match isc1 {
-                0b00 => int1 == false,
-                0b01 => int1 != self.prev_int1,
-                0b10 => self.prev_int1 && !int1,
-                0b11 => !self.prev_int1 && int1,
-                _ => false,
-            };
-        let int2_active = int2_en
-            && match isc2 {
-                0 => self.prev_int2 && !int2,
-                1 => !self.prev_int2 && int2,
-                _ => false,
-            };
+        self.eeprom = [0xFF; 512];
+        for (addr, byte) in &elf.eeprom {
+            *self
+                .eeprom
+                .get_mut(*addr as usize)
+                .ok_or("ELF .eeprom past E2END")? = *byte;
+        }
+
+        self.symbols = elf.symbols;
+        Ok(())
+    }
 
-        if int0_active {
-            set_int(self, 2);
-        } else if int1_active {
-            set_int(self, 4);
-        } else if int2_active {
-            set_int(self, 6);
+    // Names a flash word address after the function containing it, e.g.
+    // `main+0x12` (byte offset, as in avr-objdump).
+    pub fn symbolize_pc(&self, pc: u16) -> Option<String> {
+        symbolize(&self.symbols, SymbolKind::Function, pc as u32 * 2)
+    }
+
+    // Loads a `.hex`, `.bin` or `.elf` firmware file, picked by extension.
+    pub fn load_firmware(&mut self, path: &Path) -> Result<(), String> {
+        match FirmwareFormat::from_path(path) {
+            Some(FirmwareFormat::Ihex) => {
+                let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+                self.load_ihex(&text)
+            }
+            Some(FirmwareFormat::Bin) => {
+                let bytes = fs::read(path).map_err(|e| e.to_string())?;
+                self.load_bin(&bytes)
+            }
+            Some(FirmwareFormat::Elf) => {
+                let bytes = fs::read(path).map_err(|e| e.to_string())?;
+                self.load_elf(&bytes)
+            }
+            None => Err(format!("Unknown firmware format: {}", path.display())),
         }
+    }
 
-        self.prev_int0 = int0;
-        self.prev_int1 = int1;
-        self.prev_int2 = int2;
+    // Samples interrupt sources and enters the highest priority pending
+    // interrupt. Returns the cycles the entry took.
+    fn _tick_interupts(&mut self) -> Option<u64> {
+        external::tick(self);
+        interrupts::service(self)
     }
 
-    fn _tick_timers(&mut self, time_delta: u64) {}
+    fn _tick_peripherals(&mut self, cycles: u64, time_delta: u64) {
+        analog::tick(self, time_delta);
+        // The comparator is analog and runs in every mode. Timer1 and the ADC
+        // see its output in the same step.
+        comparator::tick(self);
+
+        // Timer0, Timer1, the USART, SPI and TWI run from the I/O clock, which only
+        // keeps running in idle sleep.
+        if matches!(
+            self.state,
+            State::Running | State::Sleeping(SleepMode::Idle)
+        ) {
+            timer0::tick(self, cycles);
+            timer1::tick(self, cycles);
+            self.prescaler = (self.prescaler + cycles) % 1024;
+            usart::tick(self, cycles);
+            spi::tick(self, cycles);
+            twi::tick(self, cycles);
+        }
 
-    pub fn step(&mut self, time_delta: Option<u64>) -> bool {
-        self._tick_interupts();
-        self._tick_timers(time_delta.unwrap_or(1_000_000_000 / self.clock_freq));
+        // The ADC has its own clock, and is ticked after the timers so it sees
+        // their flags as auto trigger sources
+        adc::tick(self, cycles);
 
-        let op = match self.program.get(&self.pc) {
-            Some(x) => x,
-            None => {
-                return false;
+        // Timer2 keeps its own prescaler and clock domain
+        timer2::tick(self, cycles, time_delta);
+        twi::tick_devices(self, time_delta);
+    }
+
+    fn _tick_watchdog(&mut self, time_delta: u64) {
+        // WDTCR = 65
+        // WDE @ 3, WDP2..0 @ 2..0
+        // Timeout = 16K << WDP cycles of the 1 MHz watchdog oscillator
+        let wdtcr = self.ram[65];
+        if (wdtcr >> 3) & 1 == 0 {
+            self.wdt_elapsed = 0;
+            return;
+        }
+
+        let timeout = (16_384u64 << (wdtcr & 0b111)) * 1_000;
+        self.wdt_elapsed += time_delta;
+
+        if self.wdt_elapsed >= timeout {
+            self.reset();
+            // MCUCSR @ 3 => WDRF
+            self.ram[0x54] |= 1 << 3;
+        }
+    }
+
+    // Enters a pending interrupt, or executes one instruction, or idles for
+    // one clock cycle while sleeping, and returns the clock cycles taken.
+    // Returns None once the chip is halted by BREAK or runs into erased
+    // flash or an invalid opcode.
+    //
+    // `time_delta` overrides the simulated time the step takes, in ns. By
+    // default it is the change in `elapsed_ns`, so the sum of the steps does
+    // not drift from it by rounding.
+    pub fn step(&mut self, time_delta: Option<u64>) -> Option<u64> {
+        if self.state == State::Break {
+            return None;
+        }
+
+        let interrupt = self._tick_interupts();
+        let cycles = match self.state {
+            _ if let Some(cycles) = interrupt => cycles,
+            State::Sleeping(_) => 1,
+            _ => {
+                // Erased flash and invalid opcodes halt the chip
+                let op = self.fetch_cached(self.pc)?;
+                self.execute(&op)
             }
         };
 
-        let (_cycles,) = match op {
+        let elapsed = self.elapsed_ns();
+        self.cycles += cycles;
+        interrupts::tick(self, cycles);
+
+        let time_delta = time_delta.unwrap_or_else(|| self.elapsed_ns() - elapsed);
+        self._tick_watchdog(time_delta);
+        self._tick_peripherals(cycles, time_delta);
+
+        Some(cycles)
+    }
+
+    // Steps until at least `cycles` clock cycles have passed or the chip
+    // halts. Returns the cycles actually run.
+    pub fn run(&mut self, cycles: u64) -> u64 {
+        let mut ran = 0;
+
+        while ran < cycles {
+            match self.step(None) {
+                Some(c) => ran += c,
+                None => break,
+            }
+        }
+
+        ran
+    }
+
+    // Simulated time since power-on, in ns.
+    pub fn elapsed_ns(&self) -> u64 {
+        (self.cycles as u128 * 1_000_000_000 / self.clock_freq as u128) as u64
+    }
+
+    fn execute(&mut self, op: &Op) -> u64 {
+        let (cycles,) = match op {
             Op::Nullary(mnemonic) => match mnemonic.as_str() {
                 // Branch / Control
+                "icall" => op_icall(self),
+                "ijmp" => op_ijmp(self),
                 "ret" => op_ret(self),
                 "reti" => op_reti(self),
-                // Bit / Bittest
-                "clc" => op_clc(self),
-                "clt" => op_clt(self),
-                "sec" => op_sec(self),
-                "sei" => op_sei(self),
-                "set" => op_set(self),
+                // Data Transfer
+                "lpm" => op_lpm(self, 0, 30),
+                "spm" => op_spm(self),
+                // MCU Control
+                "break" => op_break(self),
+                "nop" => op_nop(self),
+                "sleep" => op_sleep(self),
+                "wdr" => op_wdr(self),
                 _ => panic!("Unknown instruction: {}", mnemonic),
             },
 
             Op::Unary(mnemonic, arg1) => match mnemonic.as_str() {
                 // Arithmetic and Logic
-                "clr" => op_clr(self, *arg1 as u8),
                 "com" => op_com(self, *arg1 as u8),
                 "dec" => op_dec(self, *arg1 as u8),
                 "inc" => op_inc(self, *arg1 as u8),
+                "neg" => op_neg(self, *arg1 as u8),
                 // Branch
+                "call" => op_call(self, *arg1 as u16),
+                "jmp" => op_jmp(self, *arg1 as u16),
                 "rjmp" => op_rjmp(self, *arg1 as i16),
                 "rcall" => op_rcall(self, *arg1 as i16),
-                // Conditional Branches
-                "brcc" => op_brcc(self, *arg1 as i8),
-                "breq" => op_breq(self, *arg1 as i8),
-                "brne" => op_brne(self, *arg1 as i8),
-                "brtc" => op_brtc(self, *arg1 as i8),
-                "brts" => op_brts(self, *arg1 as i8),
                 // Data Transfer
                 "pop" => op_pop(self, *arg1 as u8),
                 "push" => op_push(self, *arg1 as u8),
                 // Bit / Bittest
-                "lsl" => op_lsl(self, *arg1 as u8),
-                "rol" => op_rol(self, *arg1 as u8),
+                "asr" => op_asr(self, *arg1 as u8),
+                "bclr" => op_bclr(self, *arg1 as u8),
+                "bset" => op_bset(self, *arg1 as u8),
+                "lsr" => op_lsr(self, *arg1 as u8),
                 "ror" => op_ror(self, *arg1 as u8),
+                "swap" => op_swap(self, *arg1 as u8),
                 _ => panic!("Unknown instruction: {}", mnemonic),
             },
 
             Op::Binary(mnemonic, arg1, arg2) => match mnemonic.as_str() {
                 // Arithmetic and Logic
+                "adc" => op_adc(self, *arg1 as u8, *arg2 as u8),
+                "add" => op_add(self, *arg1 as u8, *arg2 as u8),
+                "adiw" => op_adiw(self, *arg1 as u8, *arg2 as u8),
                 "and" => op_and(self, *arg1 as u8, *arg2 as u8),
                 "andi" => op_andi(self, *arg1 as u8, *arg2 as u8),
+                "cp" => op_cp(self, *arg1 as u8, *arg2 as u8),
+                "cpc" => op_cpc(self, *arg1 as u8, *arg2 as u8),
                 "cpi" => op_cpi(self, *arg1 as u8, *arg2 as u8),
                 "eor" => op_eor(self, *arg1 as u8, *arg2 as u8),
+                "fmul" => op_fmul(self, *arg1 as u8, *arg2 as u8),
+                "fmuls" => op_fmuls(self, *arg1 as u8, *arg2 as u8),
+                "fmulsu" => op_fmulsu(self, *arg1 as u8, *arg2 as u8),
+                "mul" => op_mul(self, *arg1 as u8, *arg2 as u8),
+                "muls" => op_muls(self, *arg1 as u8, *arg2 as u8),
+                "mulsu" => op_mulsu(self, *arg1 as u8, *arg2 as u8),
                 "or" => op_or(self, *arg1 as u8, *arg2 as u8),
                 "ori" => op_ori(self, *arg1 as u8, *arg2 as u8),
+                "sbc" => op_sbc(self, *arg1 as u8, *arg2 as u8),
+                "sbci" => op_sbci(self, *arg1 as u8, *arg2 as u8),
+                "sbiw" => op_sbiw(self, *arg1 as u8, *arg2 as u8),
+                "sub" => op_sub(self, *arg1 as u8, *arg2 as u8),
+                "subi" => op_subi(self, *arg1 as u8, *arg2 as u8),
                 // Branch
+                "brbc" => op_brbc(self, *arg1 as u8, *arg2 as i8),
+                "brbs" => op_brbs(self, *arg1 as u8, *arg2 as i8),
                 "cpse" => op_cpse(self, *arg1 as u8, *arg2 as u8),
+                "sbic" => op_sbic(self, *arg1 as u8, *arg2 as u8),
                 "sbis" => op_sbis(self, *arg1 as u8, *arg2 as u8),
                 "sbrc" => op_sbrc(self, *arg1 as u8, *arg2 as u8),
                 "sbrs" => op_sbrs(self, *arg1 as u8, *arg2 as u8),
                 // Data Transfer
                 "in" => op_in(self, *arg1 as u8, *arg2 as u8),
+                "ld" => op_ld(self, *arg1 as u8, *arg2),
                 "ldi" => op_ldi(self, *arg1 as u8, *arg2 as u8),
+                "lds" => op_lds(self, *arg1 as u8, *arg2 as u16),
+                "lpm" => op_lpm(self, *arg1 as u8, *arg2),
                 "mov" => op_mov(self, *arg1 as u8, *arg2 as u8),
+                "movw" => op_movw(self, *arg1 as u8, *arg2 as u8),
                 "out" => op_out(self, *arg1 as u8, *arg2 as u8),
+                "st" => op_st(self, *arg1, *arg2 as u8),
+                "sts" => op_sts(self, *arg1 as u16, *arg2 as u8),
                 // Bit / Bittest
+                "bld" => op_bld(self, *arg1 as u8, *arg2 as u8),
+                "bst" => op_bst(self, *arg1 as u8, *arg2 as u8),
                 "cbi" => op_cbi(self, *arg1 as u8, *arg2 as u8),
                 "sbi" => op_sbi(self, *arg1 as u8, *arg2 as u8),
                 _ => panic!("Unknown instruction: {}", mnemonic),
             },
 
-            Op::Ternary(_, _, _, _) => {
-                panic!("Ternary not implemented");
-            }
+            Op::Ternary(mnemonic, arg1, arg2, arg3) => match mnemonic.as_str() {
+                // Data Transfer
+                "ldd" => op_ldd(self, *arg1 as u8, *arg2, *arg3 as u8),
+                "std" => op_std(self, *arg1, *arg2 as u8, *arg3 as u8),
+                _ => panic!("Unknown instruction: {}", mnemonic),
+            },
         };
 
-        return true;
+        cycles as u64
+    }
+}
+
+#[cfg(test)]
+impl Chip {
+    // Assembles `src` into a new chip.
+    pub(crate) fn with_program(src: &str) -> Self {
+        let (cseg, dseg, _) = crate::compiler::compile(src).unwrap();
+        let mut chip = Chip::new();
+        chip.apply_cseg(&cseg).unwrap();
+        chip.apply_dseg(&dseg).unwrap();
+        chip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn break_and_resume() {
+        let mut c = Chip::with_program("ldi r16, 1\nbreak\nldi r16, 2\n");
+        c.run(10);
+        assert_eq!((c.state, c.pc, c.ram[16]), (State::Break, 2, 1));
+        assert_eq!(c.step(None), None);
+
+        c.resume();
+        assert_eq!(c.step(None), Some(1));
+        assert_eq!(c.ram[16], 2);
+    }
+
+    #[test]
+    fn int0_wakes_from_idle_sleep() {
+        let mut c = Chip::with_program(
+            "
+            rjmp main
+        .org 2
+            rjmp isr
+        .org 0x2a
+        main:
+            ldi r16, high(ramend)
+            out sph, r16
+            ldi r16, low(ramend)
+            out spl, r16
+            ldi r16, 0x40       ; INT0
+            out gicr, r16
+            ldi r16, 0x42       ; SE, falling edge
+            out mcucr, r16
+            sei
+            sleep
+            ldi r17, 1
+        end:
+            rjmp end
+        isr:
+            ldi r18, 1
+            reti
+            ",
+        );
+        // PIND high
+        c.ram[0x30] = 0xFF;
+        c.run(100);
+        assert_eq!(c.state, State::Sleeping(SleepMode::Idle));
+
+        c.ram[0x30] = 0x00;
+        c.run(20);
+        assert_eq!((c.state, c.ram[17], c.ram[18]), (State::Running, 1, 1));
+    }
+
+    const WATCHDOG_LOOP: &str = "ldi r16, 0x08\nout wdtcr, r16\nloop:\nwdr\nrjmp loop\n";
+
+    #[test]
+    fn watchdog_resets_the_chip() {
+        // 16K watchdog cycles at 1 MHz, without WDR
+        let mut c = Chip::with_program(&WATCHDOG_LOOP.replace("wdr\n", ""));
+        c.run(8 * 16_384 - 8);
+        assert_eq!(c.ram[0x54] & 1 << 3, 0);
+        c.run(16);
+        assert_eq!(c.ram[0x54] & 1 << 3, 1 << 3);
+    }
+
+    #[test]
+    fn wdr_keeps_the_watchdog_from_firing() {
+        let mut c = Chip::with_program(WATCHDOG_LOOP);
+        c.run(8 * 16_384 * 2);
+        assert_eq!(c.ram[0x54] & 1 << 3, 0);
+    }
+
+    #[test]
+    fn jump_tables() {
+        let mut c = Chip::with_program(
+            "
+            ldi r16, high(ramend)
+            out sph, r16
+            ldi r16, low(ramend)
+            out spl, r16
+
+            ; Call entry 2 of a table of RJMPs
+            ldi zl, low(jumps)
+            ldi zh, high(jumps)
+            ldi r16, 2
+            add zl, r16
+            clr r16
+            adc zh, r16
+            icall
+
+            ; Jump through an address stored in flash
+            ldi zl, low(targets << 1)
+            ldi zh, high(targets << 1)
+            lpm r0, z+
+            lpm r1, z
+            movw zl, r0
+            ijmp
+        jumps:
+            rjmp entry0
+            rjmp entry1
+            rjmp entry2
+        entry0:
+            ldi r17, 10
+            ret
+        entry1:
+            ldi r17, 11
+            ret
+        entry2:
+            ldi r17, 12
+            ret
+        targets:
+            .dw target
+        target:
+            ldi r18, 99
+            break
+            ",
+        );
+        c.run(200);
+        assert_eq!(c.state, State::Break);
+        assert_eq!((c.ram[17], c.ram[18]), (12, 99));
+    }
+
+    #[test]
+    fn invalid_opcode_halts() {
+        let mut c = Chip::with_program("ldi r16, 1\n");
+        c.flash[1] = 0x0001;
+        assert_eq!(c.step(None), Some(1));
+        assert_eq!(c.step(None), None);
+        assert_eq!((c.pc, c.ram[16]), (1, 1));
+    }
+
+    #[test]
+    fn flash_rewrite_is_decoded_again() {
+        let mut c = Chip::with_program("ldi r16, 1\nrjmp 0\n");
+        c.run(3);
+        assert_eq!(c.ram[16], 1);
+
+        // LDI R16, 2
+        c.flash[0] = 0xE002;
+        c.run(3);
+        assert_eq!(c.ram[16], 2);
+    }
+
+    #[test]
+    fn firmware_format_from_extension() {
+        let format = |p: &str| FirmwareFormat::from_path(Path::new(p));
+        assert_eq!(format("main.hex"), Some(FirmwareFormat::Ihex));
+        assert_eq!(format("MAIN.IHEX"), Some(FirmwareFormat::Ihex));
+        assert_eq!(format("build/main.Bin"), Some(FirmwareFormat::Bin));
+        assert_eq!(format("main.elf"), Some(FirmwareFormat::Elf));
+        assert_eq!(format("main.asm"), None);
+        assert_eq!(format("hex"), None);
+    }
+
+    #[test]
+    fn load_ihex_fills_flash() {
+        let mut c = Chip::new();
+        c.load_ihex(":0400000001E0FECF4E\n:00000001FF\n").unwrap();
+        assert_eq!(c.flash[..3], [0xE001, 0xCFFE, 0xFFFF]);
+        c.run(3);
+        assert_eq!(c.ram[16], 1);
+    }
+
+    #[test]
+    fn load_bin_pads_odd_bytes() {
+        let mut c = Chip::new();
+        c.load_bin(&[0x01, 0xE0, 0x12]).unwrap();
+        assert_eq!(c.flash[..3], [0xE001, 0xFF12, 0xFFFF]);
+
+        let err = c.load_bin(&[0; 16385]).unwrap_err();
+        assert_eq!(
+            err,
+            "Firmware too large: byte 0x4000 is past FLASHEND (0x3FFF)"
+        );
+    }
+
+    #[test]
+    fn delay_loop_cycles() {
+        // 1 + 3 per iteration, less 1 when BRNE falls through
+        let mut c = Chip::with_program("ldi r16, 10\nloop:\ndec r16\nbrne loop\nend:\nrjmp end\n");
+        assert_eq!(c.run(30), 30);
+        assert_eq!((c.pc, c.cycles), (3, 30));
+        assert_eq!(c.elapsed_ns(), 3_750);
+    }
+
+    #[test]
+    fn time_does_not_drift_at_odd_clock_frequencies() {
+        // 333.3 ns per cycle: the watchdog still fires after exactly 16.384 ms
+        let mut c = Chip::with_program(&WATCHDOG_LOOP.replace("wdr\n", ""));
+        c.clock_freq = 3_000_000;
+        c.run(3 * 16_384 - 8);
+        assert_eq!(c.ram[0x54] & 1 << 3, 0);
+        c.run(16);
+        assert_eq!(c.ram[0x54] & 1 << 3, 1 << 3);
+        assert_eq!(c.elapsed_ns(), c.cycles * 1_000 / 3);
     }
 }
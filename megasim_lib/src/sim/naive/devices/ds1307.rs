@@ -0,0 +1,258 @@
+use crate::sim::naive::peripherals::twi::TwiDevice;
+
+//
+// DS1307 real-time clock
+//
+// Registers 0-6 hold the time and date in BCD, register 7 is the control
+// register and 8-63 are battery backed RAM. The first byte of a write sets
+// the register pointer, which wraps around after the last RAM byte. The clock
+// runs from simulated time while CH (bit 7 of the seconds register) is clear.
+//
+
+pub const REGISTERS: usize = 64;
+
+const ADDRESS: u8 = 0x68;
+
+const SECONDS: usize = 0;
+const MINUTES: usize = 1;
+const HOURS: usize = 2;
+const DAY: usize = 3;
+const DATE: usize = 4;
+const MONTH: usize = 5;
+const YEAR: usize = 6;
+
+// Seconds: CH | 10 seconds | seconds
+const CH: u8 = 7;
+// Hours: - | 12/24 | PM / 20 hours | 10 hours | hours
+const MODE_12H: u8 = 6;
+const PM: u8 = 5;
+
+const NS_PER_SECOND: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone)]
+pub struct Ds1307 {
+    pub registers: [u8; REGISTERS],
+    pointer: u8,
+    // The next byte written sets the register pointer
+    select: bool,
+    // Time towards the next second, in ns
+    elapsed: u64,
+}
+
+impl Default for Ds1307 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn from_bcd(val: u8) -> u8 {
+    (val >> 4) * 10 + (val & 0x0F)
+}
+
+fn to_bcd(val: u8) -> u8 {
+    ((val / 10) << 4) | (val % 10)
+}
+
+fn days_in_month(month: u8, year: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Ds1307 {
+    // A running clock set to Saturday 2000-01-01 00:00:00, 24 hour mode.
+    pub fn new() -> Self {
+        let mut registers = [0; REGISTERS];
+        registers[DAY] = 0x01;
+        registers[DATE] = 0x01;
+        registers[MONTH] = 0x01;
+
+        Ds1307 {
+            registers,
+            pointer: 0,
+            select: false,
+            elapsed: 0,
+        }
+    }
+
+    fn next_day(&mut self) {
+        let r = &mut self.registers;
+        r[DAY] = r[DAY] % 7 + 1;
+
+        let year = from_bcd(r[YEAR]);
+        let month = from_bcd(r[MONTH] & 0x1F);
+        let date = from_bcd(r[DATE] & 0x3F) + 1;
+        if date <= days_in_month(month, year) {
+            r[DATE] = to_bcd(date);
+            return;
+        }
+
+        r[DATE] = 0x01;
+        if month < 12 {
+            r[MONTH] = to_bcd(month + 1);
+            return;
+        }
+
+        r[MONTH] = 0x01;
+        r[YEAR] = to_bcd((year + 1) % 100);
+    }
+
+    fn next_second(&mut self) {
+        let r = &mut self.registers;
+
+        let seconds = from_bcd(r[SECONDS] & 0x7F) + 1;
+        if seconds < 60 {
+            r[SECONDS] = to_bcd(seconds);
+            return;
+        }
+        r[SECONDS] = 0;
+
+        let minutes = from_bcd(r[MINUTES] & 0x7F) + 1;
+        if minutes < 60 {
+            r[MINUTES] = to_bcd(minutes);
+            return;
+        }
+        r[MINUTES] = 0;
+
+        let hours = r[HOURS];
+        if (hours >> MODE_12H) & 1 != 0 {
+            // 12 is followed by 1, and 11 by 12 with AM/PM toggled
+            let hour = from_bcd(hours & 0x1F);
+            let next = if hour == 12 { 1 } else { hour + 1 };
+            let mut pm = (hours >> PM) & 1 != 0;
+            if next == 12 {
+                pm = !pm;
+            }
+            r[HOURS] = 1 << MODE_12H | (pm as u8) << PM | to_bcd(next);
+
+            if next == 12 && !pm {
+                self.next_day();
+            }
+        } else {
+            let hour = from_bcd(hours & 0x3F) + 1;
+            if hour < 24 {
+                r[HOURS] = to_bcd(hour);
+            } else {
+                r[HOURS] = 0;
+                self.next_day();
+            }
+        }
+    }
+}
+
+impl TwiDevice for Ds1307 {
+    fn address(&self) -> u8 {
+        ADDRESS
+    }
+
+    fn start(&mut self, read: bool) -> bool {
+        self.select = !read;
+        true
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        if self.select {
+            self.select = false;
+            self.pointer = byte % REGISTERS as u8;
+            return true;
+        }
+
+        // Writing the seconds resets the countdown to the next second
+        if self.pointer as usize == SECONDS {
+            self.elapsed = 0;
+        }
+        self.registers[self.pointer as usize] = byte;
+        self.pointer = (self.pointer + 1) % REGISTERS as u8;
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let byte = self.registers[self.pointer as usize];
+        self.pointer = (self.pointer + 1) % REGISTERS as u8;
+        byte
+    }
+
+    fn tick(&mut self, ns: u64) {
+        if (self.registers[SECONDS] >> CH) & 1 != 0 {
+            return;
+        }
+
+        self.elapsed += ns;
+        while self.elapsed >= NS_PER_SECOND {
+            self.elapsed -= NS_PER_SECOND;
+            self.next_second();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(rtc: &mut Ds1307, registers: &[u8]) {
+        rtc.start(false);
+        rtc.write(0);
+        for byte in registers {
+            rtc.write(*byte);
+        }
+    }
+
+    fn time(rtc: &mut Ds1307) -> [u8; 7] {
+        rtc.start(false);
+        rtc.write(0);
+        rtc.start(true);
+        [0; 7].map(|_| rtc.read())
+    }
+
+    #[test]
+    fn rolls_over_into_a_leap_day() {
+        // Wednesday 2024-02-28 23:59:58
+        let mut rtc = Ds1307::new();
+        set(&mut rtc, &[0x58, 0x59, 0x23, 0x04, 0x28, 0x02, 0x24]);
+        rtc.tick(2 * NS_PER_SECOND);
+        assert_eq!(time(&mut rtc), [0x00, 0x00, 0x00, 0x05, 0x29, 0x02, 0x24]);
+
+        // Not in 2023
+        set(&mut rtc, &[0x59, 0x59, 0x23, 0x04, 0x28, 0x02, 0x23]);
+        rtc.tick(NS_PER_SECOND);
+        assert_eq!(time(&mut rtc)[4..], [0x01, 0x03, 0x23]);
+    }
+
+    #[test]
+    fn twelve_hour_mode() {
+        // 11:59:59 PM
+        let mut rtc = Ds1307::new();
+        set(&mut rtc, &[0x59, 0x59, 0x71]);
+        rtc.tick(NS_PER_SECOND);
+        assert_eq!(time(&mut rtc)[2..5], [0x52, 0x02, 0x02]);
+    }
+
+    #[test]
+    fn clock_halt() {
+        let mut rtc = Ds1307::new();
+        set(&mut rtc, &[1 << CH]);
+        rtc.tick(5 * NS_PER_SECOND);
+        assert_eq!(time(&mut rtc)[0], 1 << CH);
+
+        // Writing the seconds restarts the second
+        set(&mut rtc, &[0x00]);
+        rtc.tick(NS_PER_SECOND / 2);
+        set(&mut rtc, &[0x10]);
+        rtc.tick(NS_PER_SECOND / 2);
+        assert_eq!(time(&mut rtc)[0], 0x10);
+    }
+
+    #[test]
+    fn ram_pointer_wraps() {
+        let mut rtc = Ds1307::new();
+        rtc.start(false);
+        rtc.write(REGISTERS as u8 - 1);
+        rtc.write(0xAA);
+        rtc.write(0x80);
+        assert_eq!(rtc.registers[REGISTERS - 1], 0xAA);
+        assert_eq!(rtc.registers[SECONDS], 0x80);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::sim::naive::peripherals::twi::TwiDevice;
+
+//
+// 24C02 serial EEPROM, 256 bytes
+//
+// The first byte of a write sets the word address, the following bytes are
+// written into the 8 byte page, wrapping around within it. Writes are
+// programmed on the STOP condition, and take no time. Reads continue from the
+// current address and wrap around the whole array.
+//
+
+pub const SIZE: usize = 256;
+const PAGE_SIZE: u8 = 8;
+
+// Device address 1010 A2 A1 A0
+const BASE_ADDRESS: u8 = 0x50;
+
+#[derive(Debug, Clone)]
+pub struct Eeprom24c02 {
+    // Memory contents
+    pub data: [u8; SIZE],
+    // Address pins A2..A0
+    pins: u8,
+    pointer: u8,
+    // The next byte written is the word address
+    word_address: bool,
+    // Bytes waiting to be programmed on STOP
+    pending: Vec<(u8, u8)>,
+}
+
+impl Eeprom24c02 {
+    pub fn new(pins: u8) -> Self {
+        Eeprom24c02 {
+            data: [0xFF; SIZE],
+            pins: pins & 0b111,
+            pointer: 0,
+            word_address: false,
+            pending: vec![],
+        }
+    }
+}
+
+impl TwiDevice for Eeprom24c02 {
+    fn address(&self) -> u8 {
+        BASE_ADDRESS | self.pins
+    }
+
+    fn start(&mut self, read: bool) -> bool {
+        self.word_address = !read;
+        true
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        if self.word_address {
+            self.word_address = false;
+            self.pointer = byte;
+            return true;
+        }
+
+        self.pending.push((self.pointer, byte));
+        let page = self.pointer & !(PAGE_SIZE - 1);
+        self.pointer = page | (self.pointer.wrapping_add(1) & (PAGE_SIZE - 1));
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let byte = self.data[self.pointer as usize];
+        self.pointer = self.pointer.wrapping_add(1);
+        byte
+    }
+
+    fn stop(&mut self) {
+        for (addr, byte) in self.pending.drain(..) {
+            self.data[addr as usize] = byte;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_writes_wrap_and_land_on_stop() {
+        let mut eeprom = Eeprom24c02::new(0b101);
+        assert_eq!(eeprom.address(), 0x55);
+
+        eeprom.start(false);
+        for byte in [0x0E, 1, 2, 3] {
+            assert!(eeprom.write(byte));
+        }
+        assert_eq!(eeprom.data[0x0E], 0xFF);
+        eeprom.stop();
+        assert_eq!(
+            eeprom.data[0x08..0x10],
+            [3, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1, 2]
+        );
+    }
+
+    #[test]
+    fn sequential_reads_wrap_the_array() {
+        let mut eeprom = Eeprom24c02::new(0);
+        eeprom.data[SIZE - 1] = 0x12;
+        eeprom.data[0] = 0x34;
+
+        eeprom.start(false);
+        eeprom.write(0xFF);
+        eeprom.start(true);
+        assert_eq!([eeprom.read(), eeprom.read()], [0x12, 0x34]);
+    }
+}
@@ -0,0 +1,54 @@
+use crate::sim::naive::peripherals::spi::SpiDevice;
+
+//
+// 74HC165 parallel-in, serial-out shift register
+//
+// The inputs are loaded when the chip select goes low, and shifted out while
+// it stays low. QH is wired to MISO, SER to MOSI. Each register is a separate
+// device on the bus, daisy chains are not modelled.
+//
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hc165 {
+    shift: u8,
+    // A-H, A in bit 0
+    pub inputs: u8,
+}
+
+impl Hc165 {
+    pub fn new(inputs: u8) -> Self {
+        Hc165 { shift: 0, inputs }
+    }
+}
+
+impl SpiDevice for Hc165 {
+    fn select(&mut self, selected: bool) {
+        if selected {
+            self.shift = self.inputs;
+        }
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let out = self.shift;
+        self.shift = mosi;
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_inputs_on_select() {
+        let mut sr = Hc165::new(0x3C);
+        sr.select(true);
+        sr.inputs = 0xFF;
+        assert_eq!(sr.transfer(0x81), 0x3C);
+        assert_eq!(sr.transfer(0x00), 0x81);
+
+        sr.select(false);
+        sr.select(true);
+        assert_eq!(sr.transfer(0x00), 0xFF);
+    }
+}
@@ -0,0 +1,47 @@
+use crate::sim::naive::peripherals::spi::SpiDevice;
+
+//
+// 74HC595 serial-in, parallel-out shift register
+//
+// SER on MOSI, SRCLK on SCK, RCLK on the chip select line: the outputs are
+// latched when the chip select goes high again. QH' is wired to MISO. Each
+// register is a separate device on the bus, daisy chains are not modelled.
+//
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hc595 {
+    shift: u8,
+    // QA-QH, QA in bit 0
+    pub outputs: u8,
+}
+
+impl SpiDevice for Hc595 {
+    fn select(&mut self, selected: bool) {
+        if !selected {
+            self.outputs = self.shift;
+        }
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let out = self.shift;
+        self.shift = mosi;
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latches_on_deselect() {
+        let mut sr = Hc595::default();
+        sr.select(true);
+        assert_eq!(sr.transfer(0x12), 0x00);
+        assert_eq!(sr.transfer(0x34), 0x12);
+        assert_eq!(sr.outputs, 0x00);
+
+        sr.select(false);
+        assert_eq!(sr.outputs, 0x34);
+    }
+}
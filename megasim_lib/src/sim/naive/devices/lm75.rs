@@ -0,0 +1,151 @@
+use crate::sim::naive::peripherals::twi::TwiDevice;
+
+//
+// LM75 temperature sensor
+//
+// The first byte of a write selects the register, the following bytes are
+// written to it MSB first. Reads return the selected register, repeating.
+// Temperatures are 9-bit two's complement values in 0.5 °C steps, left
+// aligned in 16 bits.
+//
+
+// Device address 1001 A2 A1 A0
+const BASE_ADDRESS: u8 = 0x48;
+
+// Register pointer values
+const TEMPERATURE: u8 = 0;
+const CONFIGURATION: u8 = 1;
+const HYSTERESIS: u8 = 2;
+
+#[derive(Debug, Clone)]
+pub struct Lm75 {
+    // Measured temperature in °C, set by the host
+    pub temperature: f32,
+    // Address pins A2..A0
+    pins: u8,
+    configuration: u8,
+    t_hyst: u16,
+    t_os: u16,
+    pointer: u8,
+    // The next byte written selects the register
+    select: bool,
+    // Byte of the register read or written next
+    byte: usize,
+}
+
+impl Lm75 {
+    pub fn new(pins: u8, temperature: f32) -> Self {
+        Lm75 {
+            temperature,
+            pins: pins & 0b111,
+            configuration: 0,
+            // 75 °C and 80 °C
+            t_hyst: 0x4B00,
+            t_os: 0x5000,
+            pointer: TEMPERATURE,
+            select: false,
+            byte: 0,
+        }
+    }
+
+    // The sensor range is -55 °C to 125 °C.
+    fn temperature_register(&self) -> u16 {
+        let half_degrees = (self.temperature * 2.0).round().clamp(-110.0, 250.0) as i16;
+        (half_degrees << 7) as u16
+    }
+
+    fn register(&self) -> Vec<u8> {
+        match self.pointer {
+            TEMPERATURE => self.temperature_register().to_be_bytes().to_vec(),
+            CONFIGURATION => vec![self.configuration],
+            HYSTERESIS => self.t_hyst.to_be_bytes().to_vec(),
+            _ => self.t_os.to_be_bytes().to_vec(),
+        }
+    }
+}
+
+impl TwiDevice for Lm75 {
+    fn address(&self) -> u8 {
+        BASE_ADDRESS | self.pins
+    }
+
+    fn start(&mut self, read: bool) -> bool {
+        self.select = !read;
+        self.byte = 0;
+        true
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        if self.select {
+            self.select = false;
+            self.pointer = byte & 0b11;
+            return true;
+        }
+
+        // Only the 9 temperature bits of the limits are kept
+        let (register, shift) = if self.byte == 0 {
+            (0xFF00, 8)
+        } else {
+            (0x0080, 0)
+        };
+        match self.pointer {
+            TEMPERATURE => return false,
+            CONFIGURATION => self.configuration = byte & 0x1F,
+            HYSTERESIS => {
+                self.t_hyst = (self.t_hyst & !register) | ((byte as u16) << shift & register)
+            }
+            _ => self.t_os = (self.t_os & !register) | ((byte as u16) << shift & register),
+        }
+        self.byte += 1;
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let register = self.register();
+        let byte = register[self.byte % register.len()];
+        self.byte += 1;
+        byte
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(lm75: &mut Lm75, pointer: u8) -> [u8; 2] {
+        lm75.start(false);
+        lm75.write(pointer);
+        lm75.start(true);
+        [lm75.read(), lm75.read()]
+    }
+
+    #[test]
+    fn temperature_in_half_degrees() {
+        let mut lm75 = Lm75::new(0, -25.5);
+        assert_eq!(read(&mut lm75, TEMPERATURE), [0xE6, 0x80]);
+
+        lm75.temperature = 25.2;
+        assert_eq!(read(&mut lm75, TEMPERATURE), [0x19, 0x00]);
+
+        lm75.temperature = 200.0;
+        assert_eq!(read(&mut lm75, TEMPERATURE), [0x7D, 0x00]);
+    }
+
+    #[test]
+    fn limit_registers_keep_nine_bits() {
+        let mut lm75 = Lm75::new(0b111, 0.0);
+        assert_eq!(lm75.address(), 0x4F);
+        assert_eq!(read(&mut lm75, 3), [0x50, 0x00]);
+
+        lm75.start(false);
+        for byte in [3, 0x55, 0xFF] {
+            lm75.write(byte);
+        }
+        assert_eq!(read(&mut lm75, 3), [0x55, 0x80]);
+
+        // The temperature register is read only
+        lm75.start(false);
+        lm75.write(TEMPERATURE);
+        assert!(!lm75.write(0x12));
+    }
+}
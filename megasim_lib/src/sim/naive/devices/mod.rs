@@ -0,0 +1,13 @@
+pub mod ds1307;
+pub mod eeprom24c02;
+pub mod hc165;
+pub mod hc595;
+pub mod lm75;
+pub mod sd_card;
+
+pub use ds1307::Ds1307;
+pub use eeprom24c02::Eeprom24c02;
+pub use hc165::Hc165;
+pub use hc595::Hc595;
+pub use lm75::Lm75;
+pub use sd_card::SdCard;
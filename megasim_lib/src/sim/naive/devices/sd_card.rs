@@ -0,0 +1,231 @@
+use crate::sim::naive::peripherals::spi::SpiDevice;
+use std::collections::VecDeque;
+
+//
+// SD card in SPI mode
+//
+// A block addressed (SDHC) card with 512 byte blocks. Supports the commands
+// used to initialise a card and to read and write single blocks: CMD0, CMD8,
+// CMD16, CMD17, CMD24, CMD55/ACMD41, CMD58 and CMD59. CRCs are not checked.
+//
+
+pub const BLOCK_SIZE: usize = 512;
+
+// R1 response bits
+const R1_IDLE: u8 = 0x01;
+const R1_ILLEGAL_COMMAND: u8 = 0x04;
+const R1_PARAMETER_ERROR: u8 = 0x40;
+
+const DATA_TOKEN: u8 = 0xFE;
+const DATA_ACCEPTED: u8 = 0x05;
+// OCR: powered up, 2.7-3.6V, high capacity
+const OCR: [u8; 4] = [0xC0, 0xFF, 0x80, 0x00];
+
+#[derive(Debug, Clone)]
+enum Write {
+    // Waiting for the data token of a block write
+    Token(usize),
+    // Receiving the block and its CRC
+    Data(usize, Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct SdCard {
+    // Card contents
+    pub data: Vec<u8>,
+    idle: bool,
+    // The previous command was CMD55
+    app_command: bool,
+    command: Vec<u8>,
+    response: VecDeque<u8>,
+    write: Option<Write>,
+}
+
+impl SdCard {
+    pub fn new(blocks: usize) -> Self {
+        SdCard {
+            data: vec![0; blocks * BLOCK_SIZE],
+            idle: true,
+            app_command: false,
+            command: vec![],
+            response: VecDeque::new(),
+            write: None,
+        }
+    }
+
+    fn block(&self, addr: u32) -> Option<usize> {
+        let start = addr as usize * BLOCK_SIZE;
+        (start + BLOCK_SIZE <= self.data.len()).then_some(start)
+    }
+
+    fn execute(&mut self) {
+        let index = self.command[0] & 0x3F;
+        let arg = u32::from_be_bytes([
+            self.command[1],
+            self.command[2],
+            self.command[3],
+            self.command[4],
+        ]);
+        self.command.clear();
+
+        let app_command = self.app_command;
+        self.app_command = false;
+        let r1 = if self.idle { R1_IDLE } else { 0 };
+
+        // One byte of NCR before the response
+        self.response.push_back(0xFF);
+
+        match (app_command, index) {
+            // GO_IDLE_STATE
+            (_, 0) => {
+                self.idle = true;
+                self.response.push_back(R1_IDLE);
+            }
+            // SEND_IF_COND, echoes the voltage and check pattern
+            (_, 8) => {
+                let echo = (arg & 0x0FFF).to_be_bytes();
+                self.response.extend([r1, 0x00, 0x00, echo[2], echo[3]]);
+            }
+            // SET_BLOCKLEN, only 512 byte blocks
+            (_, 16) if arg as usize == BLOCK_SIZE => self.response.push_back(r1),
+            (_, 16) => self.response.push_back(r1 | R1_PARAMETER_ERROR),
+            // READ_SINGLE_BLOCK
+            (_, 17) => match self.block(arg) {
+                Some(start) => {
+                    self.response.extend([r1, 0xFF, DATA_TOKEN]);
+                    self.response.extend(&self.data[start..start + BLOCK_SIZE]);
+                    self.response.extend([0xFF, 0xFF]);
+                }
+                None => self.response.push_back(r1 | R1_PARAMETER_ERROR),
+            },
+            // WRITE_BLOCK
+            (_, 24) => match self.block(arg) {
+                Some(start) => {
+                    self.response.push_back(r1);
+                    self.write = Some(Write::Token(start));
+                }
+                None => self.response.push_back(r1 | R1_PARAMETER_ERROR),
+            },
+            // APP_CMD
+            (_, 55) => {
+                self.app_command = true;
+                self.response.push_back(r1);
+            }
+            // READ_OCR
+            (_, 58) => {
+                self.response.push_back(r1);
+                self.response.extend(OCR);
+            }
+            // CRC_ON_OFF
+            (_, 59) => self.response.push_back(r1),
+            // SD_SEND_OP_COND, initialisation completes at once
+            (true, 41) => {
+                self.idle = false;
+                self.response.push_back(0);
+            }
+            _ => self.response.push_back(r1 | R1_ILLEGAL_COMMAND),
+        }
+    }
+}
+
+impl SpiDevice for SdCard {
+    fn select(&mut self, selected: bool) {
+        if !selected {
+            self.command.clear();
+            self.response.clear();
+            self.write = None;
+        }
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let out = self.response.pop_front().unwrap_or(0xFF);
+
+        match self.write.take() {
+            Some(Write::Token(start)) if mosi == DATA_TOKEN => {
+                self.write = Some(Write::Data(start, vec![]));
+            }
+            Some(Write::Token(start)) => self.write = Some(Write::Token(start)),
+            // Block data followed by two CRC bytes
+            Some(Write::Data(start, mut block)) => {
+                block.push(mosi);
+                if block.len() == BLOCK_SIZE + 2 {
+                    self.data[start..start + BLOCK_SIZE].copy_from_slice(&block[..BLOCK_SIZE]);
+                    // Busy for a byte while programming
+                    self.response.extend([DATA_ACCEPTED, 0x00]);
+                } else {
+                    self.write = Some(Write::Data(start, block));
+                }
+            }
+            // Commands start with 01 in the top bits and are 6 bytes long
+            None => {
+                if !self.command.is_empty() || mosi & 0xC0 == 0x40 {
+                    self.command.push(mosi);
+                    if self.command.len() == 6 {
+                        self.execute();
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sends a command and returns the R1 response.
+    fn command(sd: &mut SdCard, index: u8, arg: u32) -> u8 {
+        sd.transfer(0x40 | index);
+        for byte in arg.to_be_bytes() {
+            sd.transfer(byte);
+        }
+        sd.transfer(0x95);
+        sd.transfer(0xFF);
+        sd.transfer(0xFF)
+    }
+
+    fn initialised() -> SdCard {
+        let mut sd = SdCard::new(4);
+        sd.select(true);
+        assert_eq!(command(&mut sd, 0, 0), R1_IDLE);
+        assert_eq!(command(&mut sd, 8, 0x1AA), R1_IDLE);
+        assert_eq!([0, 0, 0, 0].map(|_| sd.transfer(0xFF)), [0, 0, 0x01, 0xAA]);
+        assert_eq!(command(&mut sd, 55, 0), R1_IDLE);
+        assert_eq!(command(&mut sd, 41, 1 << 30), 0);
+        sd
+    }
+
+    #[test]
+    fn initialisation() {
+        let mut sd = initialised();
+        assert_eq!(command(&mut sd, 58, 0), 0);
+        assert_eq!([0, 0, 0, 0].map(|_| sd.transfer(0xFF)), OCR);
+
+        // ACMD41 needs CMD55 first
+        assert_eq!(command(&mut sd, 41, 0), R1_ILLEGAL_COMMAND);
+        assert_eq!(command(&mut sd, 16, 1024), R1_PARAMETER_ERROR);
+        assert_eq!(command(&mut sd, 17, 4), R1_PARAMETER_ERROR);
+    }
+
+    #[test]
+    fn write_and_read_a_block() {
+        let mut sd = initialised();
+        assert_eq!(command(&mut sd, 24, 2), 0);
+        sd.transfer(0xFF);
+        sd.transfer(DATA_TOKEN);
+        for i in 0..BLOCK_SIZE + 2 {
+            sd.transfer(i as u8);
+        }
+        assert_eq!(sd.transfer(0xFF), DATA_ACCEPTED);
+        assert_eq!(sd.data[2 * BLOCK_SIZE + 7], 7);
+
+        sd.transfer(0xFF);
+        assert_eq!(command(&mut sd, 17, 2), 0);
+        assert_eq!(sd.transfer(0xFF), 0xFF);
+        assert_eq!(sd.transfer(0xFF), DATA_TOKEN);
+        let block: Vec<u8> = (0..BLOCK_SIZE).map(|_| sd.transfer(0xFF)).collect();
+        assert_eq!(block, sd.data[2 * BLOCK_SIZE..3 * BLOCK_SIZE]);
+    }
+}
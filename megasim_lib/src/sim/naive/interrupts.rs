@@ -0,0 +1,320 @@
+use crate::sim::naive::chip::{Chip, State};
+use crate::sim::naive::peripherals::{
+    TIFR, TIMSK,
+    external::{self, GIFR, INTF0, INTF1, INTF2},
+};
+
+//
+// Interrupts (Page 44)
+//
+
+const GICR: usize = 0x5B;
+
+// GICR: INT1 | INT0 | INT2 | - | - | - | IVSEL | IVCE
+const IVSEL: u8 = 1;
+const IVCE: u8 = 0;
+
+// IVSEL can only be changed within four cycles of setting IVCE
+const IVCE_CYCLES: u64 = 4;
+
+// Start of the boot section with the default BOOTSZ fuses (1024 words)
+pub const BOOT_START: u16 = 0x1C00;
+
+// Cycles to push the PC and jump to the vector, plus the extra cycles it
+// takes to wake up from sleep
+const ENTRY_CYCLES: u64 = 4;
+const WAKE_UP_CYCLES: u64 = 4;
+
+// Reset and Interrupt Vectors (Table 18), in priority order
+pub const VECTORS: [&str; 21] = [
+    "RESET",
+    "INT0",
+    "INT1",
+    "TIMER2_COMP",
+    "TIMER2_OVF",
+    "TIMER1_CAPT",
+    "TIMER1_COMPA",
+    "TIMER1_COMPB",
+    "TIMER1_OVF",
+    "TIMER0_OVF",
+    "SPI_STC",
+    "USART_RXC",
+    "USART_UDRE",
+    "USART_TXC",
+    "ADC",
+    "EE_RDY",
+    "ANA_COMP",
+    "TWI",
+    "INT2",
+    "TIMER0_COMP",
+    "SPM_RDY",
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Trigger {
+    // Flag bit, cleared by hardware when the interrupt is taken
+    Flag(usize, u8),
+    // Flag bit, cleared by software or by the peripheral itself
+    Held(usize, u8),
+    // Requested while the bit is clear, with no flag of its own
+    Ready(usize, u8),
+}
+
+// Trigger and enable bit of each interrupt, indexed by vector number - 1.
+// Registers are data space addresses.
+const SOURCES: [(Trigger, (usize, u8)); 20] = [
+    (Trigger::Flag(GIFR, INTF0), (GICR, 6)),
+    (Trigger::Flag(GIFR, INTF1), (GICR, 7)),
+    (Trigger::Flag(TIFR, 7), (TIMSK, 7)),
+    (Trigger::Flag(TIFR, 6), (TIMSK, 6)),
+    (Trigger::Flag(TIFR, 5), (TIMSK, 5)),
+    (Trigger::Flag(TIFR, 4), (TIMSK, 4)),
+    (Trigger::Flag(TIFR, 3), (TIMSK, 3)),
+    (Trigger::Flag(TIFR, 2), (TIMSK, 2)),
+    (Trigger::Flag(TIFR, 0), (TIMSK, 0)),
+    // SPSR @ 7 => SPIF, SPCR @ 7 => SPIE
+    (Trigger::Flag(0x2E, 7), (0x2D, 7)),
+    // UCSRA @ 7 => RXC, UCSRB @ 7 => RXCIE
+    (Trigger::Held(0x2B, 7), (0x2A, 7)),
+    // UCSRA @ 5 => UDRE, UCSRB @ 5 => UDRIE
+    (Trigger::Held(0x2B, 5), (0x2A, 5)),
+    // UCSRA @ 6 => TXC, UCSRB @ 6 => TXCIE
+    (Trigger::Flag(0x2B, 6), (0x2A, 6)),
+    // ADCSRA @ 4 => ADIF, ADCSRA @ 3 => ADIE
+    (Trigger::Flag(0x26, 4), (0x26, 3)),
+    // EECR @ 1 => EEWE, EECR @ 3 => EERIE
+    (Trigger::Ready(0x3C, 1), (0x3C, 3)),
+    // ACSR @ 4 => ACI, ACSR @ 3 => ACIE
+    (Trigger::Flag(0x28, 4), (0x28, 3)),
+    // TWCR @ 7 => TWINT, TWCR @ 0 => TWIE
+    (Trigger::Held(0x56, 7), (0x56, 0)),
+    (Trigger::Flag(GIFR, INTF2), (GICR, 5)),
+    (Trigger::Flag(TIFR, 1), (TIMSK, 1)),
+    // SPMCR @ 0 => SPMEN, SPMCR @ 7 => SPMIE
+    (Trigger::Ready(0x57, 0), (0x57, 7)),
+];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Interrupts {
+    // Set by SEI and RETI, which always let one more instruction execute
+    // before an interrupt is served
+    pub(crate) inhibit: bool,
+    // Cycles left until IVCE clears itself
+    ivce_cycles: u64,
+}
+
+fn bit(c: &Chip, (addr, bit): (usize, u8)) -> bool {
+    (c.ram[addr] >> bit) & 1 != 0
+}
+
+fn requested(c: &Chip, (trigger, enable): (Trigger, (usize, u8))) -> bool {
+    if !bit(c, enable) {
+        return false;
+    }
+
+    match trigger {
+        Trigger::Flag(addr, flag) => {
+            bit(c, (addr, flag)) || (addr == GIFR && external::level_requested(c, flag))
+        }
+        Trigger::Held(addr, flag) => bit(c, (addr, flag)),
+        Trigger::Ready(addr, busy) => !bit(c, (addr, busy)),
+    }
+}
+
+// Word address of interrupt vector `n`, in the boot section when IVSEL is
+// set.
+pub fn vector_address(c: &Chip, n: u16) -> u16 {
+    let base = if bit(c, (GICR, IVSEL)) { BOOT_START } else { 0 };
+    base + n * 2
+}
+
+// Takes the highest priority pending interrupt, if interrupts are enabled.
+// Returns the cycles the interrupt entry took.
+pub(crate) fn service(c: &mut Chip) -> Option<u64> {
+    if c.interrupts.inhibit {
+        c.interrupts.inhibit = false;
+        return None;
+    }
+
+    // Interrupts are also disabled while IVCE is set
+    if !c.sreg_get().i || bit(c, (GICR, IVCE)) {
+        return None;
+    }
+
+    let index = SOURCES.iter().position(|source| requested(c, *source))?;
+    if let Trigger::Flag(addr, flag) = SOURCES[index].0 {
+        c.ram[addr] &= !(1 << flag);
+    }
+
+    let mut cycles = ENTRY_CYCLES;
+    if matches!(c.state, State::Sleeping(_)) {
+        c.state = State::Running;
+        cycles += WAKE_UP_CYCLES;
+    }
+
+    // Same stack layout as CALL, so RETI returns to the interrupted
+    // instruction
+    c.push_byte((c.pc & 0xFF) as u8);
+    c.push_byte((c.pc >> 8) as u8);
+
+    let mut sreg = c.sreg_get();
+    sreg.i = false;
+    c.sreg_set(&sreg);
+
+    c.pc = vector_address(c, index as u16 + 1);
+    Some(cycles)
+}
+
+// Counts down the IVCE timeout.
+pub(crate) fn tick(c: &mut Chip, cycles: u64) {
+    if c.interrupts.ivce_cycles == 0 {
+        return;
+    }
+
+    c.interrupts.ivce_cycles = c.interrupts.ivce_cycles.saturating_sub(cycles);
+    if c.interrupts.ivce_cycles == 0 {
+        c.ram[GICR] &= !(1 << IVCE);
+    }
+}
+
+// Writing IVCE opens a four cycle window in which the next GICR write can
+// change IVSEL. That write also clears IVCE.
+pub(crate) fn io_write(c: &mut Chip, addr: usize, val: u8) -> bool {
+    if addr != GICR {
+        return false;
+    }
+
+    let select = if (val >> IVCE) & 1 != 0 {
+        c.interrupts.ivce_cycles = IVCE_CYCLES;
+        c.ram[GICR] & (1 << IVSEL) | 1 << IVCE
+    } else if c.interrupts.ivce_cycles > 0 {
+        c.interrupts.ivce_cycles = 0;
+        val & (1 << IVSEL)
+    } else {
+        c.ram[GICR] & (1 << IVSEL)
+    };
+
+    c.ram[GICR] = val & !(1 << IVSEL | 1 << IVCE) | select;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::naive::chip::SleepMode;
+
+    const SREG_I: u8 = 1 << 7;
+    const RAMEND: u16 = 0x45F;
+
+    fn chip() -> Chip {
+        let mut c = Chip::new();
+        c.sp_set(RAMEND);
+        c.ram[0x5F] = SREG_I;
+        c.pc = 0x123;
+        // INT0/INT1 are low level triggered by default
+        c.ram[0x30] = 0xFF;
+        c
+    }
+
+    #[test]
+    fn highest_priority_first() {
+        // INT1 (vector 2) before TIMER0_OVF (vector 9)
+        let mut c = chip();
+        c.ram[GICR] = 1 << 7;
+        c.ram[TIMSK] = 1 << 0;
+        c.ram[GIFR] = 1 << INTF1;
+        c.ram[TIFR] = 1 << 0;
+
+        assert_eq!(service(&mut c), Some(4));
+        assert_eq!((c.pc, c.ram[0x5F]), (4, 0));
+        assert_eq!((c.ram[GIFR], c.ram[TIFR]), (0, 1 << 0));
+
+        // Pushed like CALL
+        assert_eq!(c.sp_get(), RAMEND - 2);
+        assert_eq!(c.ram[RAMEND as usize - 1..=RAMEND as usize], [0x01, 0x23]);
+
+        // The timer follows once I is set again
+        c.ram[0x5F] = SREG_I;
+        service(&mut c);
+        assert_eq!((c.pc, c.ram[TIFR]), (18, 0));
+    }
+
+    #[test]
+    fn flags_wait_for_the_enable_bits() {
+        let mut c = chip();
+        c.ram[TIFR] = 1 << 0;
+        assert_eq!(service(&mut c), None);
+
+        c.ram[TIMSK] = 1 << 0;
+        c.ram[0x5F] = 0;
+        assert_eq!(service(&mut c), None);
+        assert_eq!((c.pc, c.ram[TIFR]), (0x123, 1 << 0));
+    }
+
+    #[test]
+    fn one_instruction_after_sei() {
+        let mut c = chip();
+        c.ram[TIMSK] = 1 << 0;
+        c.ram[TIFR] = 1 << 0;
+        c.interrupts.inhibit = true;
+        assert_eq!(service(&mut c), None);
+        assert_eq!(service(&mut c), Some(4));
+    }
+
+    #[test]
+    fn held_and_ready_triggers() {
+        // USART_RXC keeps RXC set, it is cleared by reading UDR
+        let mut c = chip();
+        c.ram[0x2A] = 1 << 7;
+        c.ram[0x2B] = 1 << 7;
+        service(&mut c);
+        assert_eq!((c.pc, c.ram[0x2B]), (22, 1 << 7));
+
+        // EE_RDY is requested while EEWE is clear
+        let mut c = chip();
+        c.ram[0x3C] = 1 << 3 | 1 << 1;
+        assert_eq!(service(&mut c), None);
+        c.ram[0x3C] = 1 << 3;
+        service(&mut c);
+        assert_eq!(c.pc, 30);
+    }
+
+    #[test]
+    fn waking_up_takes_longer() {
+        let mut c = chip();
+        c.ram[GICR] = 1 << 6;
+        c.ram[GIFR] = 1 << INTF0;
+        c.state = State::Sleeping(SleepMode::Idle);
+        assert_eq!(service(&mut c), Some(8));
+        assert_eq!((c.state, c.pc), (State::Running, 2));
+    }
+
+    #[test]
+    fn ivsel_needs_ivce() {
+        let mut c = chip();
+        c.data_write(GICR, 1 << IVSEL);
+        assert_eq!(vector_address(&c, 1), 2);
+
+        c.data_write(GICR, 1 << IVCE);
+        c.ram[TIMSK] = 1 << 0;
+        c.ram[TIFR] = 1 << 0;
+        assert_eq!(service(&mut c), None);
+
+        c.data_write(GICR, 1 << IVSEL);
+        assert_eq!(c.ram[GICR], 1 << IVSEL);
+        assert_eq!(vector_address(&c, 1), BOOT_START + 2);
+        assert_eq!(service(&mut c), Some(4));
+        assert_eq!(c.pc, BOOT_START + 18);
+    }
+
+    #[test]
+    fn ivce_times_out() {
+        let mut c = chip();
+        c.data_write(GICR, 1 << IVCE);
+        tick(&mut c, IVCE_CYCLES);
+        assert_eq!(c.ram[GICR], 0);
+
+        c.data_write(GICR, 1 << IVSEL);
+        assert_eq!(c.ram[GICR], 0);
+    }
+}
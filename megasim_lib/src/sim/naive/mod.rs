@@ -1,2 +1,5 @@
 pub mod ops;
-pub mod chip;
\ No newline at end of file
+pub mod chip;
+pub mod peripherals;
+pub mod devices;
+pub mod interrupts;
\ No newline at end of file
@@ -1,4 +1,121 @@
-use crate::sim::naive::chip::Chip;
+use crate::sim::naive::chip::{Chip, Sreg};
+
+fn add_flags(sreg: &mut Sreg, d: u8, r: u8, result: u8) {
+    let d7 = (d >> 7) & 1 == 1;
+    let r7 = (r >> 7) & 1 == 1;
+    let res7 = (result >> 7) & 1 == 1;
+    let d3 = (d >> 3) & 1 == 1;
+    let r3 = (r >> 3) & 1 == 1;
+    let res3 = (result >> 3) & 1 == 1;
+
+    sreg.h = (d3 & r3) | (r3 & !res3) | (!res3 & d3);
+    sreg.v = (d7 & r7 & !res7) | (!d7 & !r7 & res7);
+    sreg.n = res7;
+    sreg.z = result == 0;
+    sreg.c = (d7 & r7) | (r7 & !res7) | (!res7 & d7);
+    sreg.s = sreg.n ^ sreg.v;
+}
+
+fn sub_flags(sreg: &mut Sreg, d: u8, r: u8, result: u8) {
+    let d7 = (d >> 7) & 1 == 1;
+    let r7 = (r >> 7) & 1 == 1;
+    let res7 = (result >> 7) & 1 == 1;
+    let d3 = (d >> 3) & 1 == 1;
+    let r3 = (r >> 3) & 1 == 1;
+    let res3 = (result >> 3) & 1 == 1;
+
+    sreg.h = (!d3 & r3) | (r3 & res3) | (res3 & !d3);
+    sreg.v = (d7 & !r7 & !res7) | (!d7 & r7 & res7);
+    sreg.n = res7;
+    sreg.z = result == 0;
+    sreg.c = (!d7 & r7) | (r7 & res7) | (res7 & !d7);
+    sreg.s = sreg.n ^ sreg.v;
+}
+
+fn check_word_register(name: &str, rd: u8) {
+    if !matches!(rd, 24 | 26 | 28 | 30) {
+        panic!(
+            "{}: Invalid Register R{}. Must be R24, R26, R28 or R30.",
+            name, rd
+        );
+    }
+}
+
+fn store_product(c: &mut Chip, product: u16, carry: bool) {
+    [c.ram[0], c.ram[1]] = product.to_le_bytes();
+
+    let mut sreg = c.sreg_get();
+    sreg.c = carry;
+    sreg.z = product == 0;
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+}
+
+fn check_mul_registers(name: &str, rd: u8, rr: u8, low: u8, high: u8) {
+    if !(low..=high).contains(&rd) || !(low..=high).contains(&rr) {
+        panic!(
+            "{}: Invalid Registers R{}, R{}. Must be R{}-R{}.",
+            name, rd, rr, low, high
+        );
+    }
+}
+
+pub fn op_adc(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    let d = c.ram[rd as usize];
+    let r = c.ram[rr as usize];
+
+    let mut sreg = c.sreg_get();
+    let result = d.wrapping_add(r).wrapping_add(sreg.c as u8);
+    c.ram[rd as usize] = result;
+
+    add_flags(&mut sreg, d, r, result);
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+pub fn op_add(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    let d = c.ram[rd as usize];
+    let r = c.ram[rr as usize];
+
+    let result = d.wrapping_add(r);
+    c.ram[rd as usize] = result;
+
+    let mut sreg = c.sreg_get();
+    add_flags(&mut sreg, d, r, result);
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+pub fn op_adiw(c: &mut Chip, rd: u8, k: u8) -> (u8,) {
+    check_word_register("ADIW", rd);
+    if k > 63 {
+        panic!("ADIW: Invalid constant {}. Must be 0-63.", k);
+    }
+    let d = rd as usize;
+
+    let val = u16::from_le_bytes([c.ram[d], c.ram[d + 1]]);
+    let result = val.wrapping_add(k as u16);
+    [c.ram[d], c.ram[d + 1]] = result.to_le_bytes();
+
+    let dh7 = (val >> 15) & 1 == 1;
+    let r15 = (result >> 15) & 1 == 1;
+
+    let mut sreg = c.sreg_get();
+    sreg.v = !dh7 & r15;
+    sreg.n = r15;
+    sreg.z = result == 0;
+    sreg.c = !r15 & dh7;
+    sreg.s = sreg.n ^ sreg.v;
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (2,)
+}
 
 pub fn op_and(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
     let d = rd as usize;
@@ -19,7 +136,7 @@ pub fn op_and(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
 }
 
 pub fn op_andi(c: &mut Chip, rd: u8, k: u8) -> (u8,) {
-    if rd < 16 || rd > 31 {
+    if !(16..=31).contains(&rd) {
         panic!("ANDI: Invalid Register R{}. Must be R16-R31.", rd);
     }
     let d = rd as usize;
@@ -38,21 +155,6 @@ pub fn op_andi(c: &mut Chip, rd: u8, k: u8) -> (u8,) {
     (1,)
 }
 
-pub fn op_clr(c: &mut Chip, rd: u8) -> (u8,) {
-    let d = rd as usize;
-    c.ram[d] = 0;
-
-    let mut sreg = c.sreg_get();
-    sreg.s = false;
-    sreg.v = false;
-    sreg.n = false;
-    sreg.z = true;
-    c.sreg_set(&sreg);
-
-    c.pc = c.pc.wrapping_add(1);
-    (1,)
-}
-
 pub fn op_com(c: &mut Chip, rd: u8) -> (u8,) {
     let d = rd as usize;
     let val = c.ram[d];
@@ -71,8 +173,37 @@ pub fn op_com(c: &mut Chip, rd: u8) -> (u8,) {
     (1,)
 }
 
+pub fn op_cp(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    let d = c.ram[rd as usize];
+    let r = c.ram[rr as usize];
+    let result = d.wrapping_sub(r);
+
+    let mut sreg = c.sreg_get();
+    sub_flags(&mut sreg, d, r, result);
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+pub fn op_cpc(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    let d = c.ram[rd as usize];
+    let r = c.ram[rr as usize];
+
+    let mut sreg = c.sreg_get();
+    let prev_z = sreg.z;
+    let result = d.wrapping_sub(r).wrapping_sub(sreg.c as u8);
+
+    sub_flags(&mut sreg, d, r, result);
+    sreg.z = result == 0 && prev_z;
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
 pub fn op_cpi(c: &mut Chip, rd: u8, k: u8) -> (u8,) {
-    if rd < 16 || rd > 31 {
+    if !(16..=31).contains(&rd) {
         panic!("CPI: Invalid Register R{}. Must be R16-R31.", rd);
     }
     let d = c.ram[rd as usize];
@@ -149,6 +280,66 @@ pub fn op_inc(c: &mut Chip, rd: u8) -> (u8,) {
     (1,)
 }
 
+pub fn op_fmul(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    check_mul_registers("FMUL", rd, rr, 16, 23);
+    let product = c.ram[rd as usize] as u16 * c.ram[rr as usize] as u16;
+
+    store_product(c, product << 1, (product >> 15) & 1 == 1);
+    (2,)
+}
+
+pub fn op_fmuls(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    check_mul_registers("FMULS", rd, rr, 16, 23);
+    let product = (c.ram[rd as usize] as i8 as i16 * c.ram[rr as usize] as i8 as i16) as u16;
+
+    store_product(c, product << 1, (product >> 15) & 1 == 1);
+    (2,)
+}
+
+pub fn op_fmulsu(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    check_mul_registers("FMULSU", rd, rr, 16, 23);
+    let product = (c.ram[rd as usize] as i8 as i16 * c.ram[rr as usize] as i16) as u16;
+
+    store_product(c, product << 1, (product >> 15) & 1 == 1);
+    (2,)
+}
+
+pub fn op_mul(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    let product = c.ram[rd as usize] as u16 * c.ram[rr as usize] as u16;
+
+    store_product(c, product, (product >> 15) & 1 == 1);
+    (2,)
+}
+
+pub fn op_muls(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    check_mul_registers("MULS", rd, rr, 16, 31);
+    let product = (c.ram[rd as usize] as i8 as i16 * c.ram[rr as usize] as i8 as i16) as u16;
+
+    store_product(c, product, (product >> 15) & 1 == 1);
+    (2,)
+}
+
+pub fn op_mulsu(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    check_mul_registers("MULSU", rd, rr, 16, 23);
+    let product = (c.ram[rd as usize] as i8 as i16 * c.ram[rr as usize] as i16) as u16;
+
+    store_product(c, product, (product >> 15) & 1 == 1);
+    (2,)
+}
+
+pub fn op_neg(c: &mut Chip, rd: u8) -> (u8,) {
+    let d = c.ram[rd as usize];
+    let result = 0u8.wrapping_sub(d);
+    c.ram[rd as usize] = result;
+
+    let mut sreg = c.sreg_get();
+    sub_flags(&mut sreg, 0, d, result);
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
 pub fn op_or(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
     let d = rd as usize;
     let r = rr as usize;
@@ -167,7 +358,7 @@ pub fn op_or(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
 }
 
 pub fn op_ori(c: &mut Chip, rd: u8, k: u8) -> (u8,) {
-    if rd < 16 || rd > 31 {
+    if !(16..=31).contains(&rd) {
         panic!("ORI: Invalid Register R{}. Must be R16-R31.", rd);
     }
     let d = rd as usize;
@@ -185,3 +376,411 @@ pub fn op_ori(c: &mut Chip, rd: u8, k: u8) -> (u8,) {
     c.pc = c.pc.wrapping_add(1);
     (1,)
 }
+
+pub fn op_sbc(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    let d = c.ram[rd as usize];
+    let r = c.ram[rr as usize];
+
+    let mut sreg = c.sreg_get();
+    let prev_z = sreg.z;
+    let result = d.wrapping_sub(r).wrapping_sub(sreg.c as u8);
+    c.ram[rd as usize] = result;
+
+    sub_flags(&mut sreg, d, r, result);
+    sreg.z = result == 0 && prev_z;
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+pub fn op_sbci(c: &mut Chip, rd: u8, k: u8) -> (u8,) {
+    if !(16..=31).contains(&rd) {
+        panic!("SBCI: Invalid Register R{}. Must be R16-R31.", rd);
+    }
+    let d = c.ram[rd as usize];
+
+    let mut sreg = c.sreg_get();
+    let prev_z = sreg.z;
+    let result = d.wrapping_sub(k).wrapping_sub(sreg.c as u8);
+    c.ram[rd as usize] = result;
+
+    sub_flags(&mut sreg, d, k, result);
+    sreg.z = result == 0 && prev_z;
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+pub fn op_sbiw(c: &mut Chip, rd: u8, k: u8) -> (u8,) {
+    check_word_register("SBIW", rd);
+    if k > 63 {
+        panic!("SBIW: Invalid constant {}. Must be 0-63.", k);
+    }
+    let d = rd as usize;
+
+    let val = u16::from_le_bytes([c.ram[d], c.ram[d + 1]]);
+    let result = val.wrapping_sub(k as u16);
+    [c.ram[d], c.ram[d + 1]] = result.to_le_bytes();
+
+    let dh7 = (val >> 15) & 1 == 1;
+    let r15 = (result >> 15) & 1 == 1;
+
+    let mut sreg = c.sreg_get();
+    sreg.v = dh7 & !r15;
+    sreg.n = r15;
+    sreg.z = result == 0;
+    sreg.c = r15 & !dh7;
+    sreg.s = sreg.n ^ sreg.v;
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (2,)
+}
+
+pub fn op_sub(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    let d = c.ram[rd as usize];
+    let r = c.ram[rr as usize];
+
+    let result = d.wrapping_sub(r);
+    c.ram[rd as usize] = result;
+
+    let mut sreg = c.sreg_get();
+    sub_flags(&mut sreg, d, r, result);
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+pub fn op_subi(c: &mut Chip, rd: u8, k: u8) -> (u8,) {
+    if !(16..=31).contains(&rd) {
+        panic!("SUBI: Invalid Register R{}. Must be R16-R31.", rd);
+    }
+    let d = c.ram[rd as usize];
+
+    let result = d.wrapping_sub(k);
+    c.ram[rd as usize] = result;
+
+    let mut sreg = c.sreg_get();
+    sub_flags(&mut sreg, d, k, result);
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SREG: I | T | H | S | V | N | Z | C
+    const SREG: usize = 0x5F;
+    const C: u8 = 1 << 0;
+    const Z: u8 = 1 << 1;
+    const N: u8 = 1 << 2;
+    const V: u8 = 1 << 3;
+    const S: u8 = 1 << 4;
+    const H: u8 = 1 << 5;
+
+    fn chip(regs: &[(u8, u8)], sreg: u8) -> Chip {
+        let mut c = Chip::new();
+        for &(r, val) in regs {
+            c.ram[r as usize] = val;
+        }
+        c.ram[SREG] = sreg;
+        c
+    }
+
+    #[test]
+    fn add_sets_half_carry_carry_and_overflow() {
+        let mut c = chip(&[(16, 0x88), (17, 0x88)], 0);
+        op_add(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0x10);
+        assert_eq!(c.ram[SREG], H | S | V | C);
+
+        let mut c = chip(&[(16, 0x7F), (17, 0x01)], 0);
+        op_add(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0x80);
+        assert_eq!(c.ram[SREG], H | V | N);
+
+        let mut c = chip(&[(16, 0x80), (17, 0x80)], 0);
+        op_add(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0x00);
+        assert_eq!(c.ram[SREG], S | V | Z | C);
+    }
+
+    #[test]
+    fn adc_adds_carry() {
+        let mut c = chip(&[(16, 0xFF), (17, 0x00)], C);
+        op_adc(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0x00);
+        assert_eq!(c.ram[SREG], H | Z | C);
+
+        // 16-bit 0x00FF + 0x0001
+        let mut c = chip(&[(24, 0xFF), (25, 0x00), (16, 0x01), (17, 0x00)], 0);
+        op_add(&mut c, 24, 16);
+        op_adc(&mut c, 25, 17);
+        assert_eq!((c.ram[24], c.ram[25]), (0x00, 0x01));
+        assert_eq!(c.ram[SREG] & C, 0);
+    }
+
+    #[test]
+    fn sub_sets_half_carry_borrow_and_overflow() {
+        let mut c = chip(&[(16, 0x10), (17, 0x01)], 0);
+        op_sub(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0x0F);
+        assert_eq!(c.ram[SREG], H);
+
+        let mut c = chip(&[(16, 0x00), (17, 0x01)], 0);
+        op_sub(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0xFF);
+        assert_eq!(c.ram[SREG], H | S | N | C);
+
+        let mut c = chip(&[(16, 0x80), (17, 0x01)], 0);
+        op_sub(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0x7F);
+        assert_eq!(c.ram[SREG], H | S | V);
+
+        let mut c = chip(&[(16, 0x42), (17, 0x42)], 0);
+        op_sub(&mut c, 16, 17);
+        assert_eq!(c.ram[SREG], Z);
+    }
+
+    #[test]
+    fn subi_matches_sub() {
+        let mut c = chip(&[(16, 5)], 0);
+        op_subi(&mut c, 16, 6);
+        assert_eq!(c.ram[16], 0xFF);
+        assert_eq!(c.ram[SREG], H | S | N | C);
+    }
+
+    #[test]
+    fn sbc_only_clears_zero() {
+        // A zero result keeps Z from the low byte
+        let mut c = chip(&[(16, 0x12), (17, 0x12)], Z);
+        op_sbc(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0);
+        assert_eq!(c.ram[SREG], Z);
+
+        let mut c = chip(&[(16, 0x12), (17, 0x12)], 0);
+        op_sbc(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0);
+        assert_eq!(c.ram[SREG], 0);
+    }
+
+    #[test]
+    fn sbc_subtracts_carry() {
+        let mut c = chip(&[(16, 0x00), (17, 0x00)], Z | C);
+        op_sbc(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0xFF);
+        assert_eq!(c.ram[SREG], H | S | N | C);
+
+        let mut c = chip(&[(16, 0x00)], C);
+        op_sbci(&mut c, 16, 0);
+        assert_eq!(c.ram[16], 0xFF);
+    }
+
+    #[test]
+    fn cp_cpc_compare_words() {
+        let mut c = chip(&[(24, 0x34), (25, 0x12), (16, 0x34), (17, 0x12)], 0);
+        op_cp(&mut c, 24, 16);
+        op_cpc(&mut c, 25, 17);
+        assert_eq!(c.ram[SREG], Z);
+        assert_eq!((c.ram[24], c.ram[25]), (0x34, 0x12));
+
+        let mut c = chip(&[(24, 0x35), (25, 0x12), (16, 0x34), (17, 0x12)], 0);
+        op_cp(&mut c, 24, 16);
+        op_cpc(&mut c, 25, 17);
+        assert_eq!(c.ram[SREG] & Z, 0);
+
+        let mut c = chip(&[(24, 0x33), (25, 0x12), (16, 0x34), (17, 0x12)], 0);
+        op_cp(&mut c, 24, 16);
+        op_cpc(&mut c, 25, 17);
+        assert_eq!(c.ram[SREG] & (Z | C), C);
+    }
+
+    #[test]
+    fn cpi_compares_immediate() {
+        let mut c = chip(&[(16, 0x10)], 0);
+        op_cpi(&mut c, 16, 0x20);
+        assert_eq!(c.ram[16], 0x10);
+        assert_eq!(c.ram[SREG], S | N | C);
+    }
+
+    #[test]
+    fn adiw_sbiw_wrap() {
+        let mut c = chip(&[(24, 0xFF), (25, 0xFF)], 0);
+        assert_eq!(op_adiw(&mut c, 24, 1), (2,));
+        assert_eq!((c.ram[24], c.ram[25]), (0x00, 0x00));
+        assert_eq!(c.ram[SREG], Z | C);
+
+        let mut c = chip(&[(26, 0xFF), (27, 0x7F)], 0);
+        op_adiw(&mut c, 26, 1);
+        assert_eq!(c.ram[SREG], V | N);
+
+        let mut c = chip(&[(28, 0x00), (29, 0x00)], 0);
+        assert_eq!(op_sbiw(&mut c, 28, 1), (2,));
+        assert_eq!((c.ram[28], c.ram[29]), (0xFF, 0xFF));
+        assert_eq!(c.ram[SREG], S | N | C);
+    }
+
+    #[test]
+    fn neg_com() {
+        let mut c = chip(&[(16, 0x80)], 0);
+        op_neg(&mut c, 16);
+        assert_eq!(c.ram[16], 0x80);
+        assert_eq!(c.ram[SREG] & (V | C), V | C);
+
+        let mut c = chip(&[(16, 0x00)], 0);
+        op_neg(&mut c, 16);
+        assert_eq!(c.ram[SREG], Z);
+
+        let mut c = chip(&[(16, 0x0F)], 0);
+        op_com(&mut c, 16);
+        assert_eq!(c.ram[16], 0xF0);
+        assert_eq!(c.ram[SREG], S | N | C);
+    }
+
+    #[test]
+    fn inc_dec_overflow_keeps_carry() {
+        let mut c = chip(&[(16, 0x7F)], C);
+        op_inc(&mut c, 16);
+        assert_eq!(c.ram[16], 0x80);
+        assert_eq!(c.ram[SREG], V | N | C);
+
+        let mut c = chip(&[(16, 0x80)], 0);
+        op_dec(&mut c, 16);
+        assert_eq!(c.ram[16], 0x7F);
+        assert_eq!(c.ram[SREG], S | V);
+
+        let mut c = chip(&[(16, 0x01)], 0);
+        op_dec(&mut c, 16);
+        assert_eq!(c.ram[SREG], Z);
+    }
+
+    #[test]
+    fn logic_clears_overflow() {
+        let mut c = chip(&[(16, 0xF0), (17, 0x0F)], V);
+        op_and(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0x00);
+        assert_eq!(c.ram[SREG], Z);
+
+        let mut c = chip(&[(16, 0xF0), (17, 0x0F)], V);
+        op_or(&mut c, 16, 17);
+        assert_eq!(c.ram[16], 0xFF);
+        assert_eq!(c.ram[SREG], S | N);
+
+        let mut c = chip(&[(16, 0xFF), (17, 0xFF)], 0);
+        op_eor(&mut c, 16, 17);
+        assert_eq!(c.ram[SREG], Z);
+
+        let mut c = chip(&[(16, 0x0F)], 0);
+        op_andi(&mut c, 16, 0x03);
+        op_ori(&mut c, 16, 0x80);
+        assert_eq!(c.ram[16], 0x83);
+    }
+
+    #[test]
+    fn aliases_run_as_their_canonical_instructions() {
+        let mut c = Chip::with_program(
+            "
+            ser r16
+            cbr r16, 0x0F
+            sbr r17, 0x03
+            tst r17
+            break
+            clr r16
+            break
+            ldi r18, 0x81
+            lsl r18
+            break
+            rol r19
+            break
+            ",
+        );
+
+        c.run(100);
+        assert_eq!((c.ram[16], c.ram[17]), (0xF0, 0x03));
+        assert_eq!(c.ram[SREG], 0);
+
+        c.resume();
+        c.run(100);
+        assert_eq!(c.ram[16], 0);
+        assert_eq!(c.ram[SREG], Z);
+
+        c.resume();
+        c.run(100);
+        assert_eq!(c.ram[18], 0x02);
+        assert_eq!(c.ram[SREG], S | V | C);
+
+        c.resume();
+        c.run(100);
+        assert_eq!(c.ram[19], 0x01);
+        assert_eq!(c.ram[SREG], 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Must be R16-R31")]
+    fn immediate_needs_high_register() {
+        let mut c = chip(&[], 0);
+        op_subi(&mut c, 15, 1);
+    }
+
+    fn product(c: &Chip) -> u16 {
+        u16::from_le_bytes([c.ram[0], c.ram[1]])
+    }
+
+    #[test]
+    fn mul_unsigned() {
+        let mut c = chip(&[(16, 200), (17, 200)], 0);
+        assert_eq!(op_mul(&mut c, 16, 17), (2,));
+        assert_eq!(product(&c), 40000);
+        assert_eq!(c.ram[SREG], C);
+
+        let mut c = chip(&[(2, 0), (3, 7)], C);
+        op_mul(&mut c, 2, 3);
+        assert_eq!(product(&c), 0);
+        assert_eq!(c.ram[SREG], Z);
+    }
+
+    #[test]
+    fn mul_signed() {
+        let mut c = chip(&[(16, 0xFE), (17, 3)], 0);
+        op_muls(&mut c, 16, 17);
+        assert_eq!(product(&c) as i16, -6);
+        assert_eq!(c.ram[SREG], C);
+
+        let mut c = chip(&[(16, 0xFE), (17, 200)], 0);
+        op_mulsu(&mut c, 16, 17);
+        assert_eq!(product(&c) as i16, -400);
+    }
+
+    #[test]
+    fn fmul_shifts_product() {
+        // 0.5 * 0.5 = 0.25 in 1.7 format
+        let mut c = chip(&[(16, 0x40), (17, 0x40)], 0);
+        op_fmul(&mut c, 16, 17);
+        assert_eq!(product(&c), 0x2000);
+        assert_eq!(c.ram[SREG], 0);
+
+        // -1 * -1 overflows to -1, C is bit 15 before the shift
+        let mut c = chip(&[(16, 0x80), (17, 0x80)], 0);
+        op_fmuls(&mut c, 16, 17);
+        assert_eq!(product(&c), 0x8000);
+        assert_eq!(c.ram[SREG], 0);
+
+        let mut c = chip(&[(16, 0x80), (17, 0x80)], 0);
+        op_fmulsu(&mut c, 16, 17);
+        assert_eq!(product(&c), 0x8000);
+        assert_eq!(c.ram[SREG], C);
+    }
+
+    #[test]
+    #[should_panic(expected = "Must be R16-R23")]
+    fn fmul_needs_r16_to_r23() {
+        let mut c = chip(&[], 0);
+        op_fmul(&mut c, 24, 16);
+    }
+}
@@ -1,69 +1,99 @@
 use crate::sim::naive::chip::Chip;
 
-pub fn op_cbi(c: &mut Chip, a: u8, b: u8) -> (u8,) {
-    if a > 31 {
-        panic!("CBI: Invalid I/O Address {}. Must be 0-31.", a);
+pub fn op_asr(c: &mut Chip, rd: u8) -> (u8,) {
+    let d = rd as usize;
+    let val = c.ram[d];
+    let result = (val >> 1) | (val & 0x80);
+    c.ram[d] = result;
+
+    let mut sreg = c.sreg_get();
+    sreg.c = (val & 1) == 1;
+    sreg.n = (result >> 7) & 1 == 1;
+    sreg.z = result == 0;
+    sreg.v = sreg.n ^ sreg.c;
+    sreg.s = sreg.n ^ sreg.v;
+    c.sreg_set(&sreg);
+
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+pub fn op_bclr(c: &mut Chip, s: u8) -> (u8,) {
+    if s > 7 {
+        panic!("BCLR: Invalid SREG bit {}. Must be 0-7.", s);
     }
+
+    c.ram[0x5F] &= !(1 << s);
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+pub fn op_bld(c: &mut Chip, rd: u8, b: u8) -> (u8,) {
     if b > 7 {
-        panic!("CBI: Invalid bit {}. Must be 0-7.", b);
+        panic!("BLD: Invalid bit {}. Must be 0-7.", b);
     }
 
-    let addr = (Chip::IO_OFFSET + a as u16) as usize;
-    let val = c.ram[addr];
-    let mask = !(1 << b);
-    c.ram[addr] = val & mask;
+    let d = rd as usize;
+    if c.sreg_get().t {
+        c.ram[d] |= 1 << b;
+    } else {
+        c.ram[d] &= !(1 << b);
+    }
     c.pc = c.pc.wrapping_add(1);
-    (2,)
+    (1,)
 }
 
-pub fn op_clc(c: &mut Chip) -> (u8,) {
-    let mut sreg = c.sreg_get();
-    sreg.c = false;
-    c.sreg_set(&sreg);
+pub fn op_bset(c: &mut Chip, s: u8) -> (u8,) {
+    if s > 7 {
+        panic!("BSET: Invalid SREG bit {}. Must be 0-7.", s);
+    }
+
+    c.ram[0x5F] |= 1 << s;
+    // SEI: the next instruction runs before any pending interrupt
+    if s == 7 {
+        c.interrupts.inhibit = true;
+    }
     c.pc = c.pc.wrapping_add(1);
     (1,)
 }
 
-pub fn op_clt(c: &mut Chip) -> (u8,) {
+pub fn op_bst(c: &mut Chip, rd: u8, b: u8) -> (u8,) {
+    if b > 7 {
+        panic!("BST: Invalid bit {}. Must be 0-7.", b);
+    }
+
     let mut sreg = c.sreg_get();
-    sreg.t = false;
+    sreg.t = (c.ram[rd as usize] >> b) & 1 == 1;
     c.sreg_set(&sreg);
     c.pc = c.pc.wrapping_add(1);
     (1,)
 }
 
-pub fn op_lsl(c: &mut Chip, rd: u8) -> (u8,) {
-    let d = rd as usize;
-    let val = c.ram[d];
-    let carry = (val >> 7) & 1 == 1;
-    let result = (val << 1) & 0xFF;
-    c.ram[d] = result;
-
-    let mut sreg = c.sreg_get();
-    sreg.c = carry;
-    sreg.h = (val >> 3) & 1 == 1;
-    sreg.n = (result >> 7) & 1 == 1;
-    sreg.z = result == 0;
-    sreg.v = sreg.n ^ sreg.c;
-    sreg.s = sreg.n ^ sreg.v;
-    c.sreg_set(&sreg);
+pub fn op_cbi(c: &mut Chip, a: u8, b: u8) -> (u8,) {
+    if a > 31 {
+        panic!("CBI: Invalid I/O Address {}. Must be 0-31.", a);
+    }
+    if b > 7 {
+        panic!("CBI: Invalid bit {}. Must be 0-7.", b);
+    }
 
+    let addr = (Chip::IO_OFFSET + a as u16) as usize;
+    let val = c.data_read(addr);
+    let mask = !(1 << b);
+    c.data_write(addr, val & mask);
     c.pc = c.pc.wrapping_add(1);
-    (1,)
+    (2,)
 }
 
-pub fn op_rol(c: &mut Chip, rd: u8) -> (u8,) {
-    let mut sreg = c.sreg_get();
+pub fn op_lsr(c: &mut Chip, rd: u8) -> (u8,) {
     let d = rd as usize;
     let val = c.ram[d];
-    let old_carry = if sreg.c { 1 } else { 0 };
-    let new_carry = (val >> 7) & 1 == 1;
-    let result = ((val << 1) | old_carry) & 0xFF;
+    let result = val >> 1;
     c.ram[d] = result;
 
-    sreg.c = new_carry;
-    sreg.h = (val >> 3) & 1 == 1;
-    sreg.n = (result >> 7) & 1 == 1;
+    let mut sreg = c.sreg_get();
+    sreg.c = (val & 1) == 1;
+    sreg.n = false;
     sreg.z = result == 0;
     sreg.v = sreg.n ^ sreg.c;
     sreg.s = sreg.n ^ sreg.v;
@@ -102,33 +132,122 @@ pub fn op_sbi(c: &mut Chip, a: u8, b: u8) -> (u8,) {
     }
 
     let addr = (Chip::IO_OFFSET + a as u16) as usize;
-    let val = c.ram[addr];
+    let val = c.data_read(addr);
     let mask = 1 << b;
-    c.ram[addr] = val | mask;
+    c.data_write(addr, val | mask);
     c.pc = c.pc.wrapping_add(1);
     (2,)
 }
 
-pub fn op_sec(c: &mut Chip) -> (u8,) {
-    let mut sreg = c.sreg_get();
-    sreg.c = true;
-    c.sreg_set(&sreg);
+pub fn op_swap(c: &mut Chip, rd: u8) -> (u8,) {
+    let d = rd as usize;
+    c.ram[d] = c.ram[d].rotate_left(4);
     c.pc = c.pc.wrapping_add(1);
     (1,)
 }
 
-pub fn op_sei(c: &mut Chip) -> (u8,) {
-    let mut sreg = c.sreg_get();
-    sreg.i = true;
-    c.sreg_set(&sreg);
-    c.pc = c.pc.wrapping_add(1);
-    (1,)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SREG: I | T | H | S | V | N | Z | C
+    const SREG: usize = 0x5F;
+    const C: u8 = 1 << 0;
+    const Z: u8 = 1 << 1;
+    const N: u8 = 1 << 2;
+    const V: u8 = 1 << 3;
+    const S: u8 = 1 << 4;
+    const T: u8 = 1 << 6;
+
+    const PORTB: u8 = 0x18;
+
+    fn chip(rd: u8, val: u8, sreg: u8) -> Chip {
+        let mut c = Chip::new();
+        c.ram[rd as usize] = val;
+        c.ram[SREG] = sreg;
+        c
+    }
 
-pub fn op_set(c: &mut Chip) -> (u8,) {
-    let mut sreg = c.sreg_get();
-    sreg.t = true;
-    c.sreg_set(&sreg);
-    c.pc = c.pc.wrapping_add(1);
-    (1,)
+    #[test]
+    fn lsr_shifts_into_carry() {
+        let mut c = chip(16, 0x81, N);
+        op_lsr(&mut c, 16);
+        assert_eq!(c.ram[16], 0x40);
+        assert_eq!(c.ram[SREG], S | V | C);
+
+        let mut c = chip(16, 0x01, 0);
+        op_lsr(&mut c, 16);
+        assert_eq!(c.ram[SREG], S | V | Z | C);
+    }
+
+    #[test]
+    fn asr_keeps_sign() {
+        let mut c = chip(16, 0x82, 0);
+        op_asr(&mut c, 16);
+        assert_eq!(c.ram[16], 0xC1);
+        assert_eq!(c.ram[SREG], V | N);
+    }
+
+    #[test]
+    fn ror_rotates_through_carry() {
+        let mut c = chip(16, 0x01, C);
+        op_ror(&mut c, 16);
+        assert_eq!(c.ram[16], 0x80);
+        assert_eq!(c.ram[SREG], S | N | C);
+
+        op_ror(&mut c, 16);
+        assert_eq!(c.ram[16], 0xC0);
+        assert_eq!(c.ram[SREG], V | N);
+    }
+
+    #[test]
+    fn swap_nibbles() {
+        let mut c = chip(16, 0x12, 0);
+        op_swap(&mut c, 16);
+        assert_eq!(c.ram[16], 0x21);
+        assert_eq!(c.ram[SREG], 0);
+    }
+
+    #[test]
+    fn bst_bld_copy_through_t() {
+        let mut c = chip(16, 0x20, 0);
+        op_bst(&mut c, 16, 5);
+        assert_eq!(c.ram[SREG], T);
+        op_bld(&mut c, 17, 0);
+        assert_eq!(c.ram[17], 0x01);
+
+        op_bst(&mut c, 16, 0);
+        assert_eq!(c.ram[SREG], 0);
+        op_bld(&mut c, 16, 5);
+        assert_eq!(c.ram[16], 0x00);
+    }
+
+    #[test]
+    fn bset_bclr() {
+        let mut c = chip(0, 0, 0);
+        for s in 0..8 {
+            op_bset(&mut c, s);
+        }
+        assert_eq!(c.ram[SREG], 0xFF);
+        op_bclr(&mut c, 1);
+        op_bclr(&mut c, 7);
+        assert_eq!(c.ram[SREG], 0x7D);
+    }
+
+    #[test]
+    fn sbi_cbi_io_bits() {
+        let mut c = chip(0, 0, 0);
+        assert_eq!(op_sbi(&mut c, PORTB, 0), (2,));
+        op_sbi(&mut c, PORTB, 7);
+        assert_eq!(c.ram[0x38], 0x81);
+        assert_eq!(op_cbi(&mut c, PORTB, 0), (2,));
+        assert_eq!(c.ram[0x38], 0x80);
+    }
+
+    #[test]
+    #[should_panic(expected = "Must be 0-31")]
+    fn sbi_only_reaches_lower_io() {
+        let mut c = chip(0, 0, 0);
+        op_sbi(&mut c, 32, 0);
+    }
 }
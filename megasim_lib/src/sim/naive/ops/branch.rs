@@ -1,58 +1,43 @@
 use crate::sim::naive::chip::Chip;
 
-pub fn op_brcc(c: &mut Chip, k: i8) -> (u8,) {
-    let sreg = c.sreg_get();
-    if !sreg.c {
-        c.pc = (c.pc as i32 + k as i32 + 1) as u16;
-        return (2,);
-    } else {
-        c.pc = c.pc.wrapping_add(1);
-        return (1,);
+pub fn op_brbc(c: &mut Chip, s: u8, k: i8) -> (u8,) {
+    if s > 7 {
+        panic!("BRBC: Invalid SREG bit {}. Must be 0-7.", s);
     }
-}
 
-pub fn op_breq(c: &mut Chip, k: i8) -> (u8,) {
-    let sreg = c.sreg_get();
-    if sreg.z {
+    let sreg = c.ram[0x5F];
+    if (sreg >> s) & 1 == 0 {
         c.pc = (c.pc as i32 + k as i32 + 1) as u16;
-        return (2,);
+        (2,)
     } else {
         c.pc = c.pc.wrapping_add(1);
-        return (1,);
+        (1,)
     }
 }
 
-pub fn op_brne(c: &mut Chip, k: i8) -> (u8,) {
-    let sreg = c.sreg_get();
-    if !sreg.z {
-        c.pc = (c.pc as i32 + k as i32 + 1) as u16;
-        return (2,);
-    } else {
-        c.pc = c.pc.wrapping_add(1);
-        return (1,);
+pub fn op_brbs(c: &mut Chip, s: u8, k: i8) -> (u8,) {
+    if s > 7 {
+        panic!("BRBS: Invalid SREG bit {}. Must be 0-7.", s);
     }
-}
 
-pub fn op_brtc(c: &mut Chip, k: i8) -> (u8,) {
-    let sreg = c.sreg_get();
-    if !sreg.t {
+    let sreg = c.ram[0x5F];
+    if (sreg >> s) & 1 == 1 {
         c.pc = (c.pc as i32 + k as i32 + 1) as u16;
-        return (2,);
+        (2,)
     } else {
         c.pc = c.pc.wrapping_add(1);
-        return (1,);
+        (1,)
     }
 }
 
-pub fn op_brts(c: &mut Chip, k: i8) -> (u8,) {
-    let sreg = c.sreg_get();
-    if sreg.t {
-        c.pc = (c.pc as i32 + k as i32 + 1) as u16;
-        return (2,);
-    } else {
-        c.pc = c.pc.wrapping_add(1);
-        return (1,);
-    }
+pub fn op_call(c: &mut Chip, k: u16) -> (u8,) {
+    let ret_addr = c.pc.wrapping_add(2);
+
+    c.push_byte((ret_addr & 0xFF) as u8);
+    c.push_byte(((ret_addr >> 8) & 0xFF) as u8);
+
+    c.pc = k;
+    (4,)
 }
 
 pub fn op_cpse(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
@@ -63,32 +48,46 @@ pub fn op_cpse(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
         let next_pc = c.pc.wrapping_add(1);
         let skip_size = c.get_instr_size(next_pc);
         c.pc = next_pc.wrapping_add(skip_size);
-        return if skip_size == 2 { (3,) } else { (2,) };
+        if skip_size == 2 { (3,) } else { (2,) }
     } else {
         c.pc = c.pc.wrapping_add(1);
-        return (1,);
+        (1,)
     }
 }
 
-pub fn op_rcall(c: &mut Chip, k: i16) -> (u8,) {
+pub fn op_icall(c: &mut Chip) -> (u8,) {
     let ret_addr = c.pc.wrapping_add(1);
 
-    c.ram[c.sp_get() as usize] = (ret_addr & 0xFF) as u8;
-    c.sp_add(-1);
+    c.push_byte((ret_addr & 0xFF) as u8);
+    c.push_byte(((ret_addr >> 8) & 0xFF) as u8);
+
+    c.pc = u16::from_le_bytes([c.ram[30], c.ram[31]]);
+    (3,)
+}
+
+pub fn op_ijmp(c: &mut Chip) -> (u8,) {
+    c.pc = u16::from_le_bytes([c.ram[30], c.ram[31]]);
+    (2,)
+}
+
+pub fn op_jmp(c: &mut Chip, k: u16) -> (u8,) {
+    c.pc = k;
+    (3,)
+}
+
+pub fn op_rcall(c: &mut Chip, k: i16) -> (u8,) {
+    let ret_addr = c.pc.wrapping_add(1);
 
-    c.ram[c.sp_get() as usize] = ((ret_addr >> 8) & 0xFF) as u8;
-    c.sp_add(-1);
+    c.push_byte((ret_addr & 0xFF) as u8);
+    c.push_byte(((ret_addr >> 8) & 0xFF) as u8);
 
     c.pc = (c.pc as i32 + k as i32 + 1) as u16;
     (3,)
 }
 
 pub fn op_ret(c: &mut Chip) -> (u8,) {
-    c.sp_add(1);
-    let high = c.ram[c.sp_get() as usize] as u16;
-
-    c.sp_add(1);
-    let low = c.ram[c.sp_get() as usize] as u16;
+    let high = c.pop_byte() as u16;
+    let low = c.pop_byte() as u16;
 
     let ret_addr = (high << 8) | low;
     c.pc = ret_addr;
@@ -96,11 +95,8 @@ pub fn op_ret(c: &mut Chip) -> (u8,) {
 }
 
 pub fn op_reti(c: &mut Chip) -> (u8,) {
-    c.sp_add(1);
-    let high = c.ram[c.sp_get() as usize] as u16;
-
-    c.sp_add(1);
-    let low = c.ram[c.sp_get() as usize] as u16;
+    let high = c.pop_byte() as u16;
+    let low = c.pop_byte() as u16;
 
     let ret_addr = (high << 8) | low;
     c.pc = ret_addr;
@@ -108,6 +104,8 @@ pub fn op_reti(c: &mut Chip) -> (u8,) {
     let mut sreg = c.sreg_get();
     sreg.i = true;
     c.sreg_set(&sreg);
+    // One instruction of the interrupted code runs before the next interrupt
+    c.interrupts.inhibit = true;
     (4,)
 }
 
@@ -116,6 +114,27 @@ pub fn op_rjmp(c: &mut Chip, k: i16) -> (u8,) {
     (2,)
 }
 
+pub fn op_sbic(c: &mut Chip, a: u8, b: u8) -> (u8,) {
+    if a > 31 {
+        panic!("SBIC: Invalid I/O Address {}. Must be 0-31.", a);
+    }
+    if b > 7 {
+        panic!("SBIC: Invalid bit {}. Must be 0-7.", b);
+    }
+
+    let io_val = c.data_read((Chip::IO_OFFSET + a as u16) as usize);
+
+    if (io_val >> b) & 1 == 0 {
+        let next_pc = c.pc.wrapping_add(1);
+        let skip_size = c.get_instr_size(next_pc);
+        c.pc = next_pc.wrapping_add(skip_size);
+        if skip_size == 2 { (3,) } else { (2,) }
+    } else {
+        c.pc = c.pc.wrapping_add(1);
+        (1,)
+    }
+}
+
 pub fn op_sbis(c: &mut Chip, a: u8, b: u8) -> (u8,) {
     if a > 31 {
         panic!("SBIS: Invalid I/O Address {}. Must be 0-31.", a);
@@ -124,16 +143,16 @@ pub fn op_sbis(c: &mut Chip, a: u8, b: u8) -> (u8,) {
         panic!("SBIS: Invalid bit {}. Must be 0-7.", b);
     }
 
-    let io_val = c.ram[(Chip::IO_OFFSET + a as u16) as usize];
+    let io_val = c.data_read((Chip::IO_OFFSET + a as u16) as usize);
 
     if (io_val >> b) & 1 == 1 {
         let next_pc = c.pc.wrapping_add(1);
         let skip_size = c.get_instr_size(next_pc);
         c.pc = next_pc.wrapping_add(skip_size);
-        return if skip_size == 2 { (3,) } else { (2,) };
+        if skip_size == 2 { (3,) } else { (2,) }
     } else {
         c.pc = c.pc.wrapping_add(1);
-        return (1,);
+        (1,)
     }
 }
 
@@ -148,10 +167,10 @@ pub fn op_sbrc(c: &mut Chip, rr: u8, b: u8) -> (u8,) {
         let next_pc = c.pc.wrapping_add(1);
         let skip_size = c.get_instr_size(next_pc);
         c.pc = next_pc.wrapping_add(skip_size);
-        return if skip_size == 2 { (3,) } else { (2,) };
+        if skip_size == 2 { (3,) } else { (2,) }
     } else {
         c.pc = c.pc.wrapping_add(1);
-        return (1,);
+        (1,)
     }
 }
 
@@ -166,10 +185,156 @@ pub fn op_sbrs(c: &mut Chip, rr: u8, b: u8) -> (u8,) {
         let next_pc = c.pc.wrapping_add(1);
         let skip_size = c.get_instr_size(next_pc);
         c.pc = next_pc.wrapping_add(skip_size);
-        return if skip_size == 2 { (3,) } else { (2,) };
+        if skip_size == 2 { (3,) } else { (2,) }
     } else {
         c.pc = c.pc.wrapping_add(1);
-        return (1,);
+        (1,)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAMEND: u16 = 0x45F;
+
+    fn chip() -> Chip {
+        let mut c = Chip::new();
+        c.sp_set(RAMEND);
+        c
+    }
+
+    #[test]
+    fn call_pushes_address_after_both_words() {
+        let mut c = chip();
+        c.pc = 0x1234;
+        assert_eq!(op_call(&mut c, 0x1F00), (4,));
+        assert_eq!(c.pc, 0x1F00);
+        assert_eq!(c.sp_get(), RAMEND - 2);
+        assert_eq!(c.ram[RAMEND as usize], 0x36);
+        assert_eq!(c.ram[RAMEND as usize - 1], 0x12);
+
+        assert_eq!(op_ret(&mut c), (4,));
+        assert_eq!(c.pc, 0x1236);
+        assert_eq!(c.sp_get(), RAMEND);
+    }
+
+    #[test]
+    fn call_with_stack_pointer_at_reset() {
+        // SP starts at 0, so the return address lands in r0 and unmapped
+        // data space instead of SRAM
+        let mut c = Chip::new();
+        c.pc = 0x1234;
+        assert_eq!(op_call(&mut c, 0x1F00), (4,));
+        assert_eq!(c.sp_get(), 0xFFFE);
+        assert_eq!(c.ram[0], 0x36);
+
+        assert_eq!(op_rcall(&mut c, -1), (3,));
+        assert_eq!(c.sp_get(), 0xFFFC);
+
+        assert_eq!(op_ret(&mut c), (4,));
+        assert_eq!(op_ret(&mut c), (4,));
+        assert_eq!(c.sp_get(), 0);
+        assert_eq!(c.pc, 0x36);
+    }
+
+    #[test]
+    fn jmp_reaches_all_of_flash() {
+        let mut c = chip();
+        assert_eq!(op_jmp(&mut c, 0x1FFF), (3,));
+        assert_eq!(c.pc, 0x1FFF);
+    }
+
+    #[test]
+    fn skips_two_word_instructions() {
+        let mut c = chip();
+        // CPSE R0, R1 followed by LDS R16, 0x0100
+        c.flash[1] = 0x9100;
+        c.flash[2] = 0x0100;
+        assert_eq!(op_cpse(&mut c, 0, 1), (3,));
+        assert_eq!(c.pc, 3);
+
+        // ... or a one-word NOP
+        let mut c = chip();
+        c.flash[1] = 0x0000;
+        assert_eq!(op_cpse(&mut c, 0, 1), (2,));
+        assert_eq!(c.pc, 2);
+
+        let mut c = chip();
+        c.ram[1] = 1;
+        assert_eq!(op_cpse(&mut c, 0, 1), (1,));
+        assert_eq!(c.pc, 1);
+    }
+
+    #[test]
+    fn brbs_brbc_follow_the_sreg_bit() {
+        // Z set
+        let mut c = chip();
+        c.ram[0x5F] = 1 << 1;
+        c.pc = 10;
+        assert_eq!(op_brbs(&mut c, 1, -5), (2,));
+        assert_eq!(c.pc, 6);
+
+        c.pc = 10;
+        assert_eq!(op_brbc(&mut c, 1, 20), (1,));
+        assert_eq!(c.pc, 11);
+
+        c.pc = 10;
+        assert_eq!(op_brbc(&mut c, 0, 63), (2,));
+        assert_eq!(c.pc, 74);
+
+        c.pc = 10;
+        assert_eq!(op_brbs(&mut c, 7, -64), (1,));
+        assert_eq!(c.pc, 11);
+    }
+
+    #[test]
+    #[should_panic(expected = "Must be 0-7")]
+    fn brbs_rejects_bit_8() {
+        let mut c = chip();
+        op_brbs(&mut c, 8, 0);
+    }
+
+    #[test]
+    fn sbic_sbis_test_io_bits() {
+        // PORTB bit 0 clear
+        let mut c = chip();
+        assert_eq!(op_sbic(&mut c, 0x18, 0), (2,));
+        assert_eq!(c.pc, 2);
+        c.pc = 0;
+        assert_eq!(op_sbis(&mut c, 0x18, 0), (1,));
+        assert_eq!(c.pc, 1);
+
+        c.ram[0x38] = 1;
+        c.pc = 0;
+        assert_eq!(op_sbis(&mut c, 0x18, 0), (2,));
+        assert_eq!(c.pc, 2);
+    }
+
+    #[test]
+    fn sbrc_sbrs_test_register_bits() {
+        let mut c = chip();
+        c.ram[16] = 0x80;
+        assert_eq!(op_sbrs(&mut c, 16, 7), (2,));
+        assert_eq!(c.pc, 2);
+        c.pc = 0;
+        assert_eq!(op_sbrc(&mut c, 16, 7), (1,));
+        assert_eq!(c.pc, 1);
+    }
+
+    #[test]
+    fn ijmp_icall_jump_to_z() {
+        let mut c = chip();
+        [c.ram[30], c.ram[31]] = [0x34, 0x12];
+        c.pc = 0x100;
+        assert_eq!(op_ijmp(&mut c), (2,));
+        assert_eq!(c.pc, 0x1234);
+        assert_eq!(c.sp_get(), RAMEND);
+
+        c.pc = 0x100;
+        assert_eq!(op_icall(&mut c), (3,));
+        assert_eq!(c.pc, 0x1234);
+        op_ret(&mut c);
+        assert_eq!(c.pc, 0x101);
+    }
+}
@@ -1,18 +1,67 @@
+use crate::compiler::{PTR_POST_INC, PTR_PRE_DEC};
 use crate::sim::naive::chip::Chip;
 
+// Resolves a pointer operand to a data space address, applying the
+// pre-decrement or post-increment to the pointer register pair.
+fn pointer_address(c: &mut Chip, name: &str, ptr: i64) -> usize {
+    let base = (ptr & 0xFF) as usize;
+    if !matches!(base, 26 | 28 | 30) {
+        panic!(
+            "{}: Invalid pointer register R{}. Must be X, Y or Z.",
+            name, base
+        );
+    }
+
+    let val = u16::from_le_bytes([c.ram[base], c.ram[base + 1]]);
+    let (addr, new_val) = match ptr & !0xFF {
+        0 => (val, val),
+        PTR_POST_INC => (val, val.wrapping_add(1)),
+        PTR_PRE_DEC => (val.wrapping_sub(1), val.wrapping_sub(1)),
+        _ => panic!("{}: Invalid pointer mode {}.", name, ptr >> 8),
+    };
+    [c.ram[base], c.ram[base + 1]] = new_val.to_le_bytes();
+
+    addr as usize
+}
+
+fn displaced_address(c: &mut Chip, name: &str, ptr: i64, q: u8) -> usize {
+    if ptr != 28 && ptr != 30 {
+        panic!("{}: Invalid pointer. Must be Y or Z.", name);
+    }
+    if q > 63 {
+        panic!("{}: Invalid displacement {}. Must be 0-63.", name, q);
+    }
+
+    pointer_address(c, name, ptr) + q as usize
+}
+
 pub fn op_in(c: &mut Chip, rd: u8, a: u8) -> (u8,) {
     if a > 63 {
         panic!("IN: Invalid I/O Address {}. Must be 0-63.", a);
     }
 
-    let val = c.ram[(Chip::IO_OFFSET + a as u16) as usize];
+    let val = c.data_read((Chip::IO_OFFSET + a as u16) as usize);
     c.ram[rd as usize] = val;
     c.pc = c.pc.wrapping_add(1);
     (1,)
 }
 
+pub fn op_ld(c: &mut Chip, rd: u8, ptr: i64) -> (u8,) {
+    let addr = pointer_address(c, "LD", ptr);
+    c.ram[rd as usize] = c.data_read(addr);
+    c.pc = c.pc.wrapping_add(1);
+    (2,)
+}
+
+pub fn op_ldd(c: &mut Chip, rd: u8, ptr: i64, q: u8) -> (u8,) {
+    let addr = displaced_address(c, "LDD", ptr, q);
+    c.ram[rd as usize] = c.data_read(addr);
+    c.pc = c.pc.wrapping_add(1);
+    (2,)
+}
+
 pub fn op_ldi(c: &mut Chip, rd: u8, k: u8) -> (u8,) {
-    if rd < 16 || rd > 31 {
+    if !(16..=31).contains(&rd) {
         panic!("LDI: Invalid Register R{}. Must be R16-R31.", rd);
     }
 
@@ -21,26 +70,58 @@ pub fn op_ldi(c: &mut Chip, rd: u8, k: u8) -> (u8,) {
     (1,)
 }
 
+pub fn op_lds(c: &mut Chip, rd: u8, k: u16) -> (u8,) {
+    c.ram[rd as usize] = c.data_read(k as usize);
+    c.pc = c.pc.wrapping_add(2);
+    (2,)
+}
+
+pub fn op_lpm(c: &mut Chip, rd: u8, ptr: i64) -> (u8,) {
+    if ptr & 0xFF != 30 || ptr & PTR_PRE_DEC != 0 {
+        panic!("LPM: Invalid pointer. Must be Z or Z+.");
+    }
+
+    let z = pointer_address(c, "LPM", ptr);
+    let word = c.flash[(z >> 1) % c.flash.len()];
+    c.ram[rd as usize] = if z & 1 == 1 {
+        (word >> 8) as u8
+    } else {
+        word as u8
+    };
+    c.pc = c.pc.wrapping_add(1);
+    (3,)
+}
+
 pub fn op_mov(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
     c.ram[rd as usize] = c.ram[rr as usize];
     c.pc = c.pc.wrapping_add(1);
     (1,)
 }
 
+pub fn op_movw(c: &mut Chip, rd: u8, rr: u8) -> (u8,) {
+    if rd & 1 != 0 || rd > 30 || rr & 1 != 0 || rr > 30 {
+        panic!("MOVW: Invalid registers R{}, R{}. Must be even.", rd, rr);
+    }
+
+    c.ram[rd as usize] = c.ram[rr as usize];
+    c.ram[rd as usize + 1] = c.ram[rr as usize + 1];
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
 pub fn op_out(c: &mut Chip, a: u8, rr: u8) -> (u8,) {
     if a > 63 {
         panic!("OUT: Invalid I/O Address {}. Must be 0-63.", a);
     }
 
     let val = c.ram[rr as usize];
-    c.ram[(Chip::IO_OFFSET + a as u16) as usize] = val;
+    c.data_write((Chip::IO_OFFSET + a as u16) as usize, val);
     c.pc = c.pc.wrapping_add(1);
     (1,)
 }
 
 pub fn op_pop(c: &mut Chip, rd: u8) -> (u8,) {
-    c.sp_add(1);
-    let val = c.ram[c.sp_get() as usize];
+    let val = c.pop_byte();
     c.ram[rd as usize] = val;
     c.pc = c.pc.wrapping_add(1);
     (2,)
@@ -48,8 +129,268 @@ pub fn op_pop(c: &mut Chip, rd: u8) -> (u8,) {
 
 pub fn op_push(c: &mut Chip, rr: u8) -> (u8,) {
     let val = c.ram[rr as usize];
-    c.ram[c.sp_get() as usize] = val;
-    c.sp_add(-1);
+    c.push_byte(val);
     c.pc = c.pc.wrapping_add(1);
     (2,)
 }
+
+// SPMCR (0x57): SPMIE | RWWSB | - | RWWSRE | BLBSET | PGWRT | PGERS | SPMEN
+pub fn op_spm(c: &mut Chip) -> (u8,) {
+    const SPMCR: usize = 0x57;
+    const PAGE_WORDS: usize = 64;
+
+    let spmcr = c.ram[SPMCR];
+    let z = u16::from_le_bytes([c.ram[30], c.ram[31]]) as usize;
+    let word_addr = (z >> 1) % c.flash.len();
+    let page = word_addr & !(PAGE_WORDS - 1);
+
+    match spmcr & 0x1F {
+        // Fill the temporary page buffer with R1:R0
+        0b00001 => {
+            c.spm_buffer[word_addr % PAGE_WORDS] = u16::from_le_bytes([c.ram[0], c.ram[1]]);
+        }
+        // Page erase
+        0b00011 => {
+            c.flash[page..page + PAGE_WORDS].fill(0xFFFF);
+            c.ram[SPMCR] |= 1 << 6;
+        }
+        // Page write; programming can only clear bits, so unerased words
+        // end up as the AND of old and new contents.
+        0b00101 => {
+            for i in 0..PAGE_WORDS {
+                c.flash[page + i] &= c.spm_buffer[i];
+            }
+            c.spm_buffer = [0xFFFF; PAGE_WORDS];
+            c.ram[SPMCR] |= 1 << 6;
+        }
+        // Re-enable the RWW section
+        0b10001 => {
+            c.ram[SPMCR] &= !(1 << 6);
+        }
+        // Boot lock bits are not modelled
+        0b01001 => {}
+        _ => {}
+    }
+
+    // SPMEN and the operation bits clear once the operation completes
+    c.ram[SPMCR] &= !0x1F;
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+pub fn op_st(c: &mut Chip, ptr: i64, rr: u8) -> (u8,) {
+    let val = c.ram[rr as usize];
+    let addr = pointer_address(c, "ST", ptr);
+    c.data_write(addr, val);
+    c.pc = c.pc.wrapping_add(1);
+    (2,)
+}
+
+pub fn op_sts(c: &mut Chip, k: u16, rr: u8) -> (u8,) {
+    c.data_write(k as usize, c.ram[rr as usize]);
+    c.pc = c.pc.wrapping_add(2);
+    (2,)
+}
+
+pub fn op_std(c: &mut Chip, ptr: i64, q: u8, rr: u8) -> (u8,) {
+    let val = c.ram[rr as usize];
+    let addr = displaced_address(c, "STD", ptr, q);
+    c.data_write(addr, val);
+    c.pc = c.pc.wrapping_add(1);
+    (2,)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::naive::chip::State;
+
+    const X: i64 = 26;
+    const Y: i64 = 28;
+    const Z: i64 = 30;
+
+    fn set_pointer(c: &mut Chip, ptr: i64, addr: u16) {
+        let base = ptr as usize;
+        [c.ram[base], c.ram[base + 1]] = addr.to_le_bytes();
+    }
+
+    fn pointer(c: &Chip, ptr: i64) -> u16 {
+        let base = ptr as usize;
+        u16::from_le_bytes([c.ram[base], c.ram[base + 1]])
+    }
+
+    #[test]
+    fn st_post_increment() {
+        let mut c = Chip::new();
+        set_pointer(&mut c, X, 0x60);
+        c.ram[16] = 1;
+        assert_eq!(op_st(&mut c, X | PTR_POST_INC, 16), (2,));
+        c.ram[16] = 2;
+        op_st(&mut c, X | PTR_POST_INC, 16);
+
+        assert_eq!(c.ram[0x60..0x62], [1, 2]);
+        assert_eq!(pointer(&c, X), 0x62);
+        assert_eq!(c.pc, 2);
+    }
+
+    #[test]
+    fn ld_pre_decrement() {
+        let mut c = Chip::new();
+        c.ram[0x61] = 0x42;
+        set_pointer(&mut c, Y, 0x62);
+        op_ld(&mut c, 17, Y | PTR_PRE_DEC);
+
+        assert_eq!(c.ram[17], 0x42);
+        assert_eq!(pointer(&c, Y), 0x61);
+    }
+
+    #[test]
+    fn ld_plain_keeps_pointer() {
+        let mut c = Chip::new();
+        c.ram[0x70] = 7;
+        set_pointer(&mut c, Z, 0x70);
+        op_ld(&mut c, 0, Z);
+
+        assert_eq!(c.ram[0], 7);
+        assert_eq!(pointer(&c, Z), 0x70);
+    }
+
+    #[test]
+    fn ldd_std_displacement() {
+        let mut c = Chip::new();
+        set_pointer(&mut c, Y, 0x100);
+        c.ram[18] = 0x5A;
+        op_std(&mut c, Y, 63, 18);
+        assert_eq!(c.ram[0x13F], 0x5A);
+
+        op_ldd(&mut c, 19, Y, 63);
+        assert_eq!(c.ram[19], 0x5A);
+        assert_eq!(pointer(&c, Y), 0x100);
+    }
+
+    #[test]
+    fn pointer_reaches_io_registers() {
+        // PORTB through its data address
+        let mut c = Chip::new();
+        set_pointer(&mut c, Z, 0x38);
+        c.ram[16] = 0xA5;
+        op_st(&mut c, Z, 16);
+        assert_eq!(c.ram[0x38], 0xA5);
+    }
+
+    #[test]
+    fn movw_copies_pair() {
+        let mut c = Chip::new();
+        set_pointer(&mut c, Z, 0x1234);
+        op_movw(&mut c, 24, 30);
+        assert_eq!(pointer(&c, 24), 0x1234);
+    }
+
+    #[test]
+    fn push_pop_with_stack_pointer_at_reset() {
+        // Without SP set up, pushes past the first one fall into unmapped
+        // data space
+        let mut c = Chip::with_program(
+            "ldi r16, 0x42\npush r16\npush r16\npush r16\npop r17\npop r18\npop r19\nbreak\n",
+        );
+        c.run(100);
+        assert_eq!(c.state, State::Break);
+        assert_eq!(c.sp_get(), 0);
+        assert_eq!(
+            (c.ram[0], c.ram[17], c.ram[18], c.ram[19]),
+            (0x42, 0, 0, 0x42)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Must be Y or Z")]
+    fn ldd_rejects_x() {
+        let mut c = Chip::new();
+        op_ldd(&mut c, 0, X, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Must be 0-63")]
+    fn ldd_rejects_large_displacement() {
+        let mut c = Chip::new();
+        op_ldd(&mut c, 0, Y, 64);
+    }
+
+    #[test]
+    fn lds_sts_take_two_words() {
+        let mut c = Chip::new();
+        c.ram[16] = 0x99;
+        assert_eq!(op_sts(&mut c, 0x45F, 16), (2,));
+        assert_eq!(c.ram[0x45F], 0x99);
+        assert_eq!(c.pc, 2);
+
+        assert_eq!(op_lds(&mut c, 17, 0x45F), (2,));
+        assert_eq!(c.ram[17], 0x99);
+        assert_eq!(c.pc, 4);
+    }
+
+    #[test]
+    fn lds_sts_unmapped_addresses() {
+        let mut c = Chip::new();
+        c.ram[16] = 0x99;
+        op_sts(&mut c, 0xFFFF, 16);
+        op_lds(&mut c, 16, 0x0460);
+        assert_eq!(c.ram[16], 0);
+    }
+
+    const SPMCR: usize = 0x57;
+
+    #[test]
+    fn lpm_reads_bytes_low_first() {
+        let mut c = Chip::new();
+        c.flash[0x100] = 0x0201;
+        set_pointer(&mut c, Z, 0x200);
+        assert_eq!(op_lpm(&mut c, 16, Z | PTR_POST_INC), (3,));
+        op_lpm(&mut c, 17, Z);
+        assert_eq!((c.ram[16], c.ram[17]), (0x01, 0x02));
+        assert_eq!(pointer(&c, Z), 0x201);
+
+        // Implied R0 form
+        op_lpm(&mut c, 0, Z);
+        assert_eq!(c.ram[0], 0x02);
+    }
+
+    fn spm(c: &mut Chip, z: u16, spmcr: u8) {
+        set_pointer(c, Z, z);
+        c.ram[SPMCR] = spmcr;
+        op_spm(c);
+    }
+
+    #[test]
+    fn spm_erases_and_writes_a_page() {
+        let mut c = Chip::new();
+        c.flash[0x1000..0x1040].fill(0x0000);
+
+        // Page erase
+        spm(&mut c, 0x2000, 0b00011);
+        assert!(c.flash[0x1000..0x1040].iter().all(|w| *w == 0xFFFF));
+        assert_eq!(c.ram[SPMCR], 1 << 6);
+
+        // Fill the buffer, then write the page
+        [c.ram[0], c.ram[1]] = [0x34, 0x12];
+        spm(&mut c, 0x2002, 0b00001);
+        assert_eq!(c.flash[0x1001], 0xFFFF);
+        spm(&mut c, 0x2000, 0b00101);
+        assert_eq!(c.flash[0x1001], 0x1234);
+        assert_eq!(c.flash[0x1000], 0xFFFF);
+
+        // Re-enable the RWW section
+        spm(&mut c, 0, 0b10001);
+        assert_eq!(c.ram[SPMCR], 0);
+    }
+
+    #[test]
+    fn spm_write_without_erase_ands_words() {
+        let mut c = Chip::new();
+        c.flash[0] = 0x0FF0;
+        [c.ram[0], c.ram[1]] = [0x0F, 0xFF];
+        spm(&mut c, 0, 0b00001);
+        spm(&mut c, 0, 0b00101);
+        assert_eq!(c.flash[0], 0x0F00);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::sim::naive::chip::{Chip, SleepMode, State};
+
+pub fn op_break(c: &mut Chip) -> (u8,) {
+    c.pc = c.pc.wrapping_add(1);
+    c.state = State::Break;
+    (1,)
+}
+
+pub fn op_nop(c: &mut Chip) -> (u8,) {
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+// MCUCR (0x55): SM2 | SE | SM1 | SM0 | ISC11 | ISC10 | ISC01 | ISC00
+pub fn op_sleep(c: &mut Chip) -> (u8,) {
+    let mcucr = c.ram[0x55];
+    c.pc = c.pc.wrapping_add(1);
+
+    if (mcucr >> 6) & 1 == 0 {
+        return (1,);
+    }
+
+    let sm = ((mcucr >> 5) & 0b100) | ((mcucr >> 4) & 0b011);
+    let mode = match sm {
+        0b000 => SleepMode::Idle,
+        0b001 => SleepMode::AdcNoiseReduction,
+        0b010 => SleepMode::PowerDown,
+        0b011 => SleepMode::PowerSave,
+        0b110 => SleepMode::Standby,
+        0b111 => SleepMode::ExtendedStandby,
+        // Reserved modes do not enter sleep
+        _ => return (1,),
+    };
+    c.state = State::Sleeping(mode);
+    (1,)
+}
+
+pub fn op_wdr(c: &mut Chip) -> (u8,) {
+    c.wdt_elapsed = 0;
+    c.pc = c.pc.wrapping_add(1);
+    (1,)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MCUCR: usize = 0x55;
+
+    fn sleep(mcucr: u8) -> Chip {
+        let mut c = Chip::new();
+        c.ram[MCUCR] = mcucr;
+        assert_eq!(op_sleep(&mut c), (1,));
+        assert_eq!(c.pc, 1);
+        c
+    }
+
+    #[test]
+    fn sleep_needs_se() {
+        assert_eq!(sleep(0b0011_0000).state, State::Running);
+    }
+
+    #[test]
+    fn sleep_modes() {
+        let modes = [
+            (0b0100_0000, SleepMode::Idle),
+            (0b0101_0000, SleepMode::AdcNoiseReduction),
+            (0b0110_0000, SleepMode::PowerDown),
+            (0b0111_0000, SleepMode::PowerSave),
+            (0b1110_0000, SleepMode::Standby),
+            (0b1111_0000, SleepMode::ExtendedStandby),
+        ];
+        for (mcucr, mode) in modes {
+            assert_eq!(sleep(mcucr).state, State::Sleeping(mode));
+        }
+    }
+
+    #[test]
+    fn reserved_sleep_modes_do_nothing() {
+        assert_eq!(sleep(0b1100_0000).state, State::Running);
+        assert_eq!(sleep(0b1101_0000).state, State::Running);
+    }
+
+    #[test]
+    fn break_halts() {
+        let mut c = Chip::new();
+        op_break(&mut c);
+        assert_eq!((c.state, c.pc), (State::Break, 1));
+    }
+
+    #[test]
+    fn wdr_restarts_watchdog() {
+        let mut c = Chip::new();
+        c.wdt_elapsed = 1_000;
+        op_wdr(&mut c);
+        assert_eq!(c.wdt_elapsed, 0);
+    }
+}
@@ -1,5 +1,5 @@
 pub mod arithmetic_and_logic;
-pub mod branch;
 pub mod bit_and_bittest;
+pub mod branch;
 pub mod data_transfer;
-pub mod mcu_control;
\ No newline at end of file
+pub mod mcu_control;
@@ -0,0 +1,439 @@
+use crate::sim::naive::chip::{Chip, SleepMode, State};
+use crate::sim::naive::peripherals::{
+    SFIOR, TIFR,
+    analog::{self, AnalogPin},
+};
+
+//
+// Analog to Digital Converter (Page 201)
+//
+
+const ADCL: usize = 0x24;
+const ADCH: usize = 0x25;
+const ADCSRA: usize = 0x26;
+const ADMUX: usize = 0x27;
+
+const ACSR: usize = 0x28;
+const GIFR: usize = 0x5A;
+
+// ADMUX: REFS1 | REFS0 | ADLAR | MUX4 | MUX3 | MUX2 | MUX1 | MUX0
+const REFS0: u8 = 6;
+const ADLAR: u8 = 5;
+
+// ADCSRA: ADEN | ADSC | ADATE | ADIF | ADIE | ADPS2 | ADPS1 | ADPS0
+const ADEN: u8 = 7;
+const ADSC: u8 = 6;
+const ADATE: u8 = 5;
+const ADIF: u8 = 4;
+
+// SFIOR: ADTS2 | ADTS1 | ADTS0 | - | ACME | PUD | PSR2 | PSR10
+const ADTS0: u8 = 5;
+
+// ADC clock divisors for ADPS2:0 (Table 85)
+const ADC_PRESCALER: [u64; 8] = [2, 2, 4, 8, 16, 32, 64, 128];
+
+// ADC clocks of a conversion, and of the first one after enabling the ADC,
+// which initialises the analog circuitry
+const CONVERSION_CLOCKS: u64 = 13;
+const FIRST_CONVERSION_CLOCKS: u64 = 25;
+
+const INTERNAL_REFERENCE: f64 = 2.56;
+pub(crate) const BANDGAP: f64 = 1.22;
+
+#[derive(Debug, Clone, Copy)]
+enum Input {
+    Single(AnalogPin),
+    Differential(AnalogPin, AnalogPin, f64),
+    Bandgap,
+    Ground,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Conversion {
+    // ADMUX when the conversion started
+    admux: u8,
+    // Cycles since the start, until the sample and hold, and until the end
+    elapsed: u64,
+    sample_at: u64,
+    cycles: u64,
+    sample: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Adc {
+    conversion: Option<Conversion>,
+    // Enabled since the last conversion, the next one takes 25 ADC clocks
+    initialised: bool,
+    // Last conversion result, laid out in ADCH/ADCL by ADLAR
+    result: u16,
+    // ADCL was read, the data registers are not updated until ADCH is read
+    locked: bool,
+    // Level of the auto trigger source at the last tick
+    prev_trigger: bool,
+    // Sleeping in ADC noise reduction mode at the last tick
+    noise_reduction: bool,
+}
+
+fn bit(val: u8, bit: u8) -> bool {
+    (val >> bit) & 1 != 0
+}
+
+fn adc(n: u8) -> AnalogPin {
+    AnalogPin::Adc(n)
+}
+
+// Input selection by MUX4:0 (Table 84).
+fn input(mux: u8) -> Input {
+    match mux & 0x1F {
+        n @ 0x00..=0x07 => Input::Single(adc(n)),
+        // Positive ADC0/1 against ADC0, and ADC2/3 against ADC2, gain 10x or
+        // 200x
+        n @ 0x08..=0x0F => {
+            let neg = if n < 0x0C { 0 } else { 2 };
+            let gain = if n & 0b10 == 0 { 10.0 } else { 200.0 };
+            Input::Differential(adc(neg + (n & 1)), adc(neg), gain)
+        }
+        n @ 0x10..=0x17 => Input::Differential(adc(n & 0b111), adc(1), 1.0),
+        n @ 0x18..=0x1D => Input::Differential(adc(n & 0b111), adc(2), 1.0),
+        0x1E => Input::Bandgap,
+        _ => Input::Ground,
+    }
+}
+
+// Reference voltage by REFS1:0. The reserved setting is taken as AREF.
+fn reference(c: &Chip, admux: u8) -> f64 {
+    match (admux >> REFS0) & 0b11 {
+        0b01 => analog::voltage(c, AnalogPin::Avcc),
+        0b11 => INTERNAL_REFERENCE,
+        _ => analog::voltage(c, AnalogPin::Aref),
+    }
+}
+
+// Converts the selected input. Single ended results are 0-1023, differential
+// ones 10-bit two's complement.
+fn sample(c: &Chip, admux: u8) -> u16 {
+    let vref = reference(c, admux);
+    let single = |volts: f64| (volts * 1024.0 / vref).floor().clamp(0.0, 1023.0) as u16;
+
+    match input(admux) {
+        Input::Single(pin) => single(analog::voltage(c, pin)),
+        Input::Differential(pos, neg, gain) => {
+            let volts = analog::voltage(c, pos) - analog::voltage(c, neg);
+            let result = (volts * gain * 512.0 / vref).floor().clamp(-512.0, 511.0);
+            (result as i16 as u16) & 0x3FF
+        }
+        Input::Bandgap => single(BANDGAP),
+        Input::Ground => 0,
+    }
+}
+
+// Lays the result out in ADCH/ADCL.
+fn update_data(c: &mut Chip) {
+    let result = c.adc.result;
+    let (high, low) = if bit(c.ram[ADMUX], ADLAR) {
+        (result >> 2, (result & 0b11) << 6)
+    } else {
+        (result >> 8, result & 0xFF)
+    };
+    c.ram[ADCH] = high as u8;
+    c.ram[ADCL] = low as u8;
+}
+
+fn start(c: &mut Chip) {
+    let divisor = ADC_PRESCALER[(c.ram[ADCSRA] & 0b111) as usize];
+    let clocks = if c.adc.initialised {
+        CONVERSION_CLOCKS
+    } else {
+        FIRST_CONVERSION_CLOCKS
+    };
+    // The input is sampled 1.5 ADC clocks into a conversion, or 13.5 into
+    // the first one
+    let sample_clocks = clocks - CONVERSION_CLOCKS + 1;
+
+    c.adc.initialised = true;
+    c.adc.conversion = Some(Conversion {
+        admux: c.ram[ADMUX],
+        elapsed: 0,
+        sample_at: sample_clocks * divisor + divisor / 2,
+        cycles: clocks * divisor,
+        sample: None,
+    });
+    c.ram[ADCSRA] |= 1 << ADSC;
+}
+
+fn auto_trigger_source(c: &Chip) -> u8 {
+    (c.ram[SFIOR] >> ADTS0) & 0b111
+}
+
+fn free_running(c: &Chip) -> bool {
+    bit(c.ram[ADCSRA], ADATE) && auto_trigger_source(c) == 0
+}
+
+// Level of the auto trigger source, an interrupt flag (Table 86).
+fn trigger_level(c: &Chip) -> bool {
+    match auto_trigger_source(c) {
+        // Analog comparator, ACI
+        1 => bit(c.ram[ACSR], 4),
+        // External interrupt request 0, INTF0
+        2 => bit(c.ram[GIFR], 6),
+        // Timer0 compare match, OCF0
+        3 => bit(c.ram[TIFR], 1),
+        // Timer0 overflow, TOV0
+        4 => bit(c.ram[TIFR], 0),
+        // Timer1 compare match B, OCF1B
+        5 => bit(c.ram[TIFR], 3),
+        // Timer1 overflow, TOV1
+        6 => bit(c.ram[TIFR], 2),
+        // Timer1 capture event, ICF1
+        7 => bit(c.ram[TIFR], 5),
+        // Free running is handled on completion
+        _ => false,
+    }
+}
+
+fn complete(c: &mut Chip, conversion: Conversion) {
+    c.adc.conversion = None;
+    c.ram[ADCSRA] &= !(1 << ADSC);
+
+    // A result is lost if ADCL was read but ADCH not yet
+    if !c.adc.locked {
+        c.adc.result = conversion
+            .sample
+            .unwrap_or_else(|| sample(c, conversion.admux));
+        update_data(c);
+    }
+    c.ram[ADCSRA] |= 1 << ADIF;
+
+    if free_running(c) {
+        start(c);
+    }
+}
+
+pub(crate) fn reset(c: &mut Chip) {
+    c.adc = Adc::default();
+}
+
+// Runs the ADC for `cycles` cycles. clk_ADC keeps running in idle and ADC
+// noise reduction sleep.
+pub(crate) fn tick(c: &mut Chip, cycles: u64) {
+    let noise_reduction = c.state == State::Sleeping(SleepMode::AdcNoiseReduction);
+    let entered_noise_reduction = noise_reduction && !c.adc.noise_reduction;
+    c.adc.noise_reduction = noise_reduction;
+
+    if !matches!(
+        c.state,
+        State::Running | State::Sleeping(SleepMode::Idle | SleepMode::AdcNoiseReduction)
+    ) || !bit(c.ram[ADCSRA], ADEN)
+    {
+        return;
+    }
+
+    let trigger = trigger_level(c);
+    let triggered = trigger && !c.adc.prev_trigger && bit(c.ram[ADCSRA], ADATE);
+    c.adc.prev_trigger = trigger;
+
+    // Entering ADC noise reduction mode starts a conversion
+    if c.adc.conversion.is_none() && (triggered || entered_noise_reduction) {
+        start(c);
+    }
+
+    let Some(mut conversion) = c.adc.conversion else {
+        return;
+    };
+
+    conversion.elapsed += cycles;
+    if conversion.sample.is_none() && conversion.elapsed >= conversion.sample_at {
+        conversion.sample = Some(sample(c, conversion.admux));
+    }
+
+    if conversion.elapsed >= conversion.cycles {
+        complete(c, conversion);
+    } else {
+        c.adc.conversion = Some(conversion);
+    }
+}
+
+// Reading ADCL locks the data registers until ADCH is read.
+pub(crate) fn io_read(c: &mut Chip, addr: usize) -> Option<u8> {
+    match addr {
+        ADCL => c.adc.locked = true,
+        ADCH => c.adc.locked = false,
+        _ => return None,
+    }
+
+    Some(c.ram[addr])
+}
+
+pub(crate) fn io_write(c: &mut Chip, addr: usize, val: u8) -> bool {
+    match addr {
+        // ADLAR takes effect on the data registers at once
+        ADMUX => {
+            c.ram[ADMUX] = val;
+            update_data(c);
+        }
+        ADCSRA => {
+            let adif = c.ram[ADCSRA] & (1 << ADIF);
+            let adsc = c.ram[ADCSRA] & (1 << ADSC);
+            c.ram[ADCSRA] = (val & !(1 << ADIF | 1 << ADSC)) | adsc | adif;
+
+            // ADIF is cleared by writing a one to it
+            if bit(val, ADIF) {
+                c.ram[ADCSRA] &= !(1 << ADIF);
+            }
+
+            if !bit(val, ADEN) {
+                // Disabling the ADC aborts a conversion
+                c.adc.conversion = None;
+                c.adc.initialised = false;
+                c.ram[ADCSRA] &= !(1 << ADSC);
+            } else if bit(val, ADSC) && c.adc.conversion.is_none() {
+                start(c);
+            }
+        }
+        // Data registers are read only
+        ADCL | ADCH => {}
+        _ => return false,
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::naive::peripherals::analog::AnalogSource;
+
+    // AVCC reference, ADC clock at clk/2
+    const AVCC: u8 = 1 << REFS0;
+    const START: u8 = 1 << ADEN | 1 << ADSC | 0b001;
+
+    fn adc_chip(volts: f64) -> Chip {
+        let mut c = Chip::new();
+        c.set_analog_input(adc(0), AnalogSource::Constant(volts));
+        c
+    }
+
+    fn result(c: &Chip) -> u16 {
+        u16::from_le_bytes([c.ram[ADCL], c.ram[ADCH]])
+    }
+
+    // Result of a first conversion with `admux`.
+    fn convert(c: &mut Chip, admux: u8) -> u16 {
+        c.data_write(ADMUX, admux);
+        c.data_write(ADCSRA, START);
+        tick(c, 50);
+        result(c)
+    }
+
+    #[test]
+    fn first_conversion_takes_longer() {
+        let mut c = adc_chip(2.5);
+        c.data_write(ADMUX, AVCC);
+        c.data_write(ADCSRA, START);
+        tick(&mut c, 2 * 25 - 1);
+        assert_eq!(c.ram[ADCSRA], START);
+        tick(&mut c, 1);
+        assert_eq!(c.ram[ADCSRA], 1 << ADEN | 1 << ADIF | 0b001);
+        assert_eq!(result(&c), 512);
+
+        c.data_write(ADCSRA, START | 1 << ADIF);
+        tick(&mut c, 2 * 13);
+        assert_eq!(c.ram[ADCSRA], 1 << ADEN | 1 << ADIF | 0b001);
+    }
+
+    #[test]
+    fn left_adjusted_result() {
+        let mut c = adc_chip(5.0 * 1023.0 / 1024.0);
+        c.data_write(ADMUX, AVCC);
+        c.data_write(ADCSRA, START);
+        tick(&mut c, 50);
+        assert_eq!((c.ram[ADCH], c.ram[ADCL]), (0x03, 0xFF));
+
+        c.data_write(ADMUX, AVCC | 1 << ADLAR);
+        assert_eq!((c.ram[ADCH], c.ram[ADCL]), (0xFF, 0xC0));
+    }
+
+    #[test]
+    fn input_selection() {
+        let mut c = adc_chip(2.5);
+
+        // ADC1 - ADC0, 10x
+        c.set_analog_input(adc(1), AnalogSource::Constant(2.52));
+        assert_eq!(convert(&mut c, AVCC | 0x09), 20);
+        c.set_analog_input(adc(1), AnalogSource::Constant(2.48));
+        assert_eq!(convert(&mut c, AVCC | 0x09), 0x3EB);
+
+        // Bandgap against the internal 2.56 V reference
+        assert_eq!(convert(&mut c, 0b11 << REFS0 | 0x1E), 488);
+        assert_eq!(convert(&mut c, AVCC | 0x1F), 0);
+    }
+
+    #[test]
+    fn input_is_sampled_early() {
+        let mut c = adc_chip(1.25);
+        c.data_write(ADMUX, AVCC);
+        c.data_write(ADCSRA, START);
+        // 13.5 ADC clocks into the first conversion
+        tick(&mut c, 27);
+        c.set_analog_input(adc(0), AnalogSource::Constant(2.5));
+        tick(&mut c, 23);
+        assert_eq!(result(&c), 256);
+    }
+
+    #[test]
+    fn adcl_read_locks_the_result() {
+        let mut c = adc_chip(2.5);
+        c.data_write(ADMUX, AVCC);
+        c.data_write(ADCSRA, START);
+        tick(&mut c, 50);
+        assert_eq!(c.data_read(ADCL), 0x00);
+
+        c.set_analog_input(adc(0), AnalogSource::Constant(1.25));
+        c.data_write(ADCSRA, START);
+        tick(&mut c, 26);
+        assert_eq!(c.data_read(ADCH), 0x02);
+
+        c.data_write(ADCSRA, START);
+        tick(&mut c, 26);
+        assert_eq!(result(&c), 256);
+    }
+
+    #[test]
+    fn free_running() {
+        let mut c = adc_chip(2.5);
+        c.data_write(ADCSRA, START | 1 << ADATE);
+        tick(&mut c, 50);
+        assert_eq!(
+            c.ram[ADCSRA] & (1 << ADSC | 1 << ADIF),
+            1 << ADSC | 1 << ADIF
+        );
+        assert!(c.adc.conversion.is_some());
+    }
+
+    #[test]
+    fn auto_trigger_on_timer0_overflow() {
+        let mut c = adc_chip(2.5);
+        c.ram[SFIOR] = 4 << ADTS0;
+        c.data_write(ADCSRA, 1 << ADEN | 1 << ADATE | 0b001);
+        tick(&mut c, 1);
+        assert!(c.adc.conversion.is_none());
+
+        c.ram[TIFR] = 1 << 0;
+        tick(&mut c, 1);
+        assert!(c.adc.conversion.is_some());
+    }
+
+    #[test]
+    fn noise_reduction_sleep_starts_a_conversion() {
+        let mut c = adc_chip(2.5);
+        c.data_write(ADCSRA, 1 << ADEN | 0b001);
+        c.state = State::Sleeping(SleepMode::AdcNoiseReduction);
+        tick(&mut c, 1);
+        assert_eq!(c.ram[ADCSRA] & 1 << ADSC, 1 << ADSC);
+
+        // Disabling the ADC aborts it
+        c.data_write(ADCSRA, 0);
+        assert!(c.adc.conversion.is_none());
+        assert_eq!(c.ram[ADCSRA], 0);
+    }
+}
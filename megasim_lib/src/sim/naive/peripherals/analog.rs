@@ -0,0 +1,179 @@
+use crate::sim::naive::chip::Chip;
+use std::{fmt, fs, path::Path, rc::Rc};
+
+//
+// Analog inputs driven by the host
+//
+// Each analog pin follows a source: a constant voltage, a function of the
+// simulated time, or a list of samples played back at a fixed rate. Pins that
+// are not driven read 0 V, AREF and AVCC default to 5 V.
+//
+
+const DEFAULT_SUPPLY: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnalogPin {
+    // ADC0-ADC7 on PA0-PA7
+    Adc(u8),
+    // Analog comparator inputs on PB2 and PB3
+    Ain0,
+    Ain1,
+    Aref,
+    Avcc,
+}
+
+impl AnalogPin {
+    fn index(self) -> usize {
+        match self {
+            AnalogPin::Adc(n) => (n & 0b111) as usize,
+            AnalogPin::Ain0 => 8,
+            AnalogPin::Ain1 => 9,
+            AnalogPin::Aref => 10,
+            AnalogPin::Avcc => 11,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum AnalogSource {
+    // Volts
+    Constant(f64),
+    // Volts as a function of the simulated time in seconds
+    Waveform(Rc<dyn Fn(f64) -> f64>),
+    // Volts, `rate` samples per second. The last sample is held.
+    Samples { rate: f64, samples: Vec<f64> },
+}
+
+impl fmt::Debug for AnalogSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalogSource::Constant(volts) => f.debug_tuple("Constant").field(volts).finish(),
+            AnalogSource::Waveform(_) => f.write_str("Waveform"),
+            AnalogSource::Samples { rate, samples } => f
+                .debug_struct("Samples")
+                .field("rate", rate)
+                .field("samples", &samples.len())
+                .finish(),
+        }
+    }
+}
+
+impl Default for AnalogSource {
+    fn default() -> Self {
+        AnalogSource::Constant(0.0)
+    }
+}
+
+impl AnalogSource {
+    pub fn waveform(f: impl Fn(f64) -> f64 + 'static) -> Self {
+        AnalogSource::Waveform(Rc::new(f))
+    }
+
+    // Parses samples, one voltage per line. Blank lines and lines starting
+    // with `#` are skipped.
+    pub fn parse_samples(text: &str, rate: f64) -> Result<Self, String> {
+        let samples = text
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| {
+                line.parse::<f64>()
+                    .map_err(|e| format!("Line {}: {}: {}", i + 1, line, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(AnalogSource::Samples { rate, samples })
+    }
+
+    // Loads a sample file, in the format of `parse_samples`.
+    pub fn load_samples(path: &Path, rate: f64) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse_samples(&text, rate)
+    }
+
+    fn voltage(&self, seconds: f64) -> f64 {
+        match self {
+            AnalogSource::Constant(volts) => *volts,
+            AnalogSource::Waveform(f) => f(seconds),
+            AnalogSource::Samples { rate, samples } => {
+                let i = (seconds * rate) as usize;
+                samples
+                    .get(i)
+                    .or(samples.last())
+                    .copied()
+                    .unwrap_or_default()
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AnalogInputs {
+    sources: [AnalogSource; 12],
+    // Simulated time, in ns
+    time: u64,
+}
+
+impl Default for AnalogInputs {
+    fn default() -> Self {
+        let mut sources: [AnalogSource; 12] = Default::default();
+        sources[AnalogPin::Aref.index()] = AnalogSource::Constant(DEFAULT_SUPPLY);
+        sources[AnalogPin::Avcc.index()] = AnalogSource::Constant(DEFAULT_SUPPLY);
+
+        AnalogInputs { sources, time: 0 }
+    }
+}
+
+pub(crate) fn set_source(c: &mut Chip, pin: AnalogPin, source: AnalogSource) {
+    c.analog.sources[pin.index()] = source;
+}
+
+// Voltage on `pin` at the current simulated time.
+pub(crate) fn voltage(c: &Chip, pin: AnalogPin) -> f64 {
+    let seconds = c.analog.time as f64 / 1e9;
+    c.analog.sources[pin.index()].voltage(seconds)
+}
+
+pub(crate) fn tick(c: &mut Chip, time_delta: u64) {
+    c.analog.time += time_delta;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supplies_default_to_5v() {
+        let c = Chip::new();
+        assert_eq!(voltage(&c, AnalogPin::Aref), 5.0);
+        assert_eq!(voltage(&c, AnalogPin::Avcc), 5.0);
+        assert_eq!(voltage(&c, AnalogPin::Adc(3)), 0.0);
+    }
+
+    #[test]
+    fn sources_follow_simulated_time() {
+        let mut c = Chip::new();
+        set_source(
+            &mut c,
+            AnalogPin::Adc(0),
+            AnalogSource::waveform(|t| t * 2.0),
+        );
+        let samples = AnalogSource::parse_samples("# volts\n1.0\n\n2.0\n", 1000.0).unwrap();
+        set_source(&mut c, AnalogPin::Ain0, samples);
+
+        tick(&mut c, 500_000);
+        assert_eq!(voltage(&c, AnalogPin::Adc(0)), 0.001);
+        assert_eq!(voltage(&c, AnalogPin::Ain0), 1.0);
+
+        // The last sample is held
+        tick(&mut c, 5_000_000);
+        assert_eq!(voltage(&c, AnalogPin::Ain0), 2.0);
+    }
+
+    #[test]
+    fn sample_errors_name_the_line() {
+        let err = AnalogSource::parse_samples("1.0\n\nfive\n", 1.0).unwrap_err();
+        assert!(err.starts_with("Line 3: five: "), "{}", err);
+    }
+}
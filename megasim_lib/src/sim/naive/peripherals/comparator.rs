@@ -0,0 +1,194 @@
+use crate::sim::naive::chip::Chip;
+use crate::sim::naive::peripherals::{
+    SFIOR,
+    adc::BANDGAP,
+    analog::{self, AnalogPin},
+};
+
+//
+// Analog Comparator (Page 198)
+//
+
+const ACSR: usize = 0x28;
+const ADCSRA: usize = 0x26;
+const ADMUX: usize = 0x27;
+
+// ACSR: ACD | ACBG | ACO | ACI | ACIE | ACIC | ACIS1 | ACIS0
+const ACD: u8 = 7;
+const ACBG: u8 = 6;
+const ACO: u8 = 5;
+const ACI: u8 = 4;
+
+// SFIOR @ 3 => ACME, ADCSRA @ 7 => ADEN
+const ACME: u8 = 3;
+const ADEN: u8 = 7;
+
+fn bit(val: u8, bit: u8) -> bool {
+    (val >> bit) & 1 != 0
+}
+
+// Positive input is AIN0 or the bandgap reference. The negative input is AIN1,
+// or the ADC pin selected by MUX2:0 when ACME is set and the ADC is off
+// (Table 80).
+fn output(c: &Chip) -> bool {
+    let acsr = c.ram[ACSR];
+    let positive = if bit(acsr, ACBG) {
+        BANDGAP
+    } else {
+        analog::voltage(c, AnalogPin::Ain0)
+    };
+
+    let negative = if bit(c.ram[SFIOR], ACME) && !bit(c.ram[ADCSRA], ADEN) {
+        AnalogPin::Adc(c.ram[ADMUX] & 0b111)
+    } else {
+        AnalogPin::Ain1
+    };
+
+    positive > analog::voltage(c, negative)
+}
+
+// Updates ACO and sets ACI on the edge selected by ACIS1:0.
+pub(crate) fn tick(c: &mut Chip) {
+    let acsr = c.ram[ACSR];
+    if bit(acsr, ACD) {
+        return;
+    }
+
+    let prev = bit(acsr, ACO);
+    let aco = output(c);
+    if aco == prev {
+        return;
+    }
+
+    c.ram[ACSR] = (acsr & !(1 << ACO)) | ((aco as u8) << ACO);
+
+    let event = match acsr & 0b11 {
+        // Output toggle
+        0b00 => true,
+        0b10 => !aco,
+        0b11 => aco,
+        // Reserved
+        _ => false,
+    };
+    if event {
+        c.ram[ACSR] |= 1 << ACI;
+    }
+}
+
+// Comparator output, as the Timer1 input capture source when ACIC is set.
+pub(crate) fn aco(c: &Chip) -> bool {
+    bit(c.ram[ACSR], ACO)
+}
+
+pub(crate) fn io_write(c: &mut Chip, addr: usize, val: u8) -> bool {
+    if addr != ACSR {
+        return false;
+    }
+
+    // ACO is read only, ACI is cleared by writing a one to it
+    let kept = c.ram[ACSR] & (1 << ACO | 1 << ACI);
+    c.ram[ACSR] = (val & !(1 << ACO | 1 << ACI)) | kept;
+    if bit(val, ACI) {
+        c.ram[ACSR] &= !(1 << ACI);
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::naive::peripherals::{TIFR, analog::AnalogSource, timer1};
+
+    fn comparator(ain0: f64, ain1: f64, acsr: u8) -> Chip {
+        let mut c = Chip::new();
+        c.set_analog_input(AnalogPin::Ain0, AnalogSource::Constant(ain0));
+        c.set_analog_input(AnalogPin::Ain1, AnalogSource::Constant(ain1));
+        c.data_write(ACSR, acsr);
+        c
+    }
+
+    fn drive(c: &mut Chip, pin: AnalogPin, volts: f64) {
+        c.set_analog_input(pin, AnalogSource::Constant(volts));
+        tick(c);
+    }
+
+    #[test]
+    fn interrupt_on_output_toggle() {
+        let mut c = comparator(2.0, 1.0, 0);
+        tick(&mut c);
+        assert_eq!(c.ram[ACSR], 1 << ACO | 1 << ACI);
+
+        // ACO is read only
+        c.data_write(ACSR, 1 << ACI);
+        assert_eq!(c.ram[ACSR], 1 << ACO);
+
+        drive(&mut c, AnalogPin::Ain1, 3.0);
+        assert_eq!(c.ram[ACSR], 1 << ACI);
+    }
+
+    #[test]
+    fn interrupt_on_selected_edge() {
+        // Falling edge
+        let mut c = comparator(0.0, 1.0, 0b10);
+        drive(&mut c, AnalogPin::Ain0, 2.0);
+        assert_eq!(c.ram[ACSR], 1 << ACO | 0b10);
+        drive(&mut c, AnalogPin::Ain0, 0.5);
+        assert_eq!(c.ram[ACSR], 1 << ACI | 0b10);
+
+        // Rising edge
+        let mut c = comparator(0.0, 1.0, 0b11);
+        drive(&mut c, AnalogPin::Ain0, 2.0);
+        assert_eq!(c.ram[ACSR], 1 << ACO | 1 << ACI | 0b11);
+    }
+
+    #[test]
+    fn bandgap_on_the_positive_input() {
+        let mut c = comparator(0.0, 1.2, 1 << ACBG);
+        tick(&mut c);
+        assert!(aco(&c));
+        drive(&mut c, AnalogPin::Ain1, 1.25);
+        assert!(!aco(&c));
+    }
+
+    #[test]
+    fn adc_multiplexer_on_the_negative_input() {
+        let mut c = comparator(2.0, 0.0, 0);
+        c.set_analog_input(AnalogPin::Adc(3), AnalogSource::Constant(3.0));
+        c.ram[SFIOR] = 1 << ACME;
+        c.ram[ADMUX] = 3;
+        tick(&mut c);
+        assert!(!aco(&c));
+
+        // Only while the ADC is off
+        c.ram[ADCSRA] = 1 << ADEN;
+        tick(&mut c);
+        assert!(aco(&c));
+    }
+
+    #[test]
+    fn disabled_comparator_holds_its_output() {
+        let mut c = comparator(2.0, 1.0, 1 << ACD);
+        tick(&mut c);
+        assert_eq!(c.ram[ACSR], 1 << ACD);
+    }
+
+    #[test]
+    fn triggers_timer1_input_capture() {
+        const ACIC: u8 = 2;
+        const ICR1L: usize = 0x46;
+        const TCCR1B: usize = 0x4E;
+        const ICF1: u8 = 5;
+
+        // Timer1 capturing rising edges at clk/1
+        let mut c = comparator(0.0, 1.0, 1 << ACIC);
+        c.data_write(TCCR1B, 1 << 6 | 0x01);
+        timer1::tick(&mut c, 10);
+        assert_eq!(c.ram[TIFR] & 1 << ICF1, 0);
+
+        drive(&mut c, AnalogPin::Ain0, 2.0);
+        timer1::tick(&mut c, 1);
+        assert_eq!(c.ram[TIFR] & 1 << ICF1, 1 << ICF1);
+        assert_eq!(c.ram[ICR1L], 11);
+    }
+}
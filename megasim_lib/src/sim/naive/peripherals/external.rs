@@ -0,0 +1,184 @@
+use crate::sim::naive::chip::{Chip, SleepMode, State};
+use crate::sim::naive::peripherals::PINB;
+
+//
+// External Interrupts (Page 66)
+//
+
+const PIND: usize = 0x30;
+const MCUCSR: usize = 0x54;
+const MCUCR: usize = 0x55;
+pub(crate) const GIFR: usize = 0x5A;
+
+// MCUCR: SM2 | SE | SM1 | SM0 | ISC11 | ISC10 | ISC01 | ISC00
+const ISC00: u8 = 0;
+const ISC10: u8 = 2;
+// MCUCSR: JTD | ISC2 | - | JTRF | WDRF | BORF | EXTRF | PORF
+const ISC2: u8 = 6;
+
+// GIFR / GICR
+pub(crate) const INTF2: u8 = 5;
+pub(crate) const INTF0: u8 = 6;
+pub(crate) const INTF1: u8 = 7;
+
+// INT0 is PD2, INT1 is PD3, INT2 is PB2
+const INT0_BIT: u8 = 2;
+const INT1_BIT: u8 = 3;
+const INT2_BIT: u8 = 2;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExternalInterrupts {
+    prev_int0: bool,
+    prev_int1: bool,
+    prev_int2: bool,
+}
+
+// Whether a pin change from `prev` to `now` is an edge selected by ISCn1:0.
+fn edge(isc: u8, prev: bool, now: bool) -> bool {
+    match isc {
+        0b01 => prev != now,
+        0b10 => prev && !now,
+        0b11 => !prev && now,
+        _ => false,
+    }
+}
+
+fn pins(c: &Chip) -> (bool, bool, bool) {
+    (
+        (c.ram[PIND] >> INT0_BIT) & 1 != 0,
+        (c.ram[PIND] >> INT1_BIT) & 1 != 0,
+        (c.ram[PINB] >> INT2_BIT) & 1 != 0,
+    )
+}
+
+// Samples INT0-2 and latches the selected edges into GIFR.
+pub(crate) fn tick(c: &mut Chip) {
+    let (int0, int1, int2) = pins(c);
+    let isc0 = (c.ram[MCUCR] >> ISC00) & 0b11;
+    let isc1 = (c.ram[MCUCR] >> ISC10) & 0b11;
+    let isc2 = 0b10 | ((c.ram[MCUCSR] >> ISC2) & 1);
+    let prev = c.external;
+
+    // INT0/INT1 edges are detected with the I/O clock, which only keeps
+    // running in idle sleep. INT2 is detected asynchronously.
+    if matches!(c.state, State::Running | State::Sleeping(SleepMode::Idle)) {
+        if edge(isc0, prev.prev_int0, int0) {
+            c.ram[GIFR] |= 1 << INTF0;
+        }
+        if edge(isc1, prev.prev_int1, int1) {
+            c.ram[GIFR] |= 1 << INTF1;
+        }
+    }
+    if edge(isc2, prev.prev_int2, int2) {
+        c.ram[GIFR] |= 1 << INTF2;
+    }
+
+    // The flags stay cleared while INT0/INT1 are level triggered
+    if isc0 == 0b00 {
+        c.ram[GIFR] &= !(1 << INTF0);
+    }
+    if isc1 == 0b00 {
+        c.ram[GIFR] &= !(1 << INTF1);
+    }
+
+    c.external = ExternalInterrupts {
+        prev_int0: int0,
+        prev_int1: int1,
+        prev_int2: int2,
+    };
+}
+
+// Low level requests on INT0/INT1, which are not latched and last as long as
+// the pin is held low.
+pub(crate) fn level_requested(c: &Chip, flag: u8) -> bool {
+    let (int0, int1, _) = pins(c);
+
+    match flag {
+        INTF0 => (c.ram[MCUCR] >> ISC00) & 0b11 == 0b00 && !int0,
+        INTF1 => (c.ram[MCUCR] >> ISC10) & 0b11 == 0b00 && !int1,
+        _ => false,
+    }
+}
+
+pub(crate) fn io_write(c: &mut Chip, addr: usize, val: u8) -> bool {
+    match addr {
+        // Interrupt flags are cleared by writing a one to them
+        GIFR => c.ram[GIFR] &= !val,
+        _ => return false,
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip(int0: bool, int2: bool) -> Chip {
+        let mut c = Chip::new();
+        set(&mut c, int0, int2);
+        tick(&mut c);
+        c
+    }
+
+    fn set(c: &mut Chip, int0: bool, int2: bool) {
+        c.ram[PIND] = (int0 as u8) << INT0_BIT | 1 << INT1_BIT;
+        c.ram[PINB] = (int2 as u8) << INT2_BIT;
+    }
+
+    #[test]
+    fn int0_edges() {
+        // Falling edge
+        let mut c = chip(true, false);
+        c.ram[MCUCR] = 0b10 << ISC00;
+        set(&mut c, true, false);
+        tick(&mut c);
+        assert_eq!(c.ram[GIFR], 0);
+        set(&mut c, false, false);
+        tick(&mut c);
+        assert_eq!(c.ram[GIFR], 1 << INTF0);
+
+        // Any change
+        c.data_write(GIFR, 1 << INTF0);
+        c.ram[MCUCR] = 0b01 << ISC00;
+        set(&mut c, true, false);
+        tick(&mut c);
+        assert_eq!(c.ram[GIFR], 1 << INTF0);
+    }
+
+    #[test]
+    fn low_level_is_not_latched() {
+        let mut c = chip(false, false);
+        assert!(level_requested(&c, INTF0));
+        assert!(!level_requested(&c, INTF1));
+        assert_eq!(c.ram[GIFR], 0);
+
+        c.ram[MCUCR] = 0b11 << ISC00;
+        assert!(!level_requested(&c, INTF0));
+    }
+
+    #[test]
+    fn int2_edge_select() {
+        // ISC2 = 0 is a falling edge
+        let mut c = chip(true, true);
+        set(&mut c, true, false);
+        tick(&mut c);
+        assert_eq!(c.ram[GIFR], 1 << INTF2);
+
+        c.data_write(GIFR, 1 << INTF2);
+        c.ram[MCUCSR] = 1 << ISC2;
+        set(&mut c, true, true);
+        tick(&mut c);
+        assert_eq!(c.ram[GIFR], 1 << INTF2);
+    }
+
+    #[test]
+    fn only_int2_in_power_down() {
+        let mut c = chip(true, true);
+        c.ram[MCUCR] = 0b10 << ISC00;
+        c.state = State::Sleeping(SleepMode::PowerDown);
+        set(&mut c, false, false);
+        tick(&mut c);
+        assert_eq!(c.ram[GIFR], 1 << INTF2);
+    }
+}
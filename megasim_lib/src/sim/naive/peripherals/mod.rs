@@ -0,0 +1,106 @@
+pub mod adc;
+pub mod analog;
+pub mod comparator;
+pub mod external;
+pub mod spi;
+pub mod timer0;
+pub mod timer1;
+pub mod timer2;
+pub mod twi;
+pub mod usart;
+
+use crate::sim::naive::chip::Chip;
+
+// Registers shared between peripherals, as data space addresses
+pub(crate) const SFIOR: usize = 0x50;
+pub(crate) const TIFR: usize = 0x58;
+pub(crate) const TIMSK: usize = 0x59;
+pub(crate) const DDRB: usize = 0x37;
+pub(crate) const PINB: usize = 0x36;
+
+// SFIOR @ 0 => PSR10, resets the Timer0/Timer1 prescaler
+// SFIOR @ 1 => PSR2, resets the Timer2 prescaler
+const PSR10: u8 = 0;
+const PSR2: u8 = 1;
+
+// Clock select divisors for CS 1-5 of Timer0 and Timer1.
+pub(crate) const PRESCALER_DIVISORS: [u64; 5] = [1, 8, 64, 256, 1024];
+
+// Number of prescaled clock edges within `cycles` clocks, for a free running
+// prescaler currently at `prescaler`.
+pub(crate) fn prescaled_ticks(prescaler: u64, cycles: u64, divisor: u64) -> u64 {
+    (prescaler + cycles) / divisor - prescaler / divisor
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Port {
+    A,
+    B,
+    C,
+    D,
+}
+
+// An I/O pin, e.g. `Pin::new(Port::B, 4)` for PB4.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pin {
+    pub port: Port,
+    pub bit: u8,
+}
+
+impl Pin {
+    pub fn new(port: Port, bit: u8) -> Self {
+        Pin { port, bit }
+    }
+
+    // Data space addresses of the DDRx and PORTx registers.
+    fn registers(&self) -> (usize, usize) {
+        match self.port {
+            Port::A => (0x3A, 0x3B),
+            Port::B => (0x37, 0x38),
+            Port::C => (0x34, 0x35),
+            Port::D => (0x31, 0x32),
+        }
+    }
+
+    // Level the chip drives the pin to. Input pins are assumed to be pulled
+    // up.
+    pub(crate) fn output(&self, c: &Chip) -> bool {
+        let (ddr, port) = self.registers();
+        (c.ram[ddr] >> self.bit) & 1 == 0 || (c.ram[port] >> self.bit) & 1 != 0
+    }
+}
+
+// Drives an output compare pin. The pin only follows the compare unit while
+// it is configured as an output.
+pub(crate) fn drive_pin(c: &mut Chip, ddr: usize, pin: usize, bit: u8, level: bool) {
+    if (c.ram[ddr] >> bit) & 1 == 0 {
+        return;
+    }
+
+    if level {
+        c.ram[pin] |= 1 << bit;
+    } else {
+        c.ram[pin] &= !(1 << bit);
+    }
+}
+
+// Handles writes to registers shared between peripherals. Returns false if
+// the address is not one of them.
+pub(crate) fn io_write(c: &mut Chip, addr: usize, val: u8) -> bool {
+    match addr {
+        // Interrupt flags are cleared by writing a one to them
+        TIFR => c.ram[TIFR] &= !val,
+        SFIOR => {
+            if (val >> PSR10) & 1 != 0 {
+                c.prescaler = 0;
+            }
+            if (val >> PSR2) & 1 != 0 {
+                c.timer2.prescaler = 0;
+            }
+            c.ram[SFIOR] = val & !(1 << PSR10 | 1 << PSR2);
+        }
+        _ => return false,
+    }
+
+    true
+}
@@ -0,0 +1,339 @@
+use crate::sim::naive::chip::Chip;
+use crate::sim::naive::peripherals::{DDRB, PINB, Pin};
+use std::{cell::RefCell, fmt::Debug, rc::Rc};
+
+//
+// Serial Peripheral Interface – SPI (Page 132)
+//
+
+const SPCR: usize = 0x2D;
+const SPSR: usize = 0x2E;
+const SPDR: usize = 0x2F;
+
+// SPCR: SPIE | SPE | DORD | MSTR | CPOL | CPHA | SPR1 | SPR0
+const SPE: u8 = 6;
+const DORD: u8 = 5;
+const MSTR: u8 = 4;
+
+// SPSR: SPIF | WCOL | - | - | - | - | - | SPI2X
+const SPIF: u8 = 7;
+const WCOL: u8 = 6;
+const SPI2X: u8 = 0;
+
+// SS is PB4
+const SS_BIT: u8 = 4;
+
+// SCK divisors for SPR1:0 (Table 58)
+const SCK_DIVISORS: [u64; 4] = [4, 16, 64, 128];
+
+// A device on the SPI bus, selected by pulling its chip select low. Bytes are
+// exchanged MSB first, as they appear on the wire.
+pub trait SpiDevice: Debug {
+    // Called when the chip select line changes.
+    fn select(&mut self, _selected: bool) {}
+
+    // Shifts in `mosi` and returns the byte shifted out on MISO.
+    fn transfer(&mut self, mosi: u8) -> u8;
+}
+
+#[derive(Debug)]
+struct Attached {
+    select: Pin,
+    selected: bool,
+    device: Rc<RefCell<dyn SpiDevice>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Spi {
+    // Byte being shifted out in master mode, and the cycles left until the
+    // transfer completes
+    transfer: Option<(u8, u64)>,
+    // Byte loaded for the next transfer from an external master
+    slave_data: u8,
+    // SPSR was read with SPIF set, the next SPDR access clears SPIF and WCOL
+    flags_read: bool,
+    devices: Vec<Attached>,
+}
+
+fn bit(val: u8, bit: u8) -> bool {
+    (val >> bit) & 1 != 0
+}
+
+fn is_master(c: &Chip) -> bool {
+    bit(c.ram[SPCR], SPE) && bit(c.ram[SPCR], MSTR)
+}
+
+// Clock cycles for one byte in master mode.
+fn transfer_cycles(c: &Chip) -> u64 {
+    let divisor = SCK_DIVISORS[(c.ram[SPCR] & 0b11) as usize];
+    let divisor = if bit(c.ram[SPSR], SPI2X) {
+        divisor / 2
+    } else {
+        divisor
+    };
+    8 * divisor
+}
+
+// Resets the SPI registers. Attached devices stay on the bus.
+pub(crate) fn reset(c: &mut Chip) {
+    c.spi.transfer = None;
+    c.spi.slave_data = 0;
+    c.spi.flags_read = false;
+}
+
+pub(crate) fn attach(c: &mut Chip, select: Pin, device: Rc<RefCell<dyn SpiDevice>>) {
+    let selected = !select.output(c);
+    if selected {
+        device.borrow_mut().select(true);
+    }
+
+    c.spi.devices.push(Attached {
+        select,
+        selected,
+        device,
+    });
+}
+
+// Tells devices about chip select changes.
+fn update_selects(c: &mut Chip) {
+    for i in 0..c.spi.devices.len() {
+        let selected = !c.spi.devices[i].select.output(c);
+        let attached = &mut c.spi.devices[i];
+        if selected != attached.selected {
+            attached.selected = selected;
+            attached.device.borrow_mut().select(selected);
+        }
+    }
+}
+
+// Exchanges a byte with the selected devices. MISO idles high, and is pulled
+// low by any device driving a zero.
+fn exchange(c: &mut Chip, mosi: u8) -> u8 {
+    let lsb_first = bit(c.ram[SPCR], DORD);
+    let wire = if lsb_first { mosi.reverse_bits() } else { mosi };
+
+    let miso = c
+        .spi
+        .devices
+        .iter()
+        .filter(|attached| attached.selected)
+        .fold(0xFF, |miso, attached| {
+            miso & attached.device.borrow_mut().transfer(wire)
+        });
+
+    if lsb_first { miso.reverse_bits() } else { miso }
+}
+
+fn complete(c: &mut Chip, received: u8) {
+    c.ram[SPDR] = received;
+    c.ram[SPSR] |= 1 << SPIF;
+}
+
+// A master whose SS pin is an input is forced into slave mode when SS is
+// driven low.
+fn check_ss(c: &mut Chip) {
+    if is_master(c) && !bit(c.ram[DDRB], SS_BIT) && !bit(c.ram[PINB], SS_BIT) {
+        c.ram[SPCR] &= !(1 << MSTR);
+        c.ram[SPSR] |= 1 << SPIF;
+        c.spi.transfer = None;
+    }
+}
+
+pub(crate) fn tick(c: &mut Chip, cycles: u64) {
+    update_selects(c);
+    check_ss(c);
+
+    let Some((mosi, left)) = c.spi.transfer else {
+        return;
+    };
+    if cycles < left {
+        c.spi.transfer = Some((mosi, left - cycles));
+        return;
+    }
+
+    c.spi.transfer = None;
+    let miso = exchange(c, mosi);
+    complete(c, miso);
+}
+
+// Clocks one byte in from an external master while the chip is an SPI slave.
+// SS (PB4) has to be low. Returns the byte the program loaded into SPDR.
+pub(crate) fn slave_transfer(c: &mut Chip, mosi: u8) -> Option<u8> {
+    let slave = bit(c.ram[SPCR], SPE) && !bit(c.ram[SPCR], MSTR);
+    if !slave || bit(c.ram[PINB], SS_BIT) {
+        return None;
+    }
+
+    let lsb_first = bit(c.ram[SPCR], DORD);
+    let out = c.spi.slave_data;
+    complete(c, if lsb_first { mosi.reverse_bits() } else { mosi });
+
+    Some(if lsb_first { out.reverse_bits() } else { out })
+}
+
+// SPIF and WCOL are cleared by reading SPSR and then accessing SPDR.
+fn access_spdr(c: &mut Chip) {
+    if c.spi.flags_read {
+        c.spi.flags_read = false;
+        c.ram[SPSR] &= !(1 << SPIF | 1 << WCOL);
+    }
+}
+
+pub(crate) fn io_read(c: &mut Chip, addr: usize) -> Option<u8> {
+    match addr {
+        SPSR => {
+            c.spi.flags_read = bit(c.ram[SPSR], SPIF) || bit(c.ram[SPSR], WCOL);
+            Some(c.ram[SPSR])
+        }
+        SPDR => {
+            access_spdr(c);
+            Some(c.ram[SPDR])
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn io_write(c: &mut Chip, addr: usize, val: u8) -> bool {
+    match addr {
+        // SPDR reads return the receive buffer, writes go to the shift
+        // register
+        SPDR => {
+            access_spdr(c);
+            if c.spi.transfer.is_some() {
+                c.ram[SPSR] |= 1 << WCOL;
+            } else if is_master(c) {
+                update_selects(c);
+                c.spi.transfer = Some((val, transfer_cycles(c)));
+            } else {
+                c.spi.slave_data = val;
+            }
+        }
+        // Only SPI2X is writable
+        SPSR => c.ram[SPSR] = (c.ram[SPSR] & !(1 << SPI2X)) | (val & (1 << SPI2X)),
+        _ => return false,
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::naive::devices::{Hc165, Hc595};
+    use crate::sim::naive::peripherals::Port;
+
+    const PORTB: usize = 0x38;
+
+    // Master at fosc/16, with SS, MOSI and SCK as outputs and PB3 as a second
+    // chip select. Both selects start high.
+    fn master() -> Chip {
+        let mut c = Chip::new();
+        c.data_write(DDRB, 0xB8);
+        c.data_write(PORTB, 0x18);
+        c.data_write(SPCR, 1 << SPE | 1 << MSTR | 0b01);
+        c
+    }
+
+    #[test]
+    fn master_transfer_timing() {
+        let mut c = master();
+        let input = Rc::new(RefCell::new(Hc165::new(0x3C)));
+        c.attach_spi_device(Pin::new(Port::B, 3), input.clone());
+
+        c.ram[PORTB] &= !(1 << 3);
+        c.data_write(SPDR, 0xA5);
+        tick(&mut c, 8 * 16 - 1);
+        assert_eq!(c.ram[SPSR], 0);
+        tick(&mut c, 1);
+        assert_eq!((c.ram[SPSR], c.ram[SPDR]), (1 << SPIF, 0x3C));
+
+        // SPI2X halves the SCK period
+        c.data_write(SPSR, 1 << SPI2X);
+        assert_eq!(transfer_cycles(&c), 8 * 8);
+    }
+
+    #[test]
+    fn only_selected_devices_see_the_transfer() {
+        let mut c = master();
+        let first = Rc::new(RefCell::new(Hc595::default()));
+        let second = Rc::new(RefCell::new(Hc595::default()));
+        c.attach_spi_device(Pin::new(Port::B, 4), first.clone());
+        c.attach_spi_device(Pin::new(Port::B, 3), second.clone());
+
+        c.ram[PORTB] &= !(1 << 4);
+        c.data_write(SPDR, 0xA5);
+        tick(&mut c, 8 * 16);
+        c.ram[PORTB] |= 1 << 4;
+        tick(&mut c, 1);
+
+        assert_eq!((first.borrow().outputs, second.borrow().outputs), (0xA5, 0));
+    }
+
+    #[test]
+    fn miso_is_wired_and() {
+        let mut c = master();
+        for inputs in [0xF0, 0x3C] {
+            let device = Rc::new(RefCell::new(Hc165::new(inputs)));
+            c.attach_spi_device(Pin::new(Port::B, 3), device);
+        }
+        c.ram[PORTB] &= !(1 << 3);
+        c.data_write(SPDR, 0);
+        tick(&mut c, 8 * 16);
+        assert_eq!(c.ram[SPDR], 0x30);
+    }
+
+    #[test]
+    fn lsb_first() {
+        let mut c = master();
+        c.data_write(SPCR, 1 << SPE | 1 << MSTR | 1 << DORD);
+        let output = Rc::new(RefCell::new(Hc595::default()));
+        c.attach_spi_device(Pin::new(Port::B, 3), output.clone());
+
+        c.ram[PORTB] &= !(1 << 3);
+        c.data_write(SPDR, 0x01);
+        tick(&mut c, 8 * 4);
+        c.ram[PORTB] |= 1 << 3;
+        tick(&mut c, 1);
+        assert_eq!(output.borrow().outputs, 0x80);
+    }
+
+    #[test]
+    fn write_collision_and_flag_clearing() {
+        let mut c = master();
+        c.data_write(SPDR, 1);
+        c.data_write(SPDR, 2);
+        assert_eq!(c.data_read(SPSR), 1 << WCOL);
+        c.data_read(SPDR);
+        assert_eq!(c.ram[SPSR], 0);
+
+        // SPIF without reading SPSR first stays set
+        tick(&mut c, 8 * 16);
+        c.data_read(SPDR);
+        assert_eq!(c.ram[SPSR], 1 << SPIF);
+        c.data_read(SPSR);
+        c.data_read(SPDR);
+        assert_eq!(c.ram[SPSR], 0);
+    }
+
+    #[test]
+    fn ss_input_low_forces_slave_mode() {
+        let mut c = master();
+        c.data_write(DDRB, 0xA8);
+        c.ram[PINB] = 0;
+        tick(&mut c, 1);
+        assert_eq!(c.ram[SPCR] & 1 << MSTR, 0);
+        assert_eq!(c.ram[SPSR], 1 << SPIF);
+    }
+
+    #[test]
+    fn slave_transfer_needs_ss_low() {
+        let mut c = Chip::new();
+        c.data_write(SPCR, 1 << SPE);
+        c.data_write(SPDR, 0x77);
+        assert_eq!(c.spi_slave_transfer(0x12), Some(0x77));
+        assert_eq!((c.ram[SPDR], c.ram[SPSR]), (0x12, 1 << SPIF));
+
+        c.ram[PINB] |= 1 << SS_BIT;
+        assert_eq!(c.spi_slave_transfer(0x12), None);
+    }
+}
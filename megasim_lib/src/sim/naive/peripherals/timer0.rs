@@ -0,0 +1,288 @@
+use crate::sim::naive::chip::Chip;
+use crate::sim::naive::peripherals::{
+    DDRB, PINB, PRESCALER_DIVISORS, TIFR, drive_pin, prescaled_ticks,
+};
+
+//
+// 8-bit Timer/Counter0 (Page 69)
+//
+
+const TCNT0: usize = 0x52;
+const TCCR0: usize = 0x53;
+const OCR0: usize = 0x5C;
+
+// TCCR0: FOC0 | WGM00 | COM01 | COM00 | WGM01 | CS02 | CS01 | CS00
+const FOC0: u8 = 7;
+const WGM00: u8 = 6;
+const COM00: u8 = 4;
+const WGM01: u8 = 3;
+
+// TIFR / TIMSK
+const TOV0: u8 = 0;
+const OCF0: u8 = 1;
+
+// T0 is PB0, OC0 is PB3
+const T0_BIT: u8 = 0;
+const OC0_BIT: u8 = 3;
+
+const MAX: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Normal,
+    PhaseCorrect,
+    Ctc,
+    FastPwm,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timer0 {
+    // OC0 output compare state
+    pub oc0: bool,
+    // OCR0 value in use, double buffered in the PWM modes
+    ocr: u8,
+    // Counting down in phase correct mode
+    down: bool,
+    // A TCNT0 write blocks the compare match on the next timer clock
+    block_compare: bool,
+    prev_t0: bool,
+}
+
+fn mode(tccr0: u8) -> Mode {
+    match ((tccr0 >> WGM01) & 1, (tccr0 >> WGM00) & 1) {
+        (0, 0) => Mode::Normal,
+        (0, 1) => Mode::PhaseCorrect,
+        (1, 0) => Mode::Ctc,
+        _ => Mode::FastPwm,
+    }
+}
+
+fn set_oc0(c: &mut Chip, level: bool) {
+    c.timer0.oc0 = level;
+    drive_pin(c, DDRB, PINB, OC0_BIT, level);
+}
+
+// Output compare action on a compare match (Table 39-41).
+fn compare_output(c: &mut Chip, tccr0: u8) {
+    let com = (tccr0 >> COM00) & 0b11;
+    let oc0 = c.timer0.oc0;
+
+    let level = match (mode(tccr0), com) {
+        (_, 0b00) => return,
+        (Mode::Normal | Mode::Ctc, 0b01) => !oc0,
+        (Mode::Normal | Mode::Ctc, 0b10) => false,
+        (Mode::Normal | Mode::Ctc, _) => true,
+        (_, 0b01) => return,
+        // OCR0 = MAX gives a constant output in fast PWM
+        (Mode::FastPwm, _) if c.timer0.ocr == MAX => return,
+        (Mode::FastPwm, 0b10) => false,
+        (Mode::FastPwm, _) => true,
+        (Mode::PhaseCorrect, 0b10) => c.timer0.down,
+        (Mode::PhaseCorrect, _) => !c.timer0.down,
+    };
+
+    set_oc0(c, level);
+}
+
+// One clock of the timer.
+fn count(c: &mut Chip) {
+    let tccr0 = c.ram[TCCR0];
+    let mode = mode(tccr0);
+    let tcnt = c.ram[TCNT0];
+
+    if matches!(mode, Mode::Normal | Mode::Ctc) {
+        c.timer0.ocr = c.ram[OCR0];
+    }
+
+    let blocked = c.timer0.block_compare;
+    c.timer0.block_compare = false;
+    if tcnt == c.timer0.ocr && !blocked {
+        c.ram[TIFR] |= 1 << OCF0;
+        compare_output(c, tccr0);
+    }
+
+    let next = match mode {
+        Mode::Normal => tcnt.wrapping_add(1),
+        Mode::Ctc if tcnt == c.timer0.ocr => 0,
+        Mode::Ctc => tcnt.wrapping_add(1),
+        Mode::FastPwm => tcnt.wrapping_add(1),
+        Mode::PhaseCorrect if c.timer0.down => tcnt.saturating_sub(1),
+        Mode::PhaseCorrect => tcnt.saturating_add(1),
+    };
+
+    match mode {
+        Mode::Normal | Mode::Ctc if tcnt == MAX => c.ram[TIFR] |= 1 << TOV0,
+        Mode::FastPwm if tcnt == MAX => {
+            c.ram[TIFR] |= 1 << TOV0;
+            c.timer0.ocr = c.ram[OCR0];
+
+            // Non-inverting output is set at BOTTOM, inverting is cleared
+            match (tccr0 >> COM00) & 0b11 {
+                0b10 if c.timer0.ocr != MAX => set_oc0(c, true),
+                0b11 if c.timer0.ocr != MAX => set_oc0(c, false),
+                _ => {}
+            }
+        }
+        Mode::PhaseCorrect if next == MAX => {
+            c.timer0.down = true;
+            c.timer0.ocr = c.ram[OCR0];
+        }
+        Mode::PhaseCorrect if next == 0 && c.timer0.down => {
+            c.timer0.down = false;
+            c.ram[TIFR] |= 1 << TOV0;
+        }
+        _ => {}
+    }
+
+    c.ram[TCNT0] = next;
+}
+
+pub(crate) fn tick(c: &mut Chip, cycles: u64) {
+    let cs = c.ram[TCCR0] & 0b111;
+
+    // External clock on T0, sampled once per step
+    let t0 = (c.ram[PINB] >> T0_BIT) & 1 != 0;
+    let prev_t0 = c.timer0.prev_t0;
+    c.timer0.prev_t0 = t0;
+
+    let ticks = match cs {
+        0 => 0,
+        1..=5 => prescaled_ticks(c.prescaler, cycles, PRESCALER_DIVISORS[cs as usize - 1]),
+        6 => (prev_t0 && !t0) as u64,
+        _ => (!prev_t0 && t0) as u64,
+    };
+
+    for _ in 0..ticks {
+        count(c);
+    }
+}
+
+pub(crate) fn io_write(c: &mut Chip, addr: usize, val: u8) -> bool {
+    match addr {
+        TCNT0 => {
+            c.ram[TCNT0] = val;
+            c.timer0.block_compare = true;
+        }
+        TCCR0 => {
+            // FOC0 forces a compare output action in the non-PWM modes,
+            // without setting OCF0. It always reads as zero.
+            c.ram[TCCR0] = val & !(1 << FOC0);
+            if (val >> FOC0) & 1 != 0 && matches!(mode(val), Mode::Normal | Mode::Ctc) {
+                c.timer0.ocr = c.ram[OCR0];
+                compare_output(c, val);
+            }
+        }
+        _ => return false,
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(ocr0: u8, tccr0: u8) -> Chip {
+        let mut c = Chip::new();
+        c.data_write(OCR0, ocr0);
+        c.data_write(TCCR0, tccr0);
+        c
+    }
+
+    fn clock(c: &mut Chip, n: usize) {
+        for _ in 0..n {
+            tick(c, 1);
+        }
+    }
+
+    #[test]
+    fn normal_mode_overflow() {
+        let mut c = timer(0, 0x01);
+        clock(&mut c, 255);
+        assert_eq!((c.ram[TCNT0], c.ram[TIFR] & 1 << TOV0), (255, 0));
+        clock(&mut c, 1);
+        assert_eq!((c.ram[TCNT0], c.ram[TIFR] & 1 << TOV0), (0, 1 << TOV0));
+    }
+
+    #[test]
+    fn prescaled_clock() {
+        let mut c = timer(0, 0x02);
+        tick(&mut c, 8 * 10 + 7);
+        assert_eq!(c.ram[TCNT0], 10);
+    }
+
+    #[test]
+    fn ctc_clears_on_compare_match() {
+        let mut c = timer(9, 1 << WGM01 | 0x01);
+        clock(&mut c, 9);
+        assert_eq!((c.ram[TCNT0], c.ram[TIFR]), (9, 0));
+        clock(&mut c, 1);
+        assert_eq!((c.ram[TCNT0], c.ram[TIFR]), (0, 1 << OCF0));
+    }
+
+    #[test]
+    fn tcnt_write_blocks_the_next_compare() {
+        let mut c = timer(5, 0x01);
+        c.data_write(TCNT0, 5);
+        clock(&mut c, 1);
+        assert_eq!((c.ram[TCNT0], c.ram[TIFR]), (6, 0));
+    }
+
+    #[test]
+    fn fast_pwm_duty_cycle() {
+        // Non-inverting: set at BOTTOM, cleared on match with OCR0 = 63
+        let mut c = Chip::new();
+        c.data_write(DDRB, 1 << OC0_BIT);
+        c.data_write(OCR0, 63);
+        c.data_write(TCCR0, 0x69);
+        clock(&mut c, 256);
+
+        let mut high = 0;
+        for _ in 0..256 {
+            clock(&mut c, 1);
+            assert_eq!((c.ram[PINB] >> OC0_BIT) & 1 != 0, c.timer0.oc0);
+            high += c.timer0.oc0 as u32;
+        }
+        assert_eq!(high, 64);
+    }
+
+    #[test]
+    fn phase_correct_period() {
+        let mut c = timer(0, 0x41);
+        let mut overflows = vec![];
+        for n in 0..1200 {
+            clock(&mut c, 1);
+            if c.ram[TIFR] & 1 << TOV0 != 0 {
+                overflows.push(n);
+                c.ram[TIFR] = 0;
+            }
+        }
+        assert_eq!(overflows.len(), 2);
+        assert_eq!(overflows[1] - overflows[0], 510);
+    }
+
+    #[test]
+    fn foc0_forces_a_compare_action() {
+        let mut c = Chip::new();
+        c.data_write(DDRB, 1 << OC0_BIT);
+        // Toggle OC0 on compare match, timer stopped
+        c.data_write(TCCR0, 1 << FOC0 | 1 << COM00);
+        assert!(c.timer0.oc0);
+        assert_eq!((c.ram[TCCR0], c.ram[TIFR]), (1 << COM00, 0));
+        c.data_write(TCCR0, 1 << FOC0 | 1 << COM00);
+        assert!(!c.timer0.oc0);
+    }
+
+    #[test]
+    fn external_clock_on_t0() {
+        // Rising edge
+        let mut c = timer(0, 0x07);
+        for _ in 0..5 {
+            c.ram[PINB] |= 1 << T0_BIT;
+            tick(&mut c, 1);
+            c.ram[PINB] &= !(1 << T0_BIT);
+            tick(&mut c, 1);
+        }
+        assert_eq!(c.ram[TCNT0], 5);
+    }
+}
@@ -0,0 +1,546 @@
+use crate::sim::naive::chip::Chip;
+use crate::sim::naive::peripherals::{
+    PINB, PRESCALER_DIVISORS, TIFR, comparator, drive_pin, prescaled_ticks,
+};
+
+//
+// 16-bit Timer/Counter1 (Page 86)
+//
+
+const ICR1L: usize = 0x46;
+const ICR1H: usize = 0x47;
+const OCR1BL: usize = 0x48;
+const OCR1BH: usize = 0x49;
+const OCR1AL: usize = 0x4A;
+const OCR1AH: usize = 0x4B;
+const TCNT1L: usize = 0x4C;
+const TCNT1H: usize = 0x4D;
+const TCCR1B: usize = 0x4E;
+const TCCR1A: usize = 0x4F;
+
+const DDRD: usize = 0x31;
+const PIND: usize = 0x30;
+const ACSR: usize = 0x28;
+
+// TCCR1A: COM1A1 | COM1A0 | COM1B1 | COM1B0 | FOC1A | FOC1B | WGM11 | WGM10
+const COM1A0: u8 = 6;
+const COM1B0: u8 = 4;
+const FOC1A: u8 = 3;
+const FOC1B: u8 = 2;
+
+// TCCR1B: ICNC1 | ICES1 | - | WGM13 | WGM12 | CS12 | CS11 | CS10
+const ICNC1: u8 = 7;
+const ICES1: u8 = 6;
+const WGM12: u8 = 3;
+
+// TIFR / TIMSK
+const TOV1: u8 = 2;
+const OCF1B: u8 = 3;
+const OCF1A: u8 = 4;
+const ICF1: u8 = 5;
+
+// ACSR @ 2 => ACIC, the analog comparator triggers input capture
+const ACIC: u8 = 2;
+
+// T1 is PB1, ICP1 is PD6, OC1A is PD5, OC1B is PD4
+const T1_BIT: u8 = 1;
+const ICP1_BIT: u8 = 6;
+const OC1A_BIT: u8 = 5;
+const OC1B_BIT: u8 = 4;
+
+// Samples the noise canceller needs to see before accepting an edge
+const NOISE_CANCELLER_SAMPLES: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Normal,
+    Ctc,
+    FastPwm,
+    PhaseCorrect,
+    PhaseFrequencyCorrect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Top {
+    Fixed(u16),
+    Ocr1a,
+    Icr1,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timer1 {
+    // OC1A / OC1B output compare state
+    pub oc1a: bool,
+    pub oc1b: bool,
+    // High byte latch shared by all 16-bit registers
+    temp: u8,
+    // OCR1A / OCR1B values in use, double buffered in the PWM modes
+    ocr1a: u16,
+    ocr1b: u16,
+    // Counting down in the phase correct modes
+    down: bool,
+    // A TCNT1 write blocks compare matches on the next timer clock
+    block_compare: bool,
+    prev_t1: bool,
+    // Input capture level after the noise canceller, and how long the raw
+    // input has disagreed with it
+    icp: bool,
+    icp_pending: u64,
+}
+
+// Waveform generation mode (Table 47).
+fn mode(c: &Chip) -> (Kind, Top) {
+    let wgm = (c.ram[TCCR1A] & 0b11) | ((c.ram[TCCR1B] >> WGM12) & 0b11) << 2;
+
+    match wgm {
+        0 => (Kind::Normal, Top::Fixed(0xFFFF)),
+        1 => (Kind::PhaseCorrect, Top::Fixed(0x00FF)),
+        2 => (Kind::PhaseCorrect, Top::Fixed(0x01FF)),
+        3 => (Kind::PhaseCorrect, Top::Fixed(0x03FF)),
+        4 => (Kind::Ctc, Top::Ocr1a),
+        5 => (Kind::FastPwm, Top::Fixed(0x00FF)),
+        6 => (Kind::FastPwm, Top::Fixed(0x01FF)),
+        7 => (Kind::FastPwm, Top::Fixed(0x03FF)),
+        8 => (Kind::PhaseFrequencyCorrect, Top::Icr1),
+        9 => (Kind::PhaseFrequencyCorrect, Top::Ocr1a),
+        10 => (Kind::PhaseCorrect, Top::Icr1),
+        11 => (Kind::PhaseCorrect, Top::Ocr1a),
+        12 => (Kind::Ctc, Top::Icr1),
+        // 13 is reserved and behaves as normal mode here
+        14 => (Kind::FastPwm, Top::Icr1),
+        15 => (Kind::FastPwm, Top::Ocr1a),
+        _ => (Kind::Normal, Top::Fixed(0xFFFF)),
+    }
+}
+
+fn read16(c: &Chip, low: usize) -> u16 {
+    u16::from_le_bytes([c.ram[low], c.ram[low + 1]])
+}
+
+fn write16(c: &mut Chip, low: usize, val: u16) {
+    [c.ram[low], c.ram[low + 1]] = val.to_le_bytes();
+}
+
+fn top(c: &Chip, top: Top) -> u16 {
+    match top {
+        Top::Fixed(val) => val,
+        Top::Ocr1a => c.timer1.ocr1a,
+        Top::Icr1 => read16(c, ICR1L),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Channel {
+    A,
+    B,
+}
+
+fn set_output(c: &mut Chip, ch: Channel, level: bool) {
+    let bit = match ch {
+        Channel::A => {
+            c.timer1.oc1a = level;
+            OC1A_BIT
+        }
+        Channel::B => {
+            c.timer1.oc1b = level;
+            OC1B_BIT
+        }
+    };
+    drive_pin(c, DDRD, PIND, bit, level);
+}
+
+// Output compare action on a compare match (Table 44-46).
+fn compare_output(c: &mut Chip, ch: Channel) {
+    let (com, oc, ocr) = match ch {
+        Channel::A => (
+            (c.ram[TCCR1A] >> COM1A0) & 0b11,
+            c.timer1.oc1a,
+            c.timer1.ocr1a,
+        ),
+        Channel::B => (
+            (c.ram[TCCR1A] >> COM1B0) & 0b11,
+            c.timer1.oc1b,
+            c.timer1.ocr1b,
+        ),
+    };
+    let (kind, top_kind) = mode(c);
+
+    let level = match (kind, com) {
+        (_, 0b00) => return,
+        (Kind::Normal | Kind::Ctc, 0b01) => !oc,
+        (Kind::Normal | Kind::Ctc, 0b10) => false,
+        (Kind::Normal | Kind::Ctc, _) => true,
+        // In the PWM modes with OCR1A as TOP, COM1A = 01 toggles OC1A
+        (_, 0b01) if ch == Channel::A && top_kind == Top::Ocr1a => !oc,
+        (_, 0b01) => return,
+        // OCR1x = TOP gives a constant output in fast PWM
+        (Kind::FastPwm, _) if ocr == top(c, top_kind) => return,
+        (Kind::FastPwm, 0b10) => false,
+        (Kind::FastPwm, _) => true,
+        (_, 0b10) => c.timer1.down,
+        (_, _) => !c.timer1.down,
+    };
+
+    set_output(c, ch, level);
+}
+
+// Fast PWM outputs are set (non-inverting) or cleared (inverting) at BOTTOM.
+fn bottom_output(c: &mut Chip, top_val: u16) {
+    for (ch, com, ocr) in [
+        (Channel::A, (c.ram[TCCR1A] >> COM1A0) & 0b11, c.timer1.ocr1a),
+        (Channel::B, (c.ram[TCCR1A] >> COM1B0) & 0b11, c.timer1.ocr1b),
+    ] {
+        match com {
+            0b10 if ocr != top_val => set_output(c, ch, true),
+            0b11 if ocr != top_val => set_output(c, ch, false),
+            _ => {}
+        }
+    }
+}
+
+fn update_ocr(c: &mut Chip) {
+    c.timer1.ocr1a = read16(c, OCR1AL);
+    c.timer1.ocr1b = read16(c, OCR1BL);
+}
+
+// One clock of the timer.
+fn count(c: &mut Chip) {
+    let (kind, top_kind) = mode(c);
+    let tcnt = read16(c, TCNT1L);
+
+    if matches!(kind, Kind::Normal | Kind::Ctc) {
+        update_ocr(c);
+    }
+    let top_val = top(c, top_kind);
+
+    let blocked = c.timer1.block_compare;
+    c.timer1.block_compare = false;
+    if !blocked {
+        if tcnt == c.timer1.ocr1a {
+            c.ram[TIFR] |= 1 << OCF1A;
+            compare_output(c, Channel::A);
+        }
+        if tcnt == c.timer1.ocr1b {
+            c.ram[TIFR] |= 1 << OCF1B;
+            compare_output(c, Channel::B);
+        }
+    }
+
+    // With ICR1 as TOP, ICF1 flags reaching TOP instead of a capture
+    if top_kind == Top::Icr1 && tcnt == top_val {
+        c.ram[TIFR] |= 1 << ICF1;
+    }
+
+    let next = match kind {
+        Kind::Normal => {
+            if tcnt == 0xFFFF {
+                c.ram[TIFR] |= 1 << TOV1;
+            }
+            tcnt.wrapping_add(1)
+        }
+        Kind::Ctc => {
+            if tcnt == 0xFFFF {
+                c.ram[TIFR] |= 1 << TOV1;
+            }
+            if tcnt == top_val {
+                0
+            } else {
+                tcnt.wrapping_add(1)
+            }
+        }
+        Kind::FastPwm if tcnt == top_val => {
+            c.ram[TIFR] |= 1 << TOV1;
+            update_ocr(c);
+            bottom_output(c, top(c, top_kind));
+            0
+        }
+        Kind::FastPwm => tcnt.wrapping_add(1),
+        Kind::PhaseCorrect | Kind::PhaseFrequencyCorrect if !c.timer1.down => {
+            let next = tcnt.wrapping_add(1);
+            if next >= top_val {
+                c.timer1.down = true;
+                if kind == Kind::PhaseCorrect {
+                    update_ocr(c);
+                }
+            }
+            next
+        }
+        Kind::PhaseCorrect | Kind::PhaseFrequencyCorrect => {
+            let next = tcnt.saturating_sub(1);
+            if next == 0 {
+                c.timer1.down = false;
+                c.ram[TIFR] |= 1 << TOV1;
+                if kind == Kind::PhaseFrequencyCorrect {
+                    update_ocr(c);
+                }
+            }
+            next
+        }
+    };
+
+    write16(c, TCNT1L, next);
+}
+
+// Level of the input capture source, ICP1 or the analog comparator output.
+fn capture_input(c: &Chip) -> bool {
+    if (c.ram[ACSR] >> ACIC) & 1 != 0 {
+        return comparator::aco(c);
+    }
+    (c.ram[PIND] >> ICP1_BIT) & 1 != 0
+}
+
+// Samples the input capture source and copies TCNT1 to ICR1 on the selected
+// edge.
+fn tick_capture(c: &mut Chip, cycles: u64) {
+    let raw = capture_input(c);
+    let tccr1b = c.ram[TCCR1B];
+
+    if raw == c.timer1.icp {
+        c.timer1.icp_pending = 0;
+        return;
+    }
+
+    // The noise canceller only passes a change once the input has been
+    // stable for four samples.
+    if (tccr1b >> ICNC1) & 1 != 0 {
+        c.timer1.icp_pending += cycles;
+        if c.timer1.icp_pending < NOISE_CANCELLER_SAMPLES {
+            return;
+        }
+    }
+
+    c.timer1.icp = raw;
+    c.timer1.icp_pending = 0;
+
+    let rising = (tccr1b >> ICES1) & 1 != 0;
+    let (_, top_kind) = mode(c);
+    if raw == rising && top_kind != Top::Icr1 {
+        write16(c, ICR1L, read16(c, TCNT1L));
+        c.ram[TIFR] |= 1 << ICF1;
+    }
+}
+
+pub(crate) fn tick(c: &mut Chip, cycles: u64) {
+    let cs = c.ram[TCCR1B] & 0b111;
+
+    // External clock on T1, sampled once per step
+    let t1 = (c.ram[PINB] >> T1_BIT) & 1 != 0;
+    let prev_t1 = c.timer1.prev_t1;
+    c.timer1.prev_t1 = t1;
+
+    let ticks = match cs {
+        0 => 0,
+        1..=5 => prescaled_ticks(c.prescaler, cycles, PRESCALER_DIVISORS[cs as usize - 1]),
+        6 => (prev_t1 && !t1) as u64,
+        _ => (!prev_t1 && t1) as u64,
+    };
+
+    for _ in 0..ticks {
+        count(c);
+    }
+
+    tick_capture(c, cycles);
+}
+
+// 16-bit registers are read low byte first, which latches the high byte
+// into TEMP. OCR1A/OCR1B reads do not go through TEMP.
+pub(crate) fn io_read(c: &mut Chip, addr: usize) -> Option<u8> {
+    match addr {
+        TCNT1L | ICR1L => {
+            c.timer1.temp = c.ram[addr + 1];
+            Some(c.ram[addr])
+        }
+        TCNT1H | ICR1H => Some(c.timer1.temp),
+        _ => None,
+    }
+}
+
+// 16-bit registers are written high byte first, into TEMP. The low byte
+// write then updates both bytes at once.
+pub(crate) fn io_write(c: &mut Chip, addr: usize, val: u8) -> bool {
+    match addr {
+        TCNT1H | OCR1AH | OCR1BH | ICR1H => c.timer1.temp = val,
+        TCNT1L => {
+            c.ram[TCNT1L] = val;
+            c.ram[TCNT1H] = c.timer1.temp;
+            c.timer1.block_compare = true;
+        }
+        OCR1AL | OCR1BL => {
+            c.ram[addr] = val;
+            c.ram[addr + 1] = c.timer1.temp;
+        }
+        // ICR1 is only writable while it defines TOP
+        ICR1L => {
+            if mode(c).1 == Top::Icr1 {
+                c.ram[ICR1L] = val;
+                c.ram[ICR1H] = c.timer1.temp;
+            }
+        }
+        TCCR1A => {
+            // FOC1A/FOC1B force a compare output action in the non-PWM
+            // modes, without setting the flags. They always read as zero.
+            c.ram[TCCR1A] = val & !(1 << FOC1A | 1 << FOC1B);
+            if matches!(mode(c).0, Kind::Normal | Kind::Ctc) {
+                update_ocr(c);
+                if (val >> FOC1A) & 1 != 0 {
+                    compare_output(c, Channel::A);
+                }
+                if (val >> FOC1B) & 1 != 0 {
+                    compare_output(c, Channel::B);
+                }
+            }
+        }
+        _ => return false,
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write16_io(c: &mut Chip, low: usize, val: u16) {
+        c.data_write(low + 1, (val >> 8) as u8);
+        c.data_write(low, val as u8);
+    }
+
+    fn clock(c: &mut Chip, n: usize) {
+        for _ in 0..n {
+            tick(c, 1);
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_access_through_temp() {
+        let mut c = Chip::new();
+        write16_io(&mut c, TCNT1L, 0x1234);
+        assert_eq!(read16(&c, TCNT1L), 0x1234);
+
+        // The high byte is latched by the low byte read
+        assert_eq!(c.data_read(TCNT1L), 0x34);
+        c.ram[TCNT1H] = 0x56;
+        assert_eq!(c.data_read(TCNT1H), 0x12);
+
+        // Writing only the low byte uses the last high byte written
+        c.data_write(OCR1AL, 0x78);
+        assert_eq!(read16(&c, OCR1AL), 0x1278);
+    }
+
+    #[test]
+    fn ctc_with_two_compare_channels() {
+        let mut c = Chip::new();
+        write16_io(&mut c, OCR1AL, 99);
+        write16_io(&mut c, OCR1BL, 49);
+        c.data_write(TCCR1B, 1 << WGM12 | 0x01);
+
+        clock(&mut c, 50);
+        assert_eq!(c.ram[TIFR], 1 << OCF1B);
+        clock(&mut c, 50);
+        assert_eq!(c.ram[TIFR], 1 << OCF1B | 1 << OCF1A);
+        assert_eq!(read16(&c, TCNT1L), 0);
+    }
+
+    #[test]
+    fn fast_pwm_drives_both_outputs() {
+        // 8-bit fast PWM, non-inverting on OC1A and OC1B
+        let mut c = Chip::new();
+        c.data_write(DDRD, 1 << OC1A_BIT | 1 << OC1B_BIT);
+        write16_io(&mut c, OCR1AL, 63);
+        write16_io(&mut c, OCR1BL, 191);
+        c.data_write(TCCR1A, 0xA1);
+        c.data_write(TCCR1B, 1 << WGM12 | 0x01);
+        clock(&mut c, 256);
+
+        let (mut a, mut b) = (0, 0);
+        for _ in 0..256 {
+            clock(&mut c, 1);
+            a += (c.ram[PIND] >> OC1A_BIT) & 1;
+            b += (c.ram[PIND] >> OC1B_BIT) & 1;
+        }
+        assert_eq!((a, b), (64, 192));
+    }
+
+    #[test]
+    fn phase_correct_period() {
+        let mut c = Chip::new();
+        c.data_write(TCCR1A, 0x01);
+        c.data_write(TCCR1B, 0x01);
+
+        let mut overflows = vec![];
+        for n in 0..1200 {
+            clock(&mut c, 1);
+            if c.ram[TIFR] & 1 << TOV1 != 0 {
+                overflows.push(n);
+                c.ram[TIFR] = 0;
+            }
+        }
+        assert_eq!(overflows.len(), 2);
+        assert_eq!(overflows[1] - overflows[0], 510);
+    }
+
+    #[test]
+    fn input_capture_on_the_selected_edge() {
+        let mut c = Chip::new();
+        c.data_write(TCCR1B, 1 << ICES1 | 0x01);
+        clock(&mut c, 100);
+
+        c.ram[PIND] |= 1 << ICP1_BIT;
+        clock(&mut c, 1);
+        assert_eq!(
+            (c.ram[TIFR] & 1 << ICF1, read16(&c, ICR1L)),
+            (1 << ICF1, 101)
+        );
+
+        // Falling edges are ignored
+        c.ram[TIFR] = 0;
+        c.ram[PIND] &= !(1 << ICP1_BIT);
+        clock(&mut c, 1);
+        assert_eq!((c.ram[TIFR] & 1 << ICF1, read16(&c, ICR1L)), (0, 101));
+    }
+
+    #[test]
+    fn noise_canceller_rejects_glitches() {
+        let mut c = Chip::new();
+        c.data_write(TCCR1B, 1 << ICNC1 | 1 << ICES1 | 0x01);
+
+        c.ram[PIND] |= 1 << ICP1_BIT;
+        clock(&mut c, 3);
+        c.ram[PIND] &= !(1 << ICP1_BIT);
+        clock(&mut c, 1);
+        assert_eq!(c.ram[TIFR] & 1 << ICF1, 0);
+
+        c.ram[PIND] |= 1 << ICP1_BIT;
+        clock(&mut c, 3);
+        assert_eq!(c.ram[TIFR] & 1 << ICF1, 0);
+        clock(&mut c, 1);
+        assert_eq!((c.ram[TIFR] & 1 << ICF1, read16(&c, ICR1L)), (1 << ICF1, 8));
+    }
+
+    #[test]
+    fn icr1_is_only_writable_as_top() {
+        let mut c = Chip::new();
+        write16_io(&mut c, ICR1L, 0x0123);
+        assert_eq!(read16(&c, ICR1L), 0);
+
+        // Mode 14, fast PWM with ICR1 as TOP
+        c.data_write(TCCR1A, 0x02);
+        c.data_write(TCCR1B, 0x18);
+        write16_io(&mut c, ICR1L, 0x0123);
+        assert_eq!(read16(&c, ICR1L), 0x0123);
+    }
+
+    #[test]
+    fn external_clock_on_t1() {
+        // Falling edge
+        let mut c = Chip::new();
+        c.data_write(TCCR1B, 0x06);
+        for _ in 0..5 {
+            c.ram[PINB] |= 1 << T1_BIT;
+            tick(&mut c, 1);
+            c.ram[PINB] &= !(1 << T1_BIT);
+            tick(&mut c, 1);
+        }
+        assert_eq!(read16(&c, TCNT1L), 5);
+    }
+}
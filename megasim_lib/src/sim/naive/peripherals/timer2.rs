@@ -0,0 +1,368 @@
+use crate::sim::naive::chip::{Chip, SleepMode, State};
+use crate::sim::naive::peripherals::{TIFR, drive_pin, prescaled_ticks};
+
+//
+// 8-bit Timer/Counter2 with Asynchronous Operation (Page 112)
+//
+
+const OCR2: usize = 0x43;
+const TCNT2: usize = 0x44;
+const TCCR2: usize = 0x45;
+const ASSR: usize = 0x42;
+
+const DDRD: usize = 0x31;
+const PIND: usize = 0x30;
+
+// TCCR2: FOC2 | WGM20 | COM21 | COM20 | WGM21 | CS22 | CS21 | CS20
+const FOC2: u8 = 7;
+const WGM20: u8 = 6;
+const COM20: u8 = 4;
+const WGM21: u8 = 3;
+
+// ASSR: - | - | - | - | AS2 | TCN2UB | OCR2UB | TCR2UB
+const AS2: u8 = 3;
+const TCN2UB: u8 = 2;
+const OCR2UB: u8 = 1;
+const TCR2UB: u8 = 0;
+
+// TIFR / TIMSK
+const TOV2: u8 = 6;
+const OCF2: u8 = 7;
+
+// OC2 is PD7
+const OC2_BIT: u8 = 7;
+
+const MAX: u8 = 0xFF;
+
+// Watch crystal on TOSC1/TOSC2, in Hz
+pub const TOSC_FREQ: u64 = 32_768;
+
+// TOSC1 edges a write takes to reach the asynchronous timer registers
+const SYNC_EDGES: u8 = 2;
+
+// Clock select divisors for CS 1-7 of Timer2.
+const PRESCALER_DIVISORS: [u64; 7] = [1, 8, 32, 64, 128, 256, 1024];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Normal,
+    PhaseCorrect,
+    Ctc,
+    FastPwm,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timer2 {
+    // OC2 output compare state
+    pub oc2: bool,
+    // OCR2 value in use, double buffered in the PWM modes
+    ocr: u8,
+    // Counting down in phase correct mode
+    down: bool,
+    // A TCNT2 write blocks the compare match on the next timer clock
+    block_compare: bool,
+    // Timer2 prescaler, in clk_I/O or TOSC1 cycles
+    pub(crate) prescaler: u64,
+    // Time towards the next TOSC1 edge, in ns scaled by TOSC_FREQ
+    tosc_phase: u64,
+    // Asynchronous register writes waiting to be synchronised, and the TOSC1
+    // edges left until they are
+    tcnt_pending: Option<u8>,
+    ocr_pending: Option<u8>,
+    tccr_pending: Option<u8>,
+    sync_edges: u8,
+}
+
+fn mode(tccr2: u8) -> Mode {
+    match ((tccr2 >> WGM21) & 1, (tccr2 >> WGM20) & 1) {
+        (0, 0) => Mode::Normal,
+        (0, 1) => Mode::PhaseCorrect,
+        (1, 0) => Mode::Ctc,
+        _ => Mode::FastPwm,
+    }
+}
+
+fn is_async(c: &Chip) -> bool {
+    (c.ram[ASSR] >> AS2) & 1 != 0
+}
+
+fn set_oc2(c: &mut Chip, level: bool) {
+    c.timer2.oc2 = level;
+    drive_pin(c, DDRD, PIND, OC2_BIT, level);
+}
+
+// Output compare action on a compare match (Table 52-54).
+fn compare_output(c: &mut Chip, tccr2: u8) {
+    let com = (tccr2 >> COM20) & 0b11;
+    let oc2 = c.timer2.oc2;
+
+    let level = match (mode(tccr2), com) {
+        (_, 0b00) => return,
+        (Mode::Normal | Mode::Ctc, 0b01) => !oc2,
+        (Mode::Normal | Mode::Ctc, 0b10) => false,
+        (Mode::Normal | Mode::Ctc, _) => true,
+        (_, 0b01) => return,
+        // OCR2 = MAX gives a constant output in fast PWM
+        (Mode::FastPwm, _) if c.timer2.ocr == MAX => return,
+        (Mode::FastPwm, 0b10) => false,
+        (Mode::FastPwm, _) => true,
+        (Mode::PhaseCorrect, 0b10) => c.timer2.down,
+        (Mode::PhaseCorrect, _) => !c.timer2.down,
+    };
+
+    set_oc2(c, level);
+}
+
+// One clock of the timer.
+fn count(c: &mut Chip) {
+    let tccr2 = c.ram[TCCR2];
+    let mode = mode(tccr2);
+    let tcnt = c.ram[TCNT2];
+
+    if matches!(mode, Mode::Normal | Mode::Ctc) {
+        c.timer2.ocr = c.ram[OCR2];
+    }
+
+    let blocked = c.timer2.block_compare;
+    c.timer2.block_compare = false;
+    if tcnt == c.timer2.ocr && !blocked {
+        c.ram[TIFR] |= 1 << OCF2;
+        compare_output(c, tccr2);
+    }
+
+    let next = match mode {
+        Mode::Normal => tcnt.wrapping_add(1),
+        Mode::Ctc if tcnt == c.timer2.ocr => 0,
+        Mode::Ctc => tcnt.wrapping_add(1),
+        Mode::FastPwm => tcnt.wrapping_add(1),
+        Mode::PhaseCorrect if c.timer2.down => tcnt.saturating_sub(1),
+        Mode::PhaseCorrect => tcnt.saturating_add(1),
+    };
+
+    match mode {
+        Mode::Normal | Mode::Ctc if tcnt == MAX => c.ram[TIFR] |= 1 << TOV2,
+        Mode::FastPwm if tcnt == MAX => {
+            c.ram[TIFR] |= 1 << TOV2;
+            c.timer2.ocr = c.ram[OCR2];
+
+            // Non-inverting output is set at BOTTOM, inverting is cleared
+            match (tccr2 >> COM20) & 0b11 {
+                0b10 if c.timer2.ocr != MAX => set_oc2(c, true),
+                0b11 if c.timer2.ocr != MAX => set_oc2(c, false),
+                _ => {}
+            }
+        }
+        Mode::PhaseCorrect if next == MAX => {
+            c.timer2.down = true;
+            c.timer2.ocr = c.ram[OCR2];
+        }
+        Mode::PhaseCorrect if next == 0 && c.timer2.down => {
+            c.timer2.down = false;
+            c.ram[TIFR] |= 1 << TOV2;
+        }
+        _ => {}
+    }
+
+    c.ram[TCNT2] = next;
+}
+
+// Moves pending asynchronous writes into the timer registers and clears
+// their busy flags in ASSR.
+fn synchronise(c: &mut Chip) {
+    if let Some(val) = c.timer2.tcnt_pending.take() {
+        c.ram[TCNT2] = val;
+        c.timer2.block_compare = true;
+    }
+    if let Some(val) = c.timer2.ocr_pending.take() {
+        c.ram[OCR2] = val;
+    }
+    if let Some(val) = c.timer2.tccr_pending.take() {
+        write_tccr2(c, val);
+    }
+
+    c.ram[ASSR] &= !(1 << TCN2UB | 1 << OCR2UB | 1 << TCR2UB);
+}
+
+// Runs the prescaler and counter for `cycles` cycles of the Timer2 clock.
+fn clock(c: &mut Chip, cycles: u64) {
+    let cs = c.ram[TCCR2] & 0b111;
+    if cs != 0 {
+        let divisor = PRESCALER_DIVISORS[cs as usize - 1];
+        for _ in 0..prescaled_ticks(c.timer2.prescaler, cycles, divisor) {
+            count(c);
+        }
+    }
+
+    c.timer2.prescaler = (c.timer2.prescaler + cycles) % 1024;
+}
+
+// `cycles` is the step in clk_I/O cycles, `time_delta` the same step in ns,
+// which clocks the timer from the watch crystal in asynchronous mode.
+pub(crate) fn tick(c: &mut Chip, cycles: u64, time_delta: u64) {
+    if !is_async(c) {
+        // clk_I/O only keeps running in idle sleep
+        if matches!(c.state, State::Running | State::Sleeping(SleepMode::Idle)) {
+            clock(c, cycles);
+        }
+        return;
+    }
+
+    // The crystal oscillator is stopped in power-down and standby
+    if matches!(
+        c.state,
+        State::Sleeping(SleepMode::PowerDown | SleepMode::Standby)
+    ) {
+        return;
+    }
+
+    c.timer2.tosc_phase += time_delta * TOSC_FREQ;
+    let edges = c.timer2.tosc_phase / 1_000_000_000;
+    c.timer2.tosc_phase %= 1_000_000_000;
+
+    for _ in 0..edges {
+        if c.timer2.sync_edges > 0 {
+            c.timer2.sync_edges -= 1;
+            if c.timer2.sync_edges == 0 {
+                synchronise(c);
+            }
+        }
+        clock(c, 1);
+    }
+}
+
+fn write_tccr2(c: &mut Chip, val: u8) {
+    // FOC2 forces a compare output action in the non-PWM modes, without
+    // setting OCF2. It always reads as zero.
+    c.ram[TCCR2] = val & !(1 << FOC2);
+    if (val >> FOC2) & 1 != 0 && matches!(mode(val), Mode::Normal | Mode::Ctc) {
+        c.timer2.ocr = c.ram[OCR2];
+        compare_output(c, val);
+    }
+}
+
+// In asynchronous mode TCNT2, OCR2 and TCCR2 writes go through a temporary
+// register and only reach the timer after a couple of TOSC1 edges. ASSR
+// flags the registers that are still busy.
+pub(crate) fn io_write(c: &mut Chip, addr: usize, val: u8) -> bool {
+    let busy = match addr {
+        TCNT2 => TCN2UB,
+        OCR2 => OCR2UB,
+        TCCR2 => TCR2UB,
+        // The busy flags are read only
+        ASSR => {
+            c.ram[ASSR] = (c.ram[ASSR] & 0b111) | (val & 1 << AS2);
+            return true;
+        }
+        _ => return false,
+    };
+
+    if is_async(c) {
+        match addr {
+            TCNT2 => c.timer2.tcnt_pending = Some(val),
+            OCR2 => c.timer2.ocr_pending = Some(val),
+            _ => c.timer2.tccr_pending = Some(val),
+        }
+        c.ram[ASSR] |= 1 << busy;
+        c.timer2.sync_edges = SYNC_EDGES;
+        return true;
+    }
+
+    match addr {
+        TCNT2 => {
+            c.ram[TCNT2] = val;
+            c.timer2.block_compare = true;
+        }
+        OCR2 => c.ram[OCR2] = val,
+        _ => write_tccr2(c, val),
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two TOSC1 edges of the 32.768 kHz crystal, in ns
+    const TWO_EDGES: u64 = 61_036;
+
+    fn async_timer(tccr2: u8) -> Chip {
+        let mut c = Chip::new();
+        c.data_write(ASSR, 1 << AS2);
+        c.data_write(TCCR2, tccr2);
+        tick(&mut c, 1, TWO_EDGES);
+        c
+    }
+
+    #[test]
+    fn ctc_in_synchronous_mode() {
+        let mut c = Chip::new();
+        c.data_write(OCR2, 9);
+        c.data_write(TCCR2, 1 << WGM21 | 0x01);
+        for _ in 0..10 {
+            tick(&mut c, 1, 125);
+        }
+        assert_eq!((c.ram[TCNT2], c.ram[TIFR]), (0, 1 << OCF2));
+    }
+
+    #[test]
+    fn timer2_has_its_own_divisors() {
+        let mut c = Chip::new();
+        c.data_write(TCCR2, 0x03);
+        tick(&mut c, 32 * 5, 125 * 32 * 5);
+        assert_eq!(c.ram[TCNT2], 5);
+    }
+
+    #[test]
+    fn asynchronous_writes_wait_for_tosc1() {
+        let mut c = Chip::new();
+        c.data_write(ASSR, 0xFF);
+        assert_eq!(c.ram[ASSR], 1 << AS2);
+
+        c.data_write(TCCR2, 0x05);
+        c.data_write(OCR2, 0x80);
+        assert_eq!((c.ram[TCCR2], c.ram[OCR2]), (0, 0));
+        assert_eq!(c.ram[ASSR], 1 << AS2 | 1 << TCR2UB | 1 << OCR2UB);
+
+        tick(&mut c, 1, TWO_EDGES);
+        assert_eq!((c.ram[TCCR2], c.ram[OCR2]), (0x05, 0x80));
+        assert_eq!(c.ram[ASSR], 1 << AS2);
+    }
+
+    #[test]
+    fn watch_crystal_overflows_once_per_second() {
+        // TOSC1 / 128 / 256
+        let mut c = async_timer(0x05);
+        let mut overflows = 0;
+        for _ in 0..3_500 {
+            tick(&mut c, 8_000, 1_000_000);
+            if c.ram[TIFR] & 1 << TOV2 != 0 {
+                overflows += 1;
+                c.ram[TIFR] = 0;
+            }
+        }
+        assert_eq!(overflows, 3);
+    }
+
+    #[test]
+    fn sleep_modes_stop_the_timer_clock() {
+        let mut c = async_timer(0x01);
+        let start = c.ram[TCNT2];
+        c.state = State::Sleeping(SleepMode::PowerSave);
+        tick(&mut c, 8_000, 1_000_000);
+        assert_eq!(c.ram[TCNT2] - start, 32);
+
+        c.state = State::Sleeping(SleepMode::PowerDown);
+        tick(&mut c, 8_000, 1_000_000);
+        assert_eq!(c.ram[TCNT2] - start, 32);
+
+        // Synchronous mode only counts while clk_I/O runs
+        let mut c = Chip::new();
+        c.data_write(TCCR2, 0x01);
+        c.state = State::Sleeping(SleepMode::Idle);
+        tick(&mut c, 10, 1_250);
+        c.state = State::Sleeping(SleepMode::PowerSave);
+        tick(&mut c, 10, 1_250);
+        assert_eq!(c.ram[TCNT2], 10);
+    }
+}
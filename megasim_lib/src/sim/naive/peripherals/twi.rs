@@ -0,0 +1,699 @@
+use crate::sim::naive::chip::Chip;
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt::Debug,
+    rc::Rc,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+//
+// Two-wire Serial Interface (Page 170)
+//
+
+const TWBR: usize = 0x20;
+const TWSR: usize = 0x21;
+const TWAR: usize = 0x22;
+const TWDR: usize = 0x23;
+const TWCR: usize = 0x56;
+
+// TWCR: TWINT | TWEA | TWSTA | TWSTO | TWWC | TWEN | - | TWIE
+const TWINT: u8 = 7;
+const TWEA: u8 = 6;
+const TWSTA: u8 = 5;
+const TWSTO: u8 = 4;
+const TWWC: u8 = 3;
+const TWEN: u8 = 2;
+
+// TWAR: TWA6..0 | TWGCE
+const TWGCE: u8 = 0;
+
+// Status codes (Tables 74-77)
+const START: u8 = 0x08;
+const REPEATED_START: u8 = 0x10;
+const MT_SLA_ACK: u8 = 0x18;
+const MT_SLA_NACK: u8 = 0x20;
+const MT_DATA_ACK: u8 = 0x28;
+const MT_DATA_NACK: u8 = 0x30;
+const MR_SLA_ACK: u8 = 0x40;
+const MR_SLA_NACK: u8 = 0x48;
+const MR_DATA_ACK: u8 = 0x50;
+const MR_DATA_NACK: u8 = 0x58;
+const SR_SLA_ACK: u8 = 0x60;
+const SR_GCALL_ACK: u8 = 0x70;
+const SR_DATA_ACK: u8 = 0x80;
+const SR_DATA_NACK: u8 = 0x88;
+const SR_GCALL_DATA_ACK: u8 = 0x90;
+const SR_GCALL_DATA_NACK: u8 = 0x98;
+const SR_STOP: u8 = 0xA0;
+const ST_SLA_ACK: u8 = 0xA8;
+const ST_DATA_ACK: u8 = 0xB8;
+const ST_DATA_NACK: u8 = 0xC0;
+const ST_LAST_DATA: u8 = 0xC8;
+const NO_INFO: u8 = 0xF8;
+
+// SCL rate of the host master talking to the chip as a slave
+const HOST_SCL_FREQ: u64 = 100_000;
+
+// A slave device on the bus, addressed by its 7-bit address. Bytes are
+// passed whole, the acknowledge bit is the return value of `start` and
+// `write`.
+pub trait TwiDevice: Debug {
+    // 7-bit slave address.
+    fn address(&self) -> u8;
+
+    // Called when a (repeated) START addresses the device, `read` for SLA+R.
+    // Returns whether the device acknowledges.
+    fn start(&mut self, _read: bool) -> bool {
+        true
+    }
+
+    // Receives a byte from the master. Returns whether the device
+    // acknowledges it.
+    fn write(&mut self, byte: u8) -> bool;
+
+    // Sends a byte to the master.
+    fn read(&mut self) -> u8;
+
+    // Called on a STOP condition.
+    fn stop(&mut self) {}
+
+    // Called with the simulated time passed, in ns, for devices that keep
+    // time.
+    fn tick(&mut self, _ns: u64) {}
+}
+
+// A transfer by the host, acting as a master with the chip as its slave.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TwiTransfer {
+    Write { address: u8, data: Vec<u8> },
+    Read { address: u8, len: usize },
+}
+
+// Outcome of a host transfer.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TwiReply {
+    pub address_acked: bool,
+    // Bytes written that the chip acknowledged
+    pub acked: usize,
+    // Bytes read from the chip
+    pub data: Vec<u8>,
+}
+
+// Host end of the bus. Transfers sent on `tx` are carried out in order, and
+// their replies arrive on `rx`.
+#[derive(Debug)]
+pub struct TwiHost {
+    pub tx: Sender<TwiTransfer>,
+    pub rx: Receiver<TwiReply>,
+}
+
+// Master mode operations, started by clearing TWINT
+#[derive(Debug, Clone, Copy)]
+enum Operation {
+    Start,
+    Address(u8),
+    Transmit(u8),
+    Receive(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HostStage {
+    Address,
+    Byte(usize),
+    Stop,
+    Done,
+}
+
+#[derive(Debug)]
+struct HostState {
+    transfer: TwiTransfer,
+    stage: HostStage,
+    general_call: bool,
+    // TWEA was clear when the current byte started, so the slave transmitter
+    // sends its last byte
+    last_byte: bool,
+    reply: TwiReply,
+    // Cycles left in the current stage, counted while SCL is released
+    left: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Twi {
+    // Master operation in progress, and the cycles left until it completes
+    operation: Option<(Operation, u64)>,
+    // The chip holds the bus as a master, between START and STOP
+    bus_owned: bool,
+    // Device addressed in master mode
+    addressed: Option<usize>,
+    devices: Vec<Rc<RefCell<dyn TwiDevice>>>,
+
+    // Host connection, transfers waiting and the one in progress
+    from_host: Option<Receiver<TwiTransfer>>,
+    to_host: Option<Sender<TwiReply>>,
+    host_queue: VecDeque<TwiTransfer>,
+    host: Option<HostState>,
+}
+
+fn bit(val: u8, bit: u8) -> bool {
+    (val >> bit) & 1 != 0
+}
+
+fn set_status(c: &mut Chip, status: u8) {
+    c.ram[TWSR] = (c.ram[TWSR] & 0b11) | status;
+}
+
+fn status(c: &Chip) -> u8 {
+    c.ram[TWSR] & !0b11
+}
+
+// Sets TWINT with a new status.
+fn interrupt(c: &mut Chip, status: u8) {
+    set_status(c, status);
+    c.ram[TWCR] |= 1 << TWINT;
+}
+
+// Clock cycles per SCL period: 16 + 2 * TWBR * 4^TWPS.
+fn scl_cycles(c: &Chip) -> u64 {
+    let prescaler = 4u64.pow((c.ram[TWSR] & 0b11) as u32);
+    16 + 2 * c.ram[TWBR] as u64 * prescaler
+}
+
+fn operation_cycles(c: &Chip, operation: Operation) -> u64 {
+    match operation {
+        Operation::Start => scl_cycles(c),
+        // 8 data bits and the acknowledge
+        _ => 9 * scl_cycles(c),
+    }
+}
+
+// Resets the TWI registers. Attached devices stay on the bus and the host
+// stays connected.
+pub(crate) fn reset(c: &mut Chip) {
+    c.twi.operation = None;
+    c.twi.bus_owned = false;
+    c.twi.addressed = None;
+    c.twi.host = None;
+    c.ram[TWBR] = 0;
+    c.ram[TWAR] = 0xFE;
+    c.ram[TWDR] = 0xFF;
+    set_status(c, NO_INFO);
+}
+
+pub(crate) fn attach(c: &mut Chip, device: Rc<RefCell<dyn TwiDevice>>) {
+    c.twi.devices.push(device);
+}
+
+// Connects a host master to the bus, replacing any earlier connection.
+pub(crate) fn connect(c: &mut Chip) -> TwiHost {
+    let (tx, from_host) = mpsc::channel();
+    let (to_host, rx) = mpsc::channel();
+
+    c.twi.from_host = Some(from_host);
+    c.twi.to_host = Some(to_host);
+    c.twi.host_queue.clear();
+
+    TwiHost { tx, rx }
+}
+
+fn release_bus(c: &mut Chip) {
+    if let Some(index) = c.twi.addressed.take() {
+        c.twi.devices[index].borrow_mut().stop();
+    }
+    c.twi.bus_owned = false;
+}
+
+// Carries out a master operation once its time has passed.
+fn complete(c: &mut Chip, operation: Operation) {
+    let device = c.twi.addressed.map(|index| c.twi.devices[index].clone());
+
+    let status = match operation {
+        Operation::Start => {
+            let status = if c.twi.bus_owned {
+                REPEATED_START
+            } else {
+                START
+            };
+            c.twi.bus_owned = true;
+            c.twi.addressed = None;
+            status
+        }
+        Operation::Address(sla) => {
+            let read = sla & 1 != 0;
+            c.twi.addressed = c
+                .twi
+                .devices
+                .iter()
+                .position(|device| device.borrow().address() == sla >> 1)
+                .filter(|index| c.twi.devices[*index].borrow_mut().start(read));
+
+            match (read, c.twi.addressed.is_some()) {
+                (false, true) => MT_SLA_ACK,
+                (false, false) => MT_SLA_NACK,
+                (true, true) => MR_SLA_ACK,
+                (true, false) => MR_SLA_NACK,
+            }
+        }
+        Operation::Transmit(byte) => {
+            let ack = device.is_some_and(|device| device.borrow_mut().write(byte));
+            if ack { MT_DATA_ACK } else { MT_DATA_NACK }
+        }
+        Operation::Receive(ack) => {
+            // SDA floats high without a device
+            c.ram[TWDR] = device.map_or(0xFF, |device| device.borrow_mut().read());
+            if ack { MR_DATA_ACK } else { MR_DATA_NACK }
+        }
+    };
+
+    interrupt(c, status);
+}
+
+// Starts what the TWCR bits ask for after TWINT has been cleared.
+fn start_operation(c: &mut Chip) {
+    let twcr = c.ram[TWCR];
+
+    if bit(twcr, TWSTO) {
+        // TWSTO clears itself once the STOP has been sent
+        c.ram[TWCR] &= !(1 << TWSTO);
+        release_bus(c);
+    }
+
+    let operation = if bit(twcr, TWSTA) {
+        Operation::Start
+    } else if c.twi.bus_owned {
+        match status(c) {
+            START | REPEATED_START => Operation::Address(c.ram[TWDR]),
+            MT_SLA_ACK | MT_SLA_NACK | MT_DATA_ACK | MT_DATA_NACK => {
+                Operation::Transmit(c.ram[TWDR])
+            }
+            MR_SLA_ACK | MR_DATA_ACK => Operation::Receive(bit(twcr, TWEA)),
+            _ => return,
+        }
+    } else {
+        return;
+    };
+
+    c.twi.operation = Some((operation, operation_cycles(c, operation)));
+}
+
+fn finish_host_transfer(c: &mut Chip) {
+    if let Some(host) = c.twi.host.take()
+        && let Some(to_host) = &c.twi.to_host
+    {
+        // The host may have hung up, the reply is lost then
+        let _ = to_host.send(host.reply);
+    }
+}
+
+// Advances a host master transfer by one stage, with the chip as a slave.
+fn host_stage(c: &mut Chip, host: &mut HostState) {
+    let byte_cycles = 9 * c.clock_freq.div_ceil(HOST_SCL_FREQ);
+    let twea = bit(c.ram[TWCR], TWEA);
+
+    match (host.transfer.clone(), host.stage) {
+        (
+            TwiTransfer::Write { address, .. } | TwiTransfer::Read { address, .. },
+            HostStage::Address,
+        ) => {
+            let read = matches!(host.transfer, TwiTransfer::Read { .. });
+            let own = c.ram[TWAR] >> 1 == address;
+            host.general_call = address == 0 && !read && bit(c.ram[TWAR], TWGCE);
+
+            if !bit(c.ram[TWCR], TWEN) || !twea || !(own || host.general_call) {
+                host.stage = HostStage::Done;
+                return;
+            }
+
+            host.reply.address_acked = true;
+            host.stage = HostStage::Byte(0);
+            host.left = byte_cycles;
+            interrupt(
+                c,
+                match (read, host.general_call) {
+                    (true, _) => ST_SLA_ACK,
+                    (false, true) => SR_GCALL_ACK,
+                    (false, false) => SR_SLA_ACK,
+                },
+            );
+        }
+        (TwiTransfer::Write { data, .. }, HostStage::Byte(i)) => {
+            if i == data.len() {
+                host.stage = HostStage::Stop;
+                interrupt(c, SR_STOP);
+                return;
+            }
+
+            c.ram[TWDR] = data[i];
+            if twea {
+                host.reply.acked += 1;
+                host.stage = HostStage::Byte(i + 1);
+                host.left = byte_cycles;
+            } else {
+                // Not acknowledged, the master gives up
+                host.stage = HostStage::Done;
+            }
+            interrupt(
+                c,
+                match (host.general_call, twea) {
+                    (false, true) => SR_DATA_ACK,
+                    (false, false) => SR_DATA_NACK,
+                    (true, true) => SR_GCALL_DATA_ACK,
+                    (true, false) => SR_GCALL_DATA_NACK,
+                },
+            );
+        }
+        (TwiTransfer::Read { len, .. }, HostStage::Byte(i)) => {
+            host.reply.data.push(c.ram[TWDR]);
+            // The master acknowledges every byte but the last
+            let master_ack = i + 1 < len;
+
+            let status = if !master_ack {
+                ST_DATA_NACK
+            } else if host.last_byte {
+                ST_LAST_DATA
+            } else {
+                ST_DATA_ACK
+            };
+
+            if status == ST_DATA_ACK {
+                host.stage = HostStage::Byte(i + 1);
+                host.left = byte_cycles;
+            } else {
+                // The slave stops driving SDA, the rest reads as ones
+                host.reply.data.resize(len, 0xFF);
+                host.stage = HostStage::Done;
+            }
+            interrupt(c, status);
+        }
+        (_, HostStage::Stop) => host.stage = HostStage::Done,
+        (_, HostStage::Done) => {}
+    }
+}
+
+fn tick_host(c: &mut Chip, cycles: u64) {
+    if let Some(from_host) = &c.twi.from_host {
+        c.twi.host_queue.extend(from_host.try_iter());
+    }
+
+    // The host waits for the bus to be free
+    if c.twi.host.is_none() {
+        if c.twi.bus_owned || c.twi.operation.is_some() {
+            return;
+        }
+        let Some(transfer) = c.twi.host_queue.pop_front() else {
+            return;
+        };
+        c.twi.host = Some(HostState {
+            transfer,
+            stage: HostStage::Address,
+            general_call: false,
+            last_byte: false,
+            reply: TwiReply::default(),
+            // START and the address byte
+            left: 10 * c.clock_freq.div_ceil(HOST_SCL_FREQ),
+        });
+    }
+
+    let Some(mut host) = c.twi.host.take() else {
+        return;
+    };
+
+    // The slave holds SCL low while TWINT is set
+    if !bit(c.ram[TWCR], TWINT) {
+        if host.stage == HostStage::Done {
+            c.twi.host = Some(host);
+            finish_host_transfer(c);
+            return;
+        }
+
+        host.left = host.left.saturating_sub(cycles);
+        if host.left == 0 {
+            host_stage(c, &mut host);
+        }
+    }
+
+    c.twi.host = Some(host);
+}
+
+pub(crate) fn tick(c: &mut Chip, cycles: u64) {
+    if !bit(c.ram[TWCR], TWEN) {
+        return;
+    }
+
+    if let Some((operation, left)) = c.twi.operation {
+        if cycles < left {
+            c.twi.operation = Some((operation, left - cycles));
+        } else {
+            c.twi.operation = None;
+            complete(c, operation);
+        }
+    }
+
+    tick_host(c, cycles);
+}
+
+// Devices keep their own time, even while the chip sleeps.
+pub(crate) fn tick_devices(c: &mut Chip, time_delta: u64) {
+    for device in &c.twi.devices {
+        device.borrow_mut().tick(time_delta);
+    }
+}
+
+pub(crate) fn io_write(c: &mut Chip, addr: usize, val: u8) -> bool {
+    match addr {
+        // TWDR can only be written while TWINT is set
+        TWDR => {
+            if bit(c.ram[TWCR], TWINT) {
+                c.ram[TWDR] = val;
+                c.ram[TWCR] &= !(1 << TWWC);
+            } else {
+                c.ram[TWCR] |= 1 << TWWC;
+            }
+        }
+        // Only the prescaler bits are writable
+        TWSR => c.ram[TWSR] = (c.ram[TWSR] & !0b11) | (val & 0b11),
+        TWCR => {
+            let twint = c.ram[TWCR] & (1 << TWINT);
+            let twwc = c.ram[TWCR] & (1 << TWWC);
+            c.ram[TWCR] = (val & !(1 << TWINT | 1 << TWWC)) | twint | twwc;
+
+            if !bit(val, TWEN) {
+                // Disabling the TWI releases the bus and aborts transfers
+                c.twi.operation = None;
+                c.twi.bus_owned = false;
+                c.twi.addressed = None;
+                c.ram[TWCR] &= !(1 << TWINT | 1 << TWSTO);
+                set_status(c, NO_INFO);
+                finish_host_transfer(c);
+                return true;
+            }
+
+            // TWINT is cleared by writing a one to it, which starts the next
+            // operation
+            if bit(val, TWINT) {
+                c.ram[TWCR] &= !(1 << TWINT);
+
+                match &mut c.twi.host {
+                    Some(host) => host.last_byte = !bit(val, TWEA),
+                    None => start_operation(c),
+                }
+                set_status(c, NO_INFO);
+            }
+        }
+        _ => return false,
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::naive::devices::Eeprom24c02;
+
+    const GO: u8 = 1 << TWINT | 1 << TWEN;
+    const GO_ACK: u8 = GO | 1 << TWEA;
+
+    fn master() -> (Chip, Rc<RefCell<Eeprom24c02>>) {
+        let mut c = Chip::new();
+        let eeprom = Rc::new(RefCell::new(Eeprom24c02::new(0)));
+        c.attach_twi_device(eeprom.clone());
+        c.data_write(TWBR, 2);
+        (c, eeprom)
+    }
+
+    // Waits for TWINT and returns the status.
+    fn wait(c: &mut Chip) -> u8 {
+        for _ in 0..100_000 {
+            if bit(c.ram[TWCR], TWINT) {
+                return status(c);
+            }
+            tick(c, 1);
+        }
+        panic!("TWINT never set");
+    }
+
+    fn send(c: &mut Chip, twcr: u8) -> u8 {
+        c.data_write(TWCR, twcr);
+        wait(c)
+    }
+
+    fn transmit(c: &mut Chip, byte: u8) -> u8 {
+        c.data_write(TWDR, byte);
+        send(c, GO)
+    }
+
+    #[test]
+    fn master_transmitter() {
+        let (mut c, eeprom) = master();
+        c.data_write(TWCR, GO | 1 << TWSTA);
+        // One SCL period: 16 + 2 * TWBR
+        tick(&mut c, 19);
+        assert!(!bit(c.ram[TWCR], TWINT));
+        tick(&mut c, 1);
+        assert_eq!(wait(&mut c), START);
+
+        assert_eq!(transmit(&mut c, 0xA0), MT_SLA_ACK);
+        assert_eq!(transmit(&mut c, 0x10), MT_DATA_ACK);
+        assert_eq!(transmit(&mut c, 0x11), MT_DATA_ACK);
+        assert_eq!(transmit(&mut c, 0x22), MT_DATA_ACK);
+        assert_eq!(eeprom.borrow().data[0x10], 0xFF);
+
+        c.data_write(TWCR, GO | 1 << TWSTO);
+        assert_eq!(status(&c), NO_INFO);
+        assert_eq!(c.ram[TWCR], 1 << TWEN);
+        assert_eq!(eeprom.borrow().data[0x10..0x12], [0x11, 0x22]);
+    }
+
+    #[test]
+    fn master_receiver() {
+        let (mut c, eeprom) = master();
+        eeprom.borrow_mut().data[0x10..0x12].copy_from_slice(&[0x11, 0x22]);
+
+        assert_eq!(send(&mut c, GO | 1 << TWSTA), START);
+        assert_eq!(transmit(&mut c, 0xA0), MT_SLA_ACK);
+        assert_eq!(transmit(&mut c, 0x10), MT_DATA_ACK);
+        assert_eq!(send(&mut c, GO | 1 << TWSTA), REPEATED_START);
+        assert_eq!(transmit(&mut c, 0xA1), MR_SLA_ACK);
+        assert_eq!(send(&mut c, GO_ACK), MR_DATA_ACK);
+        assert_eq!(c.ram[TWDR], 0x11);
+        assert_eq!(send(&mut c, GO), MR_DATA_NACK);
+        assert_eq!(c.ram[TWDR], 0x22);
+    }
+
+    #[test]
+    fn nobody_answers() {
+        let (mut c, _) = master();
+        assert_eq!(send(&mut c, GO | 1 << TWSTA), START);
+        assert_eq!(transmit(&mut c, 0xB0), MT_SLA_NACK);
+        assert_eq!(send(&mut c, GO | 1 << TWSTA), REPEATED_START);
+        assert_eq!(transmit(&mut c, 0xB1), MR_SLA_NACK);
+    }
+
+    #[test]
+    fn register_access() {
+        let mut c = Chip::new();
+        c.data_write(TWCR, 1 << TWEN);
+
+        // TWDR writes need TWINT
+        c.data_write(TWDR, 0x12);
+        assert_eq!((c.ram[TWDR], c.ram[TWCR]), (0xFF, 1 << TWWC | 1 << TWEN));
+
+        // Only the prescaler bits of TWSR are writable
+        c.data_write(TWSR, 0xFF);
+        assert_eq!(c.ram[TWSR], NO_INFO | 0b11);
+        c.data_write(TWBR, 1);
+        assert_eq!(scl_cycles(&c), 16 + 2 * 64);
+    }
+
+    fn slave() -> (Chip, TwiHost) {
+        let mut c = Chip::new();
+        let host = c.twi_host();
+        c.data_write(TWAR, 0x30 << 1 | 1 << TWGCE);
+        c.data_write(TWCR, 1 << TWEA | 1 << TWEN);
+        (c, host)
+    }
+
+    #[test]
+    fn slave_receiver() {
+        let (mut c, host) = slave();
+        let data = vec![1, 2];
+        host.tx
+            .send(TwiTransfer::Write {
+                address: 0x30,
+                data,
+            })
+            .unwrap();
+
+        assert_eq!(wait(&mut c), SR_SLA_ACK);
+        assert_eq!(send(&mut c, GO_ACK), SR_DATA_ACK);
+        assert_eq!(c.ram[TWDR], 1);
+        // Not acknowledging the second byte ends the transfer
+        assert_eq!(send(&mut c, GO), SR_DATA_NACK);
+        assert_eq!(c.ram[TWDR], 2);
+        c.data_write(TWCR, GO_ACK);
+        tick(&mut c, 1);
+
+        let reply = host.rx.try_recv().unwrap();
+        assert_eq!((reply.address_acked, reply.acked), (true, 1));
+    }
+
+    #[test]
+    fn slave_general_call_and_stop() {
+        let (mut c, host) = slave();
+        host.tx
+            .send(TwiTransfer::Write {
+                address: 0,
+                data: vec![7],
+            })
+            .unwrap();
+
+        assert_eq!(wait(&mut c), SR_GCALL_ACK);
+        assert_eq!(send(&mut c, GO_ACK), SR_GCALL_DATA_ACK);
+        assert_eq!(send(&mut c, GO_ACK), SR_STOP);
+        c.data_write(TWCR, GO_ACK);
+        for _ in 0..2 {
+            tick(&mut c, 1);
+        }
+        assert_eq!(host.rx.try_recv().unwrap().acked, 1);
+    }
+
+    #[test]
+    fn slave_transmitter() {
+        let (mut c, host) = slave();
+        host.tx
+            .send(TwiTransfer::Read {
+                address: 0x30,
+                len: 3,
+            })
+            .unwrap();
+
+        assert_eq!(wait(&mut c), ST_SLA_ACK);
+        c.data_write(TWDR, 0x5A);
+        assert_eq!(send(&mut c, GO_ACK), ST_DATA_ACK);
+        // Without TWEA the next byte is the last one
+        c.data_write(TWDR, 0xA5);
+        assert_eq!(send(&mut c, GO), ST_LAST_DATA);
+        c.data_write(TWCR, GO_ACK);
+        tick(&mut c, 1);
+
+        let reply = host.rx.try_recv().unwrap();
+        assert_eq!(reply.data, [0x5A, 0xA5, 0xFF]);
+    }
+
+    #[test]
+    fn other_addresses_are_not_acknowledged() {
+        let (mut c, host) = slave();
+        host.tx
+            .send(TwiTransfer::Read {
+                address: 0x31,
+                len: 1,
+            })
+            .unwrap();
+        for _ in 0..2_000 {
+            tick(&mut c, 1);
+        }
+        assert_eq!(host.rx.try_recv(), Ok(TwiReply::default()));
+        assert!(!bit(c.ram[TWCR], TWINT));
+    }
+}
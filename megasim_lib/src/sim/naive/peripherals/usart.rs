@@ -0,0 +1,482 @@
+use crate::sim::naive::chip::Chip;
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+//
+// USART (Page 143)
+//
+
+const UBRRL: usize = 0x29;
+const UCSRB: usize = 0x2A;
+const UCSRA: usize = 0x2B;
+const UDR: usize = 0x2C;
+// UBRRH and UCSRC share an I/O address, URSEL selects which one is written
+const UBRRH_UCSRC: usize = 0x40;
+
+// UCSRA: RXC | TXC | UDRE | FE | DOR | PE | U2X | MPCM
+const RXC: u8 = 7;
+const TXC: u8 = 6;
+const UDRE: u8 = 5;
+const FE: u8 = 4;
+const DOR: u8 = 3;
+const PE: u8 = 2;
+const U2X: u8 = 1;
+const MPCM: u8 = 0;
+
+// UCSRB: RXCIE | TXCIE | UDRIE | RXEN | TXEN | UCSZ2 | RXB8 | TXB8
+const RXEN: u8 = 4;
+const TXEN: u8 = 3;
+const UCSZ2: u8 = 2;
+const RXB8: u8 = 1;
+const TXB8: u8 = 0;
+
+// UCSRC: URSEL | UMSEL | UPM1 | UPM0 | USBS | UCSZ1 | UCSZ0 | UCPOL
+const URSEL: u8 = 7;
+const UMSEL: u8 = 6;
+const UPM1: u8 = 5;
+const USBS: u8 = 3;
+const UCSZ0: u8 = 1;
+
+// Initial UCSRC: asynchronous, no parity, 1 stop bit, 8 data bits
+const UCSRC_RESET: u8 = 0x86;
+
+// Two frames in UDR plus the one in the receive shift register
+const RX_BUFFER_FRAMES: usize = 3;
+
+// A frame sent to the USART by the host. The error flags make the frame
+// arrive with a bad stop bit or parity bit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SerialFrame {
+    pub data: u16,
+    pub frame_error: bool,
+    pub parity_error: bool,
+}
+
+impl SerialFrame {
+    pub fn new(data: u16) -> Self {
+        SerialFrame {
+            data,
+            ..Default::default()
+        }
+    }
+}
+
+// Host end of the USART. Frames sent on `tx` are received by the program once
+// its receiver is enabled, frames the program transmits arrive on `rx`.
+#[derive(Debug)]
+pub struct SerialPort {
+    pub tx: Sender<SerialFrame>,
+    pub rx: Receiver<u16>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Received {
+    data: u16,
+    frame_error: bool,
+    parity_error: bool,
+    // Frames were lost after this one
+    overrun: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Usart {
+    ubrrh: u8,
+    ucsrc: u8,
+    // Cycle of the last UBRRH read. A read in the next cycle returns UCSRC.
+    ubrrh_read: Option<u64>,
+
+    // Frame waiting in UDR, and the frame being shifted out with the cycles
+    // left until it is sent
+    tx_buffer: Option<u16>,
+    tx_shift: Option<(u16, u64)>,
+
+    // Received frames waiting to be read from UDR, and the frame being
+    // shifted in
+    rx_buffer: VecDeque<Received>,
+    rx_shift: Option<(SerialFrame, u64)>,
+
+    // Host connection, and frames from the host waiting to be received
+    host_rx: VecDeque<SerialFrame>,
+    from_host: Option<Receiver<SerialFrame>>,
+    to_host: Option<Sender<u16>>,
+}
+
+fn bit(val: u8, bit: u8) -> bool {
+    (val >> bit) & 1 != 0
+}
+
+// Data bits per frame, from UCSZ2:0.
+fn char_size(c: &Chip) -> u8 {
+    let ucsz = ((c.ram[UCSRB] >> UCSZ2) & 1) << 2 | ((c.usart.ucsrc >> UCSZ0) & 0b11);
+
+    match ucsz {
+        0..=3 => 5 + ucsz,
+        _ => 9,
+    }
+}
+
+fn parity_enabled(c: &Chip) -> bool {
+    bit(c.usart.ucsrc, UPM1)
+}
+
+// Clock cycles it takes to send or receive one frame at the current baud rate.
+fn frame_cycles(c: &Chip) -> u64 {
+    let ubrr = (((c.usart.ubrrh as u64) << 8) | c.ram[UBRRL] as u64) + 1;
+    let bit_cycles = if bit(c.usart.ucsrc, UMSEL) {
+        2 * ubrr
+    } else if bit(c.ram[UCSRA], U2X) {
+        8 * ubrr
+    } else {
+        16 * ubrr
+    };
+
+    let stop_bits = 1 + bit(c.usart.ucsrc, USBS) as u64;
+    let frame_bits = 1 + char_size(c) as u64 + parity_enabled(c) as u64 + stop_bits;
+    frame_bits * bit_cycles
+}
+
+// Updates the UCSRA status flags and RXB8 from the buffers.
+fn update_flags(c: &mut Chip) {
+    let head = c.usart.rx_buffer.front().copied();
+    let mut ucsra = c.ram[UCSRA] & (1 << TXC | 1 << U2X | 1 << MPCM);
+
+    if c.usart.tx_buffer.is_none() {
+        ucsra |= 1 << UDRE;
+    }
+    if let Some(frame) = head {
+        ucsra |= 1 << RXC;
+        ucsra |= (frame.frame_error as u8) << FE;
+        ucsra |= (frame.overrun as u8) << DOR;
+        ucsra |= (frame.parity_error as u8) << PE;
+    }
+    c.ram[UCSRA] = ucsra;
+
+    let rxb8 = head.is_some_and(|frame| frame.data & 0x100 != 0);
+    c.ram[UCSRB] = (c.ram[UCSRB] & !(1 << RXB8)) | ((rxb8 as u8) << RXB8);
+}
+
+// Resets the USART registers. The host connection is kept.
+pub(crate) fn reset(c: &mut Chip) {
+    c.usart = Usart {
+        ucsrc: UCSRC_RESET,
+        from_host: c.usart.from_host.take(),
+        to_host: c.usart.to_host.take(),
+        ..Default::default()
+    };
+    update_flags(c);
+}
+
+// Connects the host to the USART, replacing any earlier connection.
+pub(crate) fn connect(c: &mut Chip) -> SerialPort {
+    let (tx, from_host) = mpsc::channel();
+    let (to_host, rx) = mpsc::channel();
+
+    c.usart.from_host = Some(from_host);
+    c.usart.to_host = Some(to_host);
+    c.usart.host_rx.clear();
+
+    SerialPort { tx, rx }
+}
+
+fn tick_transmitter(c: &mut Chip, mut cycles: u64) {
+    loop {
+        if c.usart.tx_shift.is_none() {
+            if !bit(c.ram[UCSRB], TXEN) {
+                return;
+            }
+            let Some(data) = c.usart.tx_buffer.take() else {
+                return;
+            };
+            c.usart.tx_shift = Some((data, frame_cycles(c)));
+        }
+
+        let Some((data, left)) = c.usart.tx_shift else {
+            return;
+        };
+        if cycles < left {
+            c.usart.tx_shift = Some((data, left - cycles));
+            return;
+        }
+
+        cycles -= left;
+        c.usart.tx_shift = None;
+        if let Some(host) = &c.usart.to_host {
+            // The host may have hung up, the frame is lost then
+            let _ = host.send(data);
+        }
+
+        if c.usart.tx_buffer.is_none() {
+            c.ram[UCSRA] |= 1 << TXC;
+        }
+    }
+}
+
+fn receive(c: &mut Chip, frame: SerialFrame) {
+    let mask = (1u16 << char_size(c)) - 1;
+    let received = Received {
+        data: frame.data & mask,
+        frame_error: frame.frame_error,
+        parity_error: frame.parity_error && parity_enabled(c),
+        overrun: false,
+    };
+
+    if c.usart.rx_buffer.len() < RX_BUFFER_FRAMES {
+        c.usart.rx_buffer.push_back(received);
+    } else if let Some(last) = c.usart.rx_buffer.back_mut() {
+        last.overrun = true;
+    }
+}
+
+fn tick_receiver(c: &mut Chip, mut cycles: u64) {
+    if let Some(host) = &c.usart.from_host {
+        c.usart.host_rx.extend(host.try_iter());
+    }
+
+    // Frames from the host wait until the receiver is enabled
+    if !bit(c.ram[UCSRB], RXEN) {
+        return;
+    }
+
+    loop {
+        if c.usart.rx_shift.is_none() {
+            let Some(frame) = c.usart.host_rx.pop_front() else {
+                return;
+            };
+            c.usart.rx_shift = Some((frame, frame_cycles(c)));
+        }
+
+        let Some((frame, left)) = c.usart.rx_shift else {
+            return;
+        };
+        if cycles < left {
+            c.usart.rx_shift = Some((frame, left - cycles));
+            return;
+        }
+
+        cycles -= left;
+        c.usart.rx_shift = None;
+        receive(c, frame);
+    }
+}
+
+pub(crate) fn tick(c: &mut Chip, cycles: u64) {
+    tick_transmitter(c, cycles);
+    tick_receiver(c, cycles);
+    update_flags(c);
+}
+
+pub(crate) fn io_read(c: &mut Chip, addr: usize) -> Option<u8> {
+    match addr {
+        UDR => {
+            let frame = c.usart.rx_buffer.pop_front();
+            update_flags(c);
+            Some(frame.map_or(0, |frame| frame.data as u8))
+        }
+        // Reading the shared address returns UBRRH, unless it was also read
+        // in the previous cycle
+        UBRRH_UCSRC => {
+            if c.usart
+                .ubrrh_read
+                .is_some_and(|cycle| cycle + 1 == c.cycles)
+            {
+                c.usart.ubrrh_read = None;
+                Some(c.usart.ucsrc)
+            } else {
+                c.usart.ubrrh_read = Some(c.cycles);
+                Some(c.usart.ubrrh)
+            }
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn io_write(c: &mut Chip, addr: usize, val: u8) -> bool {
+    match addr {
+        // Writes while the transmit buffer is full are ignored
+        UDR => {
+            if c.usart.tx_buffer.is_none() {
+                let txb8 = ((c.ram[UCSRB] >> TXB8) & 1) as u16;
+                c.usart.tx_buffer = Some(val as u16 | (txb8 << 8));
+                tick_transmitter(c, 0);
+            }
+        }
+        UCSRA => {
+            // TXC is cleared by writing a one to it, only U2X and MPCM are
+            // writable
+            if bit(val, TXC) {
+                c.ram[UCSRA] &= !(1 << TXC);
+            }
+            let writable = 1 << U2X | 1 << MPCM;
+            c.ram[UCSRA] = (c.ram[UCSRA] & !writable) | (val & writable);
+        }
+        UCSRB => {
+            c.ram[UCSRB] = (c.ram[UCSRB] & (1 << RXB8)) | (val & !(1 << RXB8));
+
+            // Disabling the receiver flushes the receive buffer
+            if !bit(val, RXEN) {
+                c.usart.rx_buffer.clear();
+                c.usart.rx_shift = None;
+            }
+        }
+        UBRRH_UCSRC => {
+            if bit(val, URSEL) {
+                c.usart.ucsrc = val;
+            } else {
+                c.usart.ubrrh = val & 0x0F;
+            }
+        }
+        _ => return false,
+    }
+
+    update_flags(c);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // UBRR = 0, 8N1: 10 bits of 16 cycles
+    const FRAME: u64 = 160;
+
+    fn usart(ucsrb: u8) -> (Chip, SerialPort) {
+        let mut c = Chip::new();
+        let port = c.serial_port();
+        c.data_write(UCSRB, ucsrb);
+        (c, port)
+    }
+
+    #[test]
+    fn transmit_timing() {
+        let (mut c, port) = usart(1 << TXEN);
+        c.data_write(UBRRL, 51);
+        c.data_write(UDR, 0x41);
+        assert_eq!(c.ram[UCSRA], 1 << UDRE);
+
+        tick(&mut c, 10 * 16 * 52 - 1);
+        assert!(port.rx.try_recv().is_err());
+        tick(&mut c, 1);
+        assert_eq!(port.rx.try_recv(), Ok(0x41));
+        assert_eq!(c.ram[UCSRA], 1 << TXC | 1 << UDRE);
+
+        c.data_write(UCSRA, 1 << TXC);
+        assert_eq!(c.ram[UCSRA], 1 << UDRE);
+    }
+
+    #[test]
+    fn udr_write_to_a_full_buffer_is_ignored() {
+        let (mut c, port) = usart(1 << TXEN);
+        for byte in 1..=3 {
+            c.data_write(UDR, byte);
+        }
+        assert_eq!(c.ram[UCSRA], 0);
+
+        tick(&mut c, 2 * FRAME);
+        assert_eq!(port.rx.try_iter().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(c.ram[UCSRA], 1 << TXC | 1 << UDRE);
+    }
+
+    #[test]
+    fn nine_bit_frames() {
+        let (mut c, port) = usart(1 << RXEN | 1 << TXEN | 1 << UCSZ2 | 1 << TXB8);
+        c.data_write(UDR, 0xAA);
+        port.tx.send(SerialFrame::new(0x155)).unwrap();
+        tick(&mut c, 11 * 16);
+
+        assert_eq!(port.rx.try_recv(), Ok(0x1AA));
+        assert_ne!(c.ram[UCSRB] & 1 << RXB8, 0);
+        assert_eq!(c.data_read(UDR), 0x55);
+        assert_eq!(c.ram[UCSRB] & 1 << RXB8, 0);
+    }
+
+    #[test]
+    fn receive_errors() {
+        // 8E1: 12 bits per frame
+        let (mut c, port) = usart(1 << RXEN);
+        c.data_write(UBRRH_UCSRC, 0xA6);
+        port.tx
+            .send(SerialFrame {
+                data: 0x55,
+                frame_error: true,
+                parity_error: false,
+            })
+            .unwrap();
+        port.tx
+            .send(SerialFrame {
+                data: 0x33,
+                frame_error: false,
+                parity_error: true,
+            })
+            .unwrap();
+        tick(&mut c, 12 * 16);
+
+        assert_eq!(c.ram[UCSRA], 1 << RXC | 1 << UDRE | 1 << FE);
+        assert_eq!(c.data_read(UDR), 0x55);
+        assert_eq!(c.ram[UCSRA], 1 << UDRE);
+
+        tick(&mut c, 12 * 16);
+        assert_eq!(c.ram[UCSRA], 1 << RXC | 1 << UDRE | 1 << PE);
+
+        // Parity errors only count with parity checking enabled
+        c.data_write(UBRRH_UCSRC, UCSRC_RESET);
+        port.tx
+            .send(SerialFrame {
+                data: 0x33,
+                frame_error: false,
+                parity_error: true,
+            })
+            .unwrap();
+        c.data_read(UDR);
+        tick(&mut c, FRAME);
+        assert_eq!(c.ram[UCSRA], 1 << RXC | 1 << UDRE);
+    }
+
+    #[test]
+    fn overrun_flags_the_last_frame_kept() {
+        let (mut c, port) = usart(1 << RXEN);
+        for byte in 1..=5 {
+            port.tx.send(SerialFrame::new(byte)).unwrap();
+        }
+        tick(&mut c, 5 * FRAME);
+
+        let mut frames = vec![];
+        while c.ram[UCSRA] & 1 << RXC != 0 {
+            let dor = c.ram[UCSRA] & 1 << DOR != 0;
+            frames.push((c.data_read(UDR), dor));
+        }
+        assert_eq!(frames, [(1, false), (2, false), (3, true)]);
+    }
+
+    #[test]
+    fn receiver_enable() {
+        // Frames wait for the receiver
+        let (mut c, port) = usart(0);
+        port.tx.send(SerialFrame::new(1)).unwrap();
+        tick(&mut c, FRAME);
+        assert_eq!(c.ram[UCSRA] & 1 << RXC, 0);
+
+        c.data_write(UCSRB, 1 << RXEN);
+        tick(&mut c, FRAME);
+        assert_ne!(c.ram[UCSRA] & 1 << RXC, 0);
+
+        // Disabling it flushes the buffer
+        c.data_write(UCSRB, 0);
+        assert_eq!(c.ram[UCSRA] & 1 << RXC, 0);
+    }
+
+    #[test]
+    fn ubrrh_and_ucsrc_share_an_address() {
+        let mut c = Chip::new();
+        c.data_write(UBRRH_UCSRC, 0x13);
+        assert_eq!(c.usart.ubrrh, 0x03);
+        assert_eq!(c.usart.ucsrc, UCSRC_RESET);
+
+        // A second read in the next cycle returns UCSRC
+        c.cycles = 10;
+        assert_eq!(c.data_read(UBRRH_UCSRC), 0x03);
+        c.cycles = 11;
+        assert_eq!(c.data_read(UBRRH_UCSRC), UCSRC_RESET);
+        c.cycles = 12;
+        assert_eq!(c.data_read(UBRRH_UCSRC), 0x03);
+    }
+}
@@ -4,8 +4,8 @@ use js_sys::{Object, Reflect, Uint8Array};
 use std::collections::HashMap;
 
 use megasim_lib::{
-    compiler::{Op, compile},
-    sim::naive::chip::Chip,
+    compiler::{Op, compile, disassemble_cseg},
+    sim::naive::chip::{Chip, State},
 };
 
 #[wasm_bindgen(start)]
@@ -32,7 +32,7 @@ pub fn init() {
 fn program_to_string(
     cseg: &HashMap<u64, Op>,
     dseg: &HashMap<u64, u64>,
-) -> String {
+) -> Result<String, String> {
     let mut out = String::new();
 
     out.push_str("--- DSEG ---\n");
@@ -43,21 +43,11 @@ fn program_to_string(
     }
 
     out.push_str("\n--- CSEG ---\n");
-    let mut caddrs: Vec<_> = cseg.keys().cloned().collect();
-    caddrs.sort();
-    for a in caddrs {
-        let op = &cseg[&a];
-        let s = match op {
-            Op::Nullary(m) => m.to_uppercase(),
-            Op::Unary(m, a1) => format!("{} {}", m.to_uppercase(), a1),
-            Op::Binary(m, a1, a2) => format!("{} {} {}", m.to_uppercase(), a1, a2),
-            Op::Ternary(m, a1, a2, a3) =>
-                format!("{} {} {} {}", m.to_uppercase(), a1, a2, a3),
-        };
+    for (a, s) in disassemble_cseg(cseg, &HashMap::new())? {
         out.push_str(&format!("{}: {}\n", a, s));
     }
 
-    out
+    Ok(out)
 }
 
 #[wasm_bindgen]
@@ -69,15 +59,15 @@ pub struct Simulator {
 #[wasm_bindgen]
 impl Simulator {
     #[wasm_bindgen(constructor)]
-    pub fn new(source: &str) -> Simulator {
-        let (cseg, dseg) = compile(source);
-        let program_str = program_to_string(&cseg, &dseg);
+    pub fn new(source: &str) -> Result<Simulator, JsValue> {
+        let (cseg, dseg, _eseg) = compile(source).map_err(|e| JsValue::from_str(&e))?;
+        let program_str = program_to_string(&cseg, &dseg).map_err(|e| JsValue::from_str(&e))?;
 
         let mut chip = Chip::new();
-        chip.apply_cseg(&cseg).unwrap();
-        chip.apply_dseg(&dseg).unwrap();
+        chip.apply_cseg(&cseg).map_err(|e| JsValue::from_str(&e))?;
+        chip.apply_dseg(&dseg).map_err(JsValue::from_str)?;
 
-        Simulator { chip, program_str }
+        Ok(Simulator { chip, program_str })
     }
 
     pub fn program_str(&self) -> String {
@@ -88,8 +78,17 @@ impl Simulator {
         self.chip.ram_set_byte(idx, x);
     }
 
+    pub fn resume(&mut self) {
+        self.chip.resume();
+    }
+
     pub fn step(&mut self) -> bool {
-        self.chip.step(None)
+        self.chip.step(None).is_some()
+    }
+
+    // Runs for at least `cycles` clock cycles, returns the cycles run.
+    pub fn run(&mut self, cycles: f64) -> f64 {
+        self.chip.run(cycles as u64) as f64
     }
 
     pub fn state(&self) -> JsValue {
@@ -97,6 +96,14 @@ impl Simulator {
 
         Reflect::set(&obj, &"pc".into(), &(self.chip.pc as f64).into()).unwrap();
         Reflect::set(&obj, &"clock_freq".into(), &(self.chip.clock_freq as f64).into()).unwrap();
+        Reflect::set(&obj, &"cycles".into(), &(self.chip.cycles as f64).into()).unwrap();
+        Reflect::set(&obj, &"elapsed_ns".into(), &(self.chip.elapsed_ns() as f64).into()).unwrap();
+        let state = match self.chip.state {
+            State::Running => "running",
+            State::Sleeping(_) => "sleeping",
+            State::Break => "break",
+        };
+        Reflect::set(&obj, &"state".into(), &state.into()).unwrap();
         let ram = Uint8Array::from(self.chip.ram.as_ref());
         Reflect::set(&obj, &"ram".into(), &ram.into()).unwrap();
 